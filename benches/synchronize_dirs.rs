@@ -0,0 +1,41 @@
+//! Time the synchronization of 2 directories which are already synchronized.
+//!
+//! Nothing is copied, so this mostly measures how many times each file is stat'd.
+//! Run with `cargo bench --bench synchronize_dirs [FILE_COUNT]`.
+
+use keep_keeping_lib::{synchronize, ErrorHandlingType};
+use std::time::Instant;
+
+const ITERATIONS: u32 = 10;
+
+fn main() {
+    let file_count: usize = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(10_000);
+
+    let dir1 = tempfile::tempdir().unwrap();
+    let dir2 = tempfile::tempdir().unwrap();
+    let time = filetime::FileTime::from_unix_time(1_000_000_000, 0);
+
+    for i in 0..file_count {
+        for dir in &[&dir1, &dir2] {
+            let path = dir.path().join(format!("file{}", i));
+            std::fs::write(&path, b"content").unwrap();
+            filetime::set_file_mtime(&path, time).unwrap();
+        }
+    }
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        synchronize(dir1.path(), dir2.path(), |_| ErrorHandlingType::Fail).unwrap();
+    }
+    let elapsed = start.elapsed() / ITERATIONS;
+
+    println!(
+        "{} synchronized files: {:?} per run, {:?} per file",
+        file_count,
+        elapsed,
+        elapsed / file_count as u32
+    );
+}
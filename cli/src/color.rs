@@ -0,0 +1,69 @@
+use std::io::IsTerminal;
+
+/// When to color the output.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Only when writing to a terminal and `NO_COLOR` is not set.
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn from_arg(arg: &str) -> Option<Self> {
+        match arg {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+}
+
+/// Highlight messages using ANSI escape codes, stderr and stdout being colored independently.
+#[derive(Clone, Copy)]
+pub struct Colors {
+    stderr: bool,
+    stdout: bool,
+}
+
+impl Colors {
+    pub fn new(choice: ColorChoice) -> Self {
+        // See https://no-color.org
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        let enabled = |is_terminal: bool| match choice {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => !no_color && is_terminal,
+        };
+
+        Self {
+            stderr: enabled(std::io::stderr().is_terminal()),
+            stdout: enabled(std::io::stdout().is_terminal()),
+        }
+    }
+
+    #[inline]
+    fn paint(enabled: bool, code: u8, text: &str) -> String {
+        if enabled {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text.to_owned()
+        }
+    }
+
+    /// Red, for errors printed to stderr.
+    pub fn error(self, text: &str) -> String {
+        Self::paint(self.stderr, 31, text)
+    }
+
+    /// Green, for what is copied or created, printed to stdout.
+    pub fn copied(self, text: &str) -> String {
+        Self::paint(self.stdout, 32, text)
+    }
+
+    /// Yellow, for what is left out, printed to stdout.
+    pub fn skipped(self, text: &str) -> String {
+        Self::paint(self.stdout, 33, text)
+    }
+}
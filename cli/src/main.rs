@@ -1,63 +1,821 @@
 #[macro_use]
 extern crate clap;
 
+mod color;
+mod watch;
+
+use color::{ColorChoice, Colors};
 use keep_keeping_lib as keep_keeping;
 
-use std::cmp::Ordering;
+use serde::Serialize;
+use std::cell::{Cell, RefCell};
 use std::path::Path;
 use std::process::exit;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Exit codes of the CLI, scripts can rely on them.
+mod exit_code {
+    /// Synchronization completed without any error.
+    pub const SUCCESS: i32 = 0;
+    /// Synchronization failed.
+    pub const ERROR: i32 = 1;
+    /// Invalid arguments.
+    pub const USAGE: i32 = 2;
+    /// Synchronization completed but some entries were skipped because of errors.
+    pub const SKIPPED_ERRORS: i32 = 3;
+    /// One of the paths to synchronize does not exist.
+    pub const NOT_FOUND: i32 = 4;
+    /// Cancelled by the user (e.g. with Ctrl+C).
+    pub const CANCELLED: i32 = 130;
+}
 
 fn main() {
+    // the library logs its decisions and actions, shown with `RUST_LOG` (e.g. `RUST_LOG=debug`)
+    env_logger::init();
+
     let matches: clap::ArgMatches = clap_app!(("Keep Keeping CLI") =>
         (version: crate_version!())
         (author: crate_authors!())
         (about: "Synchronizes paths together")
-        (@arg PATHS: +required ... "Paths to synchronize")
+        (@arg PATHS: required_unless[PAIRS] ... "Paths to synchronize")
+        (@arg PAIRS: --pairs +takes_value value_name("FILE") conflicts_with[PATHS WATCH]
+            "Synchronizes each pair of paths listed in FILE ('-' for stdin), one pair per line \
+            separated by a tab ('#' starting a comment), then prints a summary of all of them. \
+            The exit code is the one of the first pair which failed")
+        (@arg COLOR: --color +takes_value possible_value[auto always never] default_value("auto")
+            "Colors the output: 'auto' only colors it when printing to a terminal and \
+            the NO_COLOR environment variable is not set")
+        (@arg NO_COLOR: --("no-color") conflicts_with[COLOR] "Same as '--color never'")
+        (@arg STATS: --stats
+            "Prints statistics once synchronized, along with an estimate of how long copying \
+            would take with '--dry-run'")
+        (@arg STATS_INTERVAL: --("stats-interval") +takes_value value_name("DURATION")
+            "Prints how many files and bytes have been copied so far every DURATION while \
+            synchronizing (e.g. '10s', '5m' or '1h', in seconds without a unit), files copied \
+            at once with '--jobs' being counted once they are all copied")
+        (@arg THROUGHPUT: --throughput +takes_value value_name("MB/S") default_value("50")
+            "Copy speed the estimate of '--dry-run --stats' assumes, in megabytes per second")
+        (@arg VERBOSE: -v --verbose +multiple
+            "Prints each modification as it's made, '-vv' also prints metadata updates and \
+            every entry checked along with why it's synchronized the way it is")
+        (@arg QUIET: -q --quiet conflicts_with[VERBOSE]
+            "Only prints the errors stopping the synchronization")
+        (@arg FORMAT: --format +takes_value possible_value[text json] default_value("text")
+            "Format of the output: 'json' only prints a JSON object with statistics and \
+            errors once synchronized")
+        (@arg ERROR_MODE: --("error-mode") +takes_value possible_value[fail skip ignore]
+            default_value("fail")
+            "What to do on errors: 'fail' stops synchronizing, 'skip' skips the entry and \
+            'ignore' carries on as if nothing happened (errors are printed either way)")
+        (@arg WATCH: -w --watch
+            "Keeps running after synchronizing, synchronizing again whenever something changes")
+        (@arg DRY_RUN: -n --("dry-run")
+            "Prints what would be done without modifying anything")
+        (@arg PROPAGATE_DELETIONS: --("propagate-deletions")
+            "Deletes entries deleted from the other side since the last synchronization \
+            instead of copying them back")
+        (@arg INITIALIZE: --initialize requires[PROPAGATE_DELETIONS]
+            "Synchronizes as if for the first time, copying back entries missing from one side \
+            and recording what's left for the next '--propagate-deletions'")
+        (@arg RSYNC_TRAILING_SLASH: --("rsync-trailing-slash")
+            "Like rsync, synchronizes 'src' with 'dst/src' and the contents of 'src/' with 'dst'")
+        (@arg EXCLUDE: --exclude +takes_value +multiple number_of_values(1) value_name("GLOB")
+            "Never synchronizes the entries matching this pattern, relative to the synchronized \
+            paths (e.g. '**/*.tmp' in every directory), can be repeated")
+        (@arg CREATE_MISSING_TARGET: --("create-missing-target")
+            "Creates a path which doesn't exist as a copy of the other one when it's a directory")
+        (@arg MERGE_ROOT_BUNDLE: --("merge-root-bundle")
+            "Merges the contents of the given paths even if they are macOS apps")
+        (@arg JOBS: -j --jobs +takes_value value_name("N")
+            "Copies up to N files at once, '--jobs 1' copying them one by one in order \
+            (defaults to the number of CPUs)")
+        (@arg METADATA_ONLY: --("metadata-only")
+            "Only updates the timestamps and permissions of files present on both sides, \
+            never copying any data")
+        (after_help: "EXIT CODES:\n    \
+            0    Synchronized successfully\n    \
+            1    Synchronization failed\n    \
+            2    Invalid arguments\n    \
+            3    Synchronized but some entries were skipped because of errors\n    \
+            4    A path does not exist\n    \
+            130  Cancelled")
     )
-    .get_matches();
+    .get_matches_safe()
+    .unwrap_or_else(|err| match err.kind {
+        clap::ErrorKind::HelpDisplayed | clap::ErrorKind::VersionDisplayed => err.exit(),
+        _ => {
+            eprintln!("{}", err.message);
+            exit(exit_code::USAGE);
+        }
+    });
 
-    let paths: Vec<_> = matches.values_of("PATHS").unwrap_or_default().collect();
+    let color_choice = if matches.is_present("NO_COLOR") {
+        ColorChoice::Never
+    } else {
+        matches
+            .value_of("COLOR")
+            .and_then(ColorChoice::from_arg)
+            .unwrap_or(ColorChoice::Auto)
+    };
+    let colors = Colors::new(color_choice);
 
-    match paths.len().cmp(&2) {
-        Ordering::Less => {
-            eprintln!("You must precise at least 2 paths to synchronize.");
-            exit(1);
-        }
-        Ordering::Greater => {
-            eprintln!("Synchronizing more than 2 paths is not supported yet.");
-            exit(1);
-        }
-        Ordering::Equal => synchronize_or_exit(paths[0], paths[1]),
+    let pairs = matches.value_of("PAIRS").map(|file| {
+        read_pairs(file).unwrap_or_else(|(code, message)| {
+            eprintln!("{}", colors.error(&message));
+            exit(code);
+        })
+    });
+    let path_sets = match &pairs {
+        Some(pairs) => parse_pairs(pairs).unwrap_or_else(|line| {
+            eprintln!(
+                "{}",
+                colors.error(&format!(
+                    "Line {} should hold 2 paths separated by a tab.",
+                    line
+                ))
+            );
+            exit(exit_code::USAGE);
+        }),
+        None => vec![matches.values_of("PATHS").unwrap_or_default().collect()],
+    };
+
+    if path_sets.iter().any(|paths| paths.len() < 2) {
+        eprintln!(
+            "{}",
+            colors.error("You must precise at least 2 paths to synchronize.")
+        );
+        exit(exit_code::USAGE);
     }
+
+    synchronize_or_exit(&path_sets, colors, &matches);
+}
+
+/// Read the list of pairs given to `--pairs`, returns the exit code and message on errors.
+fn read_pairs(file: &str) -> Result<String, (i32, String)> {
+    use std::io::Read;
+
+    let mut pairs = String::new();
+    let read = if file == "-" {
+        std::io::stdin().read_to_string(&mut pairs).map(|_| ())
+    } else {
+        std::fs::read_to_string(file).map(|content| pairs = content)
+    };
+
+    read.map(|_| pairs).map_err(|err| {
+        let code = if err.kind() == std::io::ErrorKind::NotFound {
+            exit_code::NOT_FOUND
+        } else {
+            exit_code::ERROR
+        };
+
+        (code, format!("Could not read '{}': {}", file, err))
+    })
+}
+
+/// Parse one pair of tab-separated paths per line, skipping blank lines and comments.
+///
+/// Returns the number of the first invalid line on errors.
+fn parse_pairs(pairs: &str) -> Result<Vec<Vec<&str>>, usize> {
+    pairs
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(index, line)| {
+            let paths: Vec<_> = line.split('\t').collect();
+
+            match paths[..] {
+                [path1, path2] if !path1.is_empty() && !path2.is_empty() => Ok(paths),
+                _ => Err(index + 1),
+            }
+        })
+        .collect()
 }
 
 #[inline]
-fn synchronize_or_exit(path1_str: &str, path2_str: &str) {
-    let path1 = Path::new(path1_str);
-    let path2 = Path::new(path2_str);
+fn synchronize_or_exit(path_sets: &[Vec<&str>], colors: Colors, matches: &clap::ArgMatches) {
+    let exclude = matches
+        .values_of("EXCLUDE")
+        .unwrap_or_default()
+        .map(|pattern| {
+            keep_keeping::Pattern::new(pattern).unwrap_or_else(|err| {
+                eprintln!(
+                    "{}",
+                    colors.error(&format!("Invalid pattern '{}': {}", pattern, err))
+                );
+                exit(exit_code::USAGE);
+            })
+        })
+        .collect();
+
+    let jobs = matches
+        .value_of("JOBS")
+        .map(|jobs| match jobs.parse::<usize>() {
+            Ok(jobs) if jobs >= 1 => jobs,
+            _ => {
+                eprintln!(
+                    "{}",
+                    colors.error(&format!(
+                        "Invalid number of jobs '{}': must be at least 1",
+                        jobs
+                    ))
+                );
+                exit(exit_code::USAGE);
+            }
+        });
+
+    // always given, it has a default value
+    let throughput = matches.value_of("THROUGHPUT").unwrap_or_default();
+    let throughput = match throughput.parse::<f64>() {
+        Ok(throughput) if throughput.is_finite() && throughput > 0.0 => throughput,
+        _ => {
+            eprintln!(
+                "{}",
+                colors.error(&format!(
+                    "Invalid throughput '{}': must be a positive number of MB/s",
+                    throughput
+                ))
+            );
+            exit(exit_code::USAGE);
+        }
+    };
+
+    let stats_interval = matches.value_of("STATS_INTERVAL").map(|interval| {
+        parse_duration(interval).unwrap_or_else(|| {
+            eprintln!(
+                "{}",
+                colors.error(&format!(
+                    "Invalid interval '{}': must be a positive number of seconds, \
+                    minutes ('m') or hours ('h')",
+                    interval
+                ))
+            );
+            exit(exit_code::USAGE);
+        })
+    });
+
+    let path_sets: Vec<Vec<_>> = path_sets
+        .iter()
+        .map(|path_strs| path_strs.iter().map(Path::new).collect())
+        .collect();
+    let create_missing_target = matches.is_present("CREATE_MISSING_TARGET");
+    // a missing path is created from the other one when it's a directory
+    let missing_paths: Vec<_> = path_sets
+        .iter()
+        .filter(|paths| !create_missing_target || !paths.iter().any(|path| path.is_dir()))
+        .flatten()
+        .filter(|path| !path.exists())
+        .collect();
+
+    let error_handling = match matches.value_of("ERROR_MODE") {
+        Some("skip") => keep_keeping::ErrorHandlingType::Skip,
+        Some("ignore") => keep_keeping::ErrorHandlingType::Ignore,
+        _ => keep_keeping::ErrorHandlingType::Fail,
+    };
+
+    let json = matches.value_of("FORMAT") == Some("json");
+    // nothing but JSON is printed to stdout with `--format json`
+    let verbosity = if json {
+        0
+    } else {
+        matches.occurrences_of("VERBOSE")
+    };
+    let quiet = matches.is_present("QUIET");
+    let stats_interval = stats_interval.filter(|_| !json && !quiet);
+
+    let errors = Rc::new(RefCell::new(Vec::new()));
+    let on_err = {
+        let errors = errors.clone();
+
+        move |context: &keep_keeping::ErrorContext| {
+            let err = context.error;
+            if !json && (!quiet || error_handling == keep_keeping::ErrorHandlingType::Fail) {
+                eprintln!("{}", colors.error(&format!("Error: {}", err)));
+            }
+
+            errors.borrow_mut().push(JsonError::new(err.path(), err));
+
+            error_handling
+        }
+    };
+
+    if !missing_paths.is_empty() {
+        if json {
+            let errors: Vec<_> = missing_paths
+                .iter()
+                .map(|path| JsonError::new(Some(path), "Path does not exist"))
+                .collect();
+            print_json(None, &errors);
+        } else {
+            for path in missing_paths {
+                eprintln!(
+                    "{}",
+                    colors.error(&format!("Path does not exist: '{}'", path.display()))
+                );
+            }
+        }
+
+        exit(exit_code::NOT_FOUND);
+    }
+
+    let dry_run = matches.is_present("DRY_RUN");
 
-    let path1_exists = path1.exists();
-    let path2_exists = path2.exists();
+    // Ctrl+C stops the synchronization in progress, or the CLI right away if there's none
+    // (e.g. waiting for changes). Without a handler, it still stops the CLI less gracefully.
+    let cancel = Arc::new(AtomicBool::new(false));
+    let synchronizing = Arc::new(AtomicBool::new(false));
+    let _ = ctrlc::set_handler({
+        let (cancel, synchronizing) = (cancel.clone(), synchronizing.clone());
 
-    let on_err = |err: &dyn std::error::Error| {
-        eprintln!("Error: {}", err);
+        move || {
+            if synchronizing.load(Ordering::SeqCst) {
+                cancel.store(true, Ordering::SeqCst);
+            } else {
+                exit(exit_code::CANCELLED);
+            }
+        }
+    });
+
+    let last_stats = Cell::new(Instant::now());
+    let on_progress = move |progress: &keep_keeping::Progress| {
+        // not for each chunk of a copy
+        if verbosity >= 2 && progress.bytes.is_none() {
+            println!("CHECK {}", progress.path.display());
+        }
 
-        keep_keeping::ErrorHandlingType::Fail
+        if let Some(interval) = stats_interval {
+            if last_stats.get().elapsed() >= interval {
+                last_stats.set(Instant::now());
+
+                let bytes_copied =
+                    progress.stats.bytes_copied + progress.bytes.map_or(0, |(copied, _)| copied);
+                println!(
+                    "{} files, {} copied",
+                    format_count(progress.stats.files_copied),
+                    format_bytes(bytes_copied)
+                );
+            }
+        }
+    };
+    let on_decision = move |decision: &keep_keeping::Decision| {
+        println!("{}", format_decision(decision, colors));
     };
+    let on_action = move |action: &keep_keeping::PlannedAction| {
+        use keep_keeping::PlannedAction::*;
+
+        if verbosity >= 2 || !matches!(action, SetTimes { .. } | SetPermissions(_)) {
+            println!("{}", format_action(action, colors));
+        }
+    };
+
+    let options = keep_keeping::SyncOptions {
+        error_handler: Box::new(on_err),
+        on_progress: (verbosity >= 2 || stats_interval.is_some())
+            .then(|| Box::new(on_progress) as _),
+        on_decision: (verbosity >= 2).then(|| Box::new(on_decision) as _),
+        // dry runs print every planned action once done
+        on_action: (verbosity >= 1 && !dry_run).then(|| Box::new(on_action) as _),
+        propagate_deletions: matches.is_present("PROPAGATE_DELETIONS"),
+        initialize: matches.is_present("INITIALIZE"),
+        rsync_trailing_slash: matches.is_present("RSYNC_TRAILING_SLASH"),
+        merge_root_bundle: matches.is_present("MERGE_ROOT_BUNDLE"),
+        metadata_only: matches.is_present("METADATA_ONLY"),
+        create_missing_target,
+        exclude,
+        dry_run,
+        cancel: Some(cancel),
+        parallel: jobs != Some(1),
+        jobs,
+        ..Default::default()
+    };
+
+    // returns the exit code, along with the report if synchronized
+    let synchronize = |paths: &[&Path]| {
+        errors.borrow_mut().clear();
+
+        synchronizing.store(true, Ordering::SeqCst);
+        let result = match *paths {
+            [path1, path2] => keep_keeping::synchronize_with(path1, path2, &options),
+            _ => keep_keeping::synchronize_many_with(paths, &options),
+        };
+        synchronizing.store(false, Ordering::SeqCst);
+
+        match &result {
+            Ok(report) if json => print_json(Some(&report.stats), &errors.borrow()),
+            Err(_) if json => print_json(None, &errors.borrow()),
+            Ok(report) => {
+                let size_mismatches = if quiet {
+                    &[][..]
+                } else {
+                    &report.size_mismatches[..]
+                };
+
+                for (path1, path2) in size_mismatches {
+                    eprintln!(
+                        "{}",
+                        colors.error(&format!(
+                            "Sizes differ, metadata not updated: '{}' and '{}'",
+                            path1.display(),
+                            path2.display()
+                        ))
+                    );
+                }
+
+                for action in &report.planned {
+                    println!("{}", format_action(action, colors));
+                }
+
+                if matches.is_present("STATS") {
+                    print_stats(report);
+
+                    if dry_run {
+                        print_estimate(&report.planned, throughput);
+                    }
+                }
+            }
+            Err(_) => (),
+        }
+
+        let code = match &result {
+            Err(keep_keeping::SyncError::OverlappingPaths(_, _)) => exit_code::USAGE,
+            // e.g. removed while watching
+            Err(keep_keeping::SyncError::NotFound(_)) => exit_code::NOT_FOUND,
+            Err(keep_keeping::SyncError::Aborted) => exit_code::CANCELLED,
+            Err(_) => exit_code::ERROR,
+            // ignored errors are not fatal
+            Ok(_)
+                if !errors.borrow().is_empty()
+                    && error_handling != keep_keeping::ErrorHandlingType::Ignore =>
+            {
+                exit_code::SKIPPED_ERRORS
+            }
+            Ok(_) => exit_code::SUCCESS,
+        };
+
+        (code, result.ok())
+    };
+
+    if matches.is_present("PAIRS") {
+        let mut code = exit_code::SUCCESS;
+        let mut total = keep_keeping::SyncReport::default();
+        let mut error_count = 0;
+
+        for paths in &path_sets {
+            let (pair_code, report) = synchronize(paths);
+            error_count += errors.borrow().len();
+
+            if let Some(report) = report {
+                total.merge(report);
+            }
+
+            match pair_code {
+                exit_code::CANCELLED => exit(exit_code::CANCELLED),
+                exit_code::SUCCESS => (),
+                // an error stopping a pair outweighs skipped entries
+                _ if code == exit_code::SUCCESS || code == exit_code::SKIPPED_ERRORS => {
+                    code = pair_code
+                }
+                _ => (),
+            }
+        }
+
+        if !json && !quiet {
+            println!(
+                "Synchronized {} pairs: copied {} files ({} bytes), {} errors",
+                format_count(path_sets.len() as u64),
+                format_count(total.stats.files_copied),
+                format_count(total.stats.bytes_copied),
+                format_count(error_count as u64)
+            );
+        }
+
+        exit(code);
+    }
+
+    let paths = &path_sets[0];
+    let (code, _) = synchronize(paths);
+
+    if matches.is_present("WATCH") && code != exit_code::CANCELLED {
+        // errors are already printed, a failed synchronization is retried on the next change
+        let watched = watch::watch(paths, || {
+            if synchronize(paths).0 == exit_code::CANCELLED {
+                exit(exit_code::CANCELLED);
+            }
+        });
 
-    if path1_exists && path2_exists {
-        if keep_keeping::synchronize(path1, path2, on_err).is_err() {
-            exit(1);
+        if let Err(err) = watched {
+            eprintln!("{}", colors.error(&format!("Error: {}", err)));
+            exit(exit_code::ERROR);
         }
+    }
+
+    exit(code);
+}
+
+fn print_stats(report: &keep_keeping::SyncReport) {
+    let stats = &report.stats;
+
+    println!(
+        "Copied {} files ({} bytes), created {} directories",
+        format_count(stats.files_copied),
+        format_count(stats.bytes_copied),
+        format_count(stats.dirs_created)
+    );
+    println!(
+        "Deleted {} files and {} directories",
+        format_count(stats.files_deleted),
+        format_count(stats.dirs_deleted)
+    );
+
+    if let Some(breakdown) = format_filter_breakdown(&report.filtered) {
+        println!("{}", breakdown);
+    }
+
+    if !report.metadata_updated.is_empty() {
+        println!(
+            "Updated the metadata of {} files",
+            format_count(report.metadata_updated.len() as u64)
+        );
+    }
+}
+
+/// Print how long the copies planned by a dry run would take at `throughput` MB/s.
+fn print_estimate(planned: &[keep_keeping::PlannedAction], throughput: f64) {
+    let bytes: u64 = planned
+        .iter()
+        .filter_map(|action| match action {
+            keep_keeping::PlannedAction::Copy { from, .. } => std::fs::metadata(from).ok(),
+            _ => None,
+        })
+        .map(|metadata| metadata.len())
+        .sum();
+    let seconds = bytes as f64 / (throughput * 1_000_000.0);
+
+    println!(
+        "Copying {} bytes would take ~{} at {} MB/s",
+        format_count(bytes),
+        format_duration(Duration::from_secs_f64(seconds.ceil())),
+        throughput
+    );
+}
+
+/// Parse a positive duration in seconds, or in minutes or hours with an 'm' or 'h' suffix
+/// (e.g. "10s", "5m").
+fn parse_duration(duration: &str) -> Option<Duration> {
+    let (number, unit) = match duration.char_indices().last()? {
+        (index, 's') => (&duration[..index], 1),
+        (index, 'm') => (&duration[..index], 60),
+        (index, 'h') => (&duration[..index], 60 * 60),
+        _ => (duration, 1),
+    };
+
+    match number.parse::<u64>() {
+        Ok(number) if number > 0 => Some(Duration::from_secs(number.checked_mul(unit)?)),
+        _ => None,
+    }
+}
+
+/// Format a duration rounded to the second (e.g. "3m20s").
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+
+    if hours > 0 {
+        format!("{}h{:02}m{:02}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m{:02}s", minutes, seconds)
     } else {
-        if !path1_exists {
-            eprintln!("Path does not exist: '{}'", path1_str);
+        format!("{}s", seconds)
+    }
+}
+
+/// Format how many entries each filter excluded (e.g. "Exclude and ignore patterns excluded
+/// 1,203 entries; size filter excluded 45 files"), `None` if nothing was excluded.
+fn format_filter_breakdown(filtered: &keep_keeping::FilterStats) -> Option<String> {
+    let breakdown = [
+        (
+            "exclude and ignore patterns",
+            filtered.excluded_patterns,
+            "entries",
+        ),
+        ("include patterns", filtered.not_included, "files"),
+        ("excluded paths", filtered.excluded_paths, "entries"),
+        ("hidden entries filter", filtered.hidden, "entries"),
+        ("size filter", filtered.too_large, "files"),
+        ("content filter", filtered.content_filter, "files"),
+        ("regular files filter", filtered.symlinks, "symbolic links"),
+        ("regular files filter", filtered.fifos, "FIFOs"),
+        ("regular files filter", filtered.sockets, "sockets"),
+        ("regular files filter", filtered.devices, "devices"),
+        (
+            "regular files filter",
+            filtered.other_special_files,
+            "special files",
+        ),
+    ]
+    .iter()
+    .filter(|&&(_, count, _)| count > 0)
+    .map(|&(filter, count, entries)| {
+        format!("{} excluded {} {}", filter, format_count(count), entries)
+    })
+    .collect::<Vec<_>>()
+    .join("; ");
+
+    let mut chars = breakdown.chars();
+    let first = chars.next()?;
+    Some(first.to_uppercase().chain(chars).collect())
+}
+
+/// Error printed by `--format json`.
+#[derive(Serialize)]
+struct JsonError {
+    path: Option<String>,
+    message: String,
+}
+
+impl JsonError {
+    fn new(path: Option<&Path>, message: impl ToString) -> Self {
+        JsonError {
+            // JSON strings must be valid UTF-8
+            path: path.map(|path| path.to_string_lossy().into_owned()),
+            message: message.to_string(),
         }
+    }
+}
+
+/// Print the result of a synchronization as a JSON object, `stats` is `None` if it failed.
+fn print_json(stats: Option<&keep_keeping::SyncStats>, errors: &[JsonError]) {
+    #[derive(Serialize)]
+    struct Output<'a> {
+        stats: Option<&'a keep_keeping::SyncStats>,
+        errors: &'a [JsonError],
+    }
+
+    println!(
+        "{}",
+        serde_json::to_string(&Output { stats, errors }).expect("always serializable")
+    );
+}
 
-        if !path2_exists {
-            eprintln!("Path does not exist: '{}'", path2_str);
+/// Format an action planned by a dry run (e.g. "COPY a -> b"), copies and creations being
+/// colored.
+fn format_action(action: &keep_keeping::PlannedAction, colors: Colors) -> String {
+    use keep_keeping::PlannedAction::*;
+
+    match action {
+        Copy { from, to } => colors.copied(&format!("COPY {} -> {}", from.display(), to.display())),
+        Delete(path) => format!("DELETE {}", path.display()),
+        CreateDir(path) => colors.copied(&format!("MKDIR {}", path.display())),
+        CreateSymlink { link, target } => colors.copied(&format!(
+            "SYMLINK {} -> {}",
+            link.display(),
+            target.display()
+        )),
+        CreateHardLink { link, original } => colors.copied(&format!(
+            "LINK {} -> {}",
+            link.display(),
+            original.display()
+        )),
+        Rename { from, to } => format!("RENAME {} -> {}", from.display(), to.display()),
+        SetTimes { path, .. } => format!("SET TIMES {}", path.display()),
+        SetPermissions(path) => format!("SET PERMISSIONS {}", path.display()),
+    }
+}
+
+/// Format why an entry is synchronized the way it is (e.g. "WHY a: newer, copied"), copied and
+/// skipped entries being colored.
+fn format_decision(decision: &keep_keeping::Decision, colors: Colors) -> String {
+    use keep_keeping::Decision::*;
+
+    let reason = match decision {
+        CopiedNewer(_) => "newer, copied",
+        AlreadyInSync(_) => "already in sync",
+        CopiedMissing(_) => "missing from the other side, copied",
+        CreatedDir(_) => "missing from the other side, created",
+        ReplacedFileWithDir(_) => "older than the directory of the other side, replaced",
+        ReplacedDirWithFile(_) => "older than the file of the other side, replaced",
+        KeptBoth(_) => "conflicting, both versions kept",
+        Deleted(_) => "gone from the other side, deleted",
+        LeftAsIs(_) => "differs but left as is",
+        SkippedTooLarge(_) => "too large, skipped",
+    };
+    let line = format!("WHY {}: {}", decision.path().display(), reason);
+
+    match decision {
+        CopiedNewer(_)
+        | CopiedMissing(_)
+        | CreatedDir(_)
+        | ReplacedFileWithDir(_)
+        | ReplacedDirWithFile(_)
+        | KeptBoth(_) => colors.copied(&line),
+        LeftAsIs(_) | SkippedTooLarge(_) => colors.skipped(&line),
+        AlreadyInSync(_) | Deleted(_) => line,
+    }
+}
+
+/// Format a number of bytes with a decimal unit (e.g. "4.2 GB").
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["kB", "MB", "GB", "TB"];
+
+    if bytes < 1000 {
+        return format!("{} B", bytes);
+    }
+
+    let mut size = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    // rounded to 1 decimal, "999.95 kB" would be "1000.0 kB"
+    while size >= 999.95 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Format a number with thousands separators (e.g. "1,203").
+fn format_count(count: u64) -> String {
+    let digits = count.to_string();
+    let mut formatted = String::with_capacity(digits.len() + digits.len() / 3);
+
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            formatted.push(',');
         }
 
-        exit(1);
+        formatted.push(digit);
+    }
+
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    #[test]
+    fn format_count() {
+        assert_eq!(super::format_count(0), "0");
+        assert_eq!(super::format_count(999), "999");
+        assert_eq!(super::format_count(1_203), "1,203");
+        assert_eq!(super::format_count(1_234_567), "1,234,567");
+    }
+
+    #[test]
+    fn parse_duration() {
+        let parse = super::parse_duration;
+
+        assert_eq!(parse("10"), Some(Duration::from_secs(10)));
+        assert_eq!(parse("10s"), Some(Duration::from_secs(10)));
+        assert_eq!(parse("5m"), Some(Duration::from_secs(300)));
+        assert_eq!(parse("1h"), Some(Duration::from_secs(3_600)));
+        assert_eq!(parse("0s"), None);
+        assert_eq!(parse("m"), None);
+        assert_eq!(parse("ten"), None);
+    }
+
+    #[test]
+    fn format_duration() {
+        let format = |seconds| super::format_duration(Duration::from_secs(seconds));
+
+        assert_eq!(format(0), "0s");
+        assert_eq!(format(45), "45s");
+        assert_eq!(format(200), "3m20s");
+        assert_eq!(format(3_605), "1h00m05s");
+    }
+
+    #[test]
+    fn format_bytes() {
+        assert_eq!(super::format_bytes(999), "999 B");
+        assert_eq!(super::format_bytes(1_000), "1.0 kB");
+        assert_eq!(super::format_bytes(999_960), "1.0 MB");
+        assert_eq!(super::format_bytes(4_200_000_000), "4.2 GB");
+    }
+
+    #[test]
+    fn parse_pairs() {
+        let pairs = "# backups\na\tb\n\n  # disabled\nc d\te\n";
+        assert_eq!(
+            super::parse_pairs(pairs),
+            Ok(vec![vec!["a", "b"], vec!["c d", "e"]])
+        );
+
+        assert_eq!(super::parse_pairs("a\tb\nc\n"), Err(2));
+        assert_eq!(super::parse_pairs("a\tb\tc\n"), Err(1));
+        assert_eq!(super::parse_pairs("a\t\n"), Err(1));
+    }
+
+    #[test]
+    fn format_filter_breakdown() {
+        let mut filtered = super::keep_keeping::FilterStats::default();
+        assert_eq!(super::format_filter_breakdown(&filtered), None);
+
+        filtered.excluded_patterns = 1_203;
+        filtered.too_large = 45;
+        assert_eq!(
+            super::format_filter_breakdown(&filtered).unwrap(),
+            "Exclude and ignore patterns excluded 1,203 entries; size filter excluded 45 files"
+        );
     }
 }
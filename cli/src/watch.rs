@@ -0,0 +1,71 @@
+//! Continuous synchronization, see `--watch`.
+
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::path::Path;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+/// How long nothing must change before synchronizing, a copy in progress fires many events.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Call `synchronize` whenever something changes in `paths`.
+///
+/// Only returns if the paths can't be watched.
+pub fn watch(paths: &[&Path], mut synchronize: impl FnMut()) -> notify::Result<()> {
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+
+    for path in paths {
+        watcher.watch(path, RecursiveMode::Recursive)?;
+    }
+
+    loop {
+        match receiver.recv() {
+            Ok(event) if is_change(&event) => (),
+            Ok(_) => continue,
+            // the watcher is never dropped before returning
+            Err(_) => return Ok(()),
+        }
+
+        wait_for_quiet(&receiver);
+
+        // changes made meanwhile are synchronized again, along with the events fired by the
+        // synchronization writing to the paths: a synchronization with nothing left to copy
+        // writes nothing
+        loop {
+            synchronize();
+
+            if !wait_for_quiet(&receiver) {
+                break;
+            }
+        }
+    }
+}
+
+/// Whether `event` may come from a change, reading files (e.g. when synchronizing) changes
+/// nothing but errors (e.g. too many events) may hide changes.
+fn is_change(event: &notify::Result<Event>) -> bool {
+    !matches!(
+        event,
+        Ok(Event {
+            kind: EventKind::Access(_),
+            ..
+        })
+    )
+}
+
+/// Drop events until nothing has changed for `DEBOUNCE`, returning whether anything changed.
+fn wait_for_quiet(receiver: &Receiver<notify::Result<Event>>) -> bool {
+    let mut deadline = Instant::now() + DEBOUNCE;
+    let mut changed = false;
+
+    while let Ok(event) = receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+    {
+        if is_change(&event) {
+            deadline = Instant::now() + DEBOUNCE;
+            changed = true;
+        }
+    }
+
+    changed
+}
@@ -0,0 +1,27 @@
+#![cfg(unix)]
+
+use std::process::Command;
+use std::time::Duration;
+
+#[test]
+fn interrupted_while_watching() {
+    let dir1 = tempfile::tempdir().unwrap();
+    let dir2 = tempfile::tempdir().unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_keep-keeping"))
+        .arg("--watch")
+        .arg(dir1.path())
+        .arg(dir2.path())
+        .spawn()
+        .unwrap();
+
+    // let the first synchronization end
+    std::thread::sleep(Duration::from_secs(1));
+    let interrupted = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+
+    assert!(interrupted.success());
+    assert_eq!(child.wait().unwrap().code(), Some(130));
+}
@@ -0,0 +1,33 @@
+use std::process::Command;
+
+fn synchronize(args: &[&std::ffi::OsStr]) -> Option<i32> {
+    Command::new(env!("CARGO_BIN_EXE_keep-keeping"))
+        .args(args)
+        .output()
+        .unwrap()
+        .status
+        .code()
+}
+
+#[test]
+fn missing_target_is_created() {
+    let dir1 = tempfile::tempdir().unwrap();
+    let dir2 = tempfile::tempdir().unwrap();
+    let target = dir2.path().join("backup");
+
+    std::fs::create_dir(dir1.path().join("sub")).unwrap();
+    std::fs::write(dir1.path().join("sub").join("a"), "a").unwrap();
+
+    // missing paths are reported by default
+    let code = synchronize(&[dir1.path().as_os_str(), target.as_os_str()]);
+    assert_eq!(code, Some(4));
+    assert!(!target.exists());
+
+    let code = synchronize(&[
+        "--create-missing-target".as_ref(),
+        dir1.path().as_os_str(),
+        target.as_os_str(),
+    ]);
+    assert_eq!(code, Some(0));
+    assert_eq!(std::fs::read(target.join("sub").join("a")).unwrap(), b"a");
+}
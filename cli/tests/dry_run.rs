@@ -0,0 +1,86 @@
+use std::process::Command;
+
+#[test]
+fn dry_run_leaves_the_tree_unchanged() {
+    let dir1 = tempfile::tempdir().unwrap();
+    let dir2 = tempfile::tempdir().unwrap();
+
+    std::fs::create_dir(dir1.path().join("sub")).unwrap();
+    std::fs::write(dir1.path().join("sub").join("a"), "a").unwrap();
+    std::fs::write(dir2.path().join("b"), "b").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_keep-keeping"))
+        .arg("--dry-run")
+        .arg(dir1.path())
+        .arg(dir2.path())
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(&format!(
+        "COPY {} -> {}",
+        dir2.path().join("b").display(),
+        dir1.path().join("b").display()
+    )));
+    assert!(stdout.contains(&format!("MKDIR {}", dir2.path().join("sub").display())));
+
+    let names = |dir: &std::path::Path| {
+        let mut names: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        names
+    };
+    assert_eq!(names(dir1.path()), ["sub"]);
+    assert_eq!(names(dir2.path()), ["b"]);
+}
+
+#[test]
+fn estimate() {
+    let dir1 = tempfile::tempdir().unwrap();
+    let dir2 = tempfile::tempdir().unwrap();
+    std::fs::write(dir1.path().join("a"), vec![0; 2_500_000]).unwrap();
+    std::fs::write(dir2.path().join("b"), vec![0; 500_000]).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_keep-keeping"))
+        .args(["--dry-run", "--stats", "--throughput", "1"])
+        .arg(dir1.path())
+        .arg(dir2.path())
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("Copying 3,000,000 bytes would take ~3s at 1 MB/s"));
+    assert!(!dir2.path().join("a").exists());
+}
+
+#[test]
+fn colored_actions() {
+    let dir1 = tempfile::tempdir().unwrap();
+    let dir2 = tempfile::tempdir().unwrap();
+    std::fs::write(dir1.path().join("a"), "a").unwrap();
+
+    let dry_run = |color: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_keep-keeping"))
+            .args(["--dry-run", "--color", color])
+            .arg(dir1.path())
+            .arg(dir2.path())
+            .output()
+            .unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let copy = format!(
+        "COPY {} -> {}",
+        dir1.path().join("a").display(),
+        dir2.path().join("a").display()
+    );
+
+    assert!(dry_run("always").contains(&format!("\x1b[32m{}\x1b[0m", copy)));
+    // stdout isn't a terminal
+    assert!(!dry_run("auto").contains('\x1b'));
+}
@@ -0,0 +1,68 @@
+//! Errors are caused by paths too long for Linux (`PATH_MAX` is 4096 bytes): the second
+//! directory is nested deep enough to store short names only, which works even as root.
+#![cfg(target_os = "linux")]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Length of the second directory path, leaving room for names up to ~50 bytes.
+const DEEP_PATH_LEN: usize = 4040;
+
+/// Create a directory whose path is `DEEP_PATH_LEN` bytes long under `base`.
+fn create_deep_dir(base: &Path) -> PathBuf {
+    let mut path = base.to_owned();
+    while path.as_os_str().len() < DEEP_PATH_LEN - 201 {
+        path.push("d".repeat(200));
+    }
+    path.push("d".repeat(DEEP_PATH_LEN - 1 - path.as_os_str().len()));
+
+    std::fs::create_dir_all(&path).unwrap();
+    path
+}
+
+fn synchronize(error_mode: &str) -> (Output, PathBuf, tempfile::TempDir) {
+    let dir1 = tempfile::tempdir().unwrap();
+    let base2 = tempfile::tempdir().unwrap();
+    let dir2 = create_deep_dir(base2.path());
+
+    std::fs::write(dir1.path().join("fine"), "fine").unwrap();
+    // too long once in the second directory
+    std::fs::write(dir1.path().join("n".repeat(100)), "long").unwrap();
+    std::fs::write(dir1.path().join("z"), "z").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_keep-keeping"))
+        .args(["--error-mode", error_mode])
+        .arg(dir1.path())
+        .arg(&dir2)
+        .output()
+        .unwrap();
+
+    (output, dir2, base2)
+}
+
+#[test]
+fn error_mode_skip() {
+    let (output, dir2, _base2) = synchronize("skip");
+
+    // some entries were skipped
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(std::fs::read(dir2.join("fine")).unwrap(), b"fine");
+    assert_eq!(std::fs::read(dir2.join("z")).unwrap(), b"z");
+}
+
+#[test]
+fn error_mode_ignore() {
+    let (output, dir2, _base2) = synchronize("ignore");
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(!output.stderr.is_empty());
+    assert_eq!(std::fs::read(dir2.join("fine")).unwrap(), b"fine");
+    assert_eq!(std::fs::read(dir2.join("z")).unwrap(), b"z");
+}
+
+#[test]
+fn error_mode_fail() {
+    let (output, _dir2, _base2) = synchronize("fail");
+
+    assert_eq!(output.status.code(), Some(1));
+}
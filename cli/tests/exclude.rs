@@ -0,0 +1,46 @@
+use std::process::{Command, Output};
+
+fn synchronize(exclude: &str, dir1: &std::path::Path, dir2: &std::path::Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_keep-keeping"))
+        .args(["--stats", "--exclude", exclude, "--exclude", "cache"])
+        .arg(dir1)
+        .arg(dir2)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn excluded_entries_are_not_mirrored() {
+    let dir1 = tempfile::tempdir().unwrap();
+    let dir2 = tempfile::tempdir().unwrap();
+    std::fs::write(dir1.path().join("kept"), "kept").unwrap();
+    std::fs::write(dir1.path().join("scratch.tmp"), "tmp").unwrap();
+    std::fs::create_dir(dir1.path().join("cache")).unwrap();
+    std::fs::write(dir1.path().join("cache").join("data"), "data").unwrap();
+
+    let output = synchronize("*.tmp", dir1.path(), dir2.path());
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(dir2.path().join("kept").is_file());
+    assert!(!dir2.path().join("scratch.tmp").exists());
+    assert!(!dir2.path().join("cache").exists());
+    // the content of an excluded directory isn't walked
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("Exclude and ignore patterns excluded 2 entries"));
+}
+
+#[test]
+fn invalid_pattern() {
+    let dir1 = tempfile::tempdir().unwrap();
+    let dir2 = tempfile::tempdir().unwrap();
+    std::fs::write(dir1.path().join("a"), "a").unwrap();
+
+    let output = synchronize("[", dir1.path(), dir2.path());
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("Invalid pattern '['"));
+    assert!(!dir2.path().join("a").exists());
+}
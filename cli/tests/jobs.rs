@@ -0,0 +1,54 @@
+use std::process::Command;
+
+fn synchronize(args: &[&std::ffi::OsStr]) -> Option<i32> {
+    Command::new(env!("CARGO_BIN_EXE_keep-keeping"))
+        .args(args)
+        .output()
+        .unwrap()
+        .status
+        .code()
+}
+
+#[test]
+fn parallel_copies() {
+    let dir1 = tempfile::tempdir().unwrap();
+    let dir2 = tempfile::tempdir().unwrap();
+
+    for i in 0..50 {
+        let sub_dir = dir1.path().join(format!("sub{}", i % 4));
+        std::fs::create_dir_all(&sub_dir).unwrap();
+        std::fs::write(sub_dir.join(i.to_string()), i.to_string()).unwrap();
+    }
+
+    let code = synchronize(&[
+        "--jobs".as_ref(),
+        "2".as_ref(),
+        dir1.path().as_os_str(),
+        dir2.path().as_os_str(),
+    ]);
+    assert_eq!(code, Some(0));
+
+    for i in 0..50 {
+        let path = dir2
+            .path()
+            .join(format!("sub{}", i % 4))
+            .join(i.to_string());
+        assert_eq!(std::fs::read_to_string(path).unwrap(), i.to_string());
+    }
+}
+
+#[test]
+fn invalid_jobs() {
+    let dir1 = tempfile::tempdir().unwrap();
+    let dir2 = tempfile::tempdir().unwrap();
+
+    for jobs in ["0", "many"] {
+        let code = synchronize(&[
+            "--jobs".as_ref(),
+            jobs.as_ref(),
+            dir1.path().as_os_str(),
+            dir2.path().as_os_str(),
+        ]);
+        assert_eq!(code, Some(2));
+    }
+}
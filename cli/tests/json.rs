@@ -0,0 +1,43 @@
+use std::process::Command;
+
+#[test]
+fn json_output() {
+    let dir1 = tempfile::tempdir().unwrap();
+    let dir2 = tempfile::tempdir().unwrap();
+
+    std::fs::write(dir1.path().join("a"), "a").unwrap();
+    std::fs::write(dir1.path().join("b"), "b").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_keep-keeping"))
+        .args(["--format", "json"])
+        .arg(dir1.path())
+        .arg(dir2.path())
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["stats"]["files_copied"], 2);
+    assert_eq!(json["errors"], serde_json::json!([]));
+}
+
+#[test]
+fn json_output_missing_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let missing = dir.path().join("missing");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_keep-keeping"))
+        .args(["--format", "json"])
+        .arg(dir.path())
+        .arg(&missing)
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(4));
+    assert!(output.stderr.is_empty());
+
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["stats"], serde_json::Value::Null);
+    assert_eq!(json["errors"][0]["path"], missing.to_str().unwrap());
+}
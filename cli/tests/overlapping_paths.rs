@@ -0,0 +1,20 @@
+use std::process::Command;
+
+#[test]
+fn nested_paths_are_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let nested = dir.path().join("nested");
+    std::fs::create_dir(&nested).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_keep-keeping"))
+        .arg(dir.path())
+        .arg(&nested)
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("are the same path or one of them contains the other"));
+    assert!(std::fs::read_dir(&nested).unwrap().next().is_none());
+}
@@ -0,0 +1,66 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn synchronize(pairs: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_keep-keeping"))
+        .args(["--pairs", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(pairs.as_bytes())
+        .unwrap();
+
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn every_pair_is_synchronized() {
+    let dirs: Vec<_> = (0..4).map(|_| tempfile::tempdir().unwrap()).collect();
+    std::fs::write(dirs[0].path().join("a"), "a").unwrap();
+    std::fs::write(dirs[2].path().join("b"), "bb").unwrap();
+    std::fs::write(dirs[2].path().join("c"), "ccc").unwrap();
+
+    let pairs = format!(
+        "# documents\n{}\t{}\n\n{}\t{}\n",
+        dirs[0].path().display(),
+        dirs[1].path().display(),
+        dirs[2].path().display(),
+        dirs[3].path().display()
+    );
+    let output = synchronize(&pairs);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(dirs[1].path().join("a").is_file());
+    assert!(dirs[3].path().join("b").is_file());
+    assert!(dirs[3].path().join("c").is_file());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("Synchronized 2 pairs: copied 3 files (6 bytes), 0 errors"));
+}
+
+#[test]
+fn invalid_line() {
+    let output = synchronize("only one path\n");
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("Line 1"));
+}
+
+#[test]
+fn missing_path() {
+    let dir = tempfile::tempdir().unwrap();
+    let pairs = format!(
+        "{}\t{}\n",
+        dir.path().display(),
+        dir.path().join("missing").display()
+    );
+
+    assert_eq!(synchronize(&pairs).status.code(), Some(4));
+}
@@ -0,0 +1,53 @@
+use std::process::{Command, Output};
+
+fn synchronize(flag: &str, dir1: &std::path::Path, dir2: &std::path::Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_keep-keeping"))
+        .arg(flag)
+        .arg(dir1)
+        .arg(dir2)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn verbose_prints_modifications() {
+    let dir1 = tempfile::tempdir().unwrap();
+    let dir2 = tempfile::tempdir().unwrap();
+    std::fs::write(dir1.path().join("a"), "a").unwrap();
+    std::fs::write(dir2.path().join("b"), "b").unwrap();
+
+    let output = synchronize("-v", dir1.path(), dir2.path());
+    assert_eq!(output.status.code(), Some(0));
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(&format!(
+        "COPY {} -> {}",
+        dir1.path().join("a").display(),
+        dir2.path().join("a").display()
+    )));
+    assert!(stdout.contains(&format!(
+        "COPY {} -> {}",
+        dir2.path().join("b").display(),
+        dir1.path().join("b").display()
+    )));
+    assert!(!stdout.contains("CHECK"));
+
+    let stdout = String::from_utf8(synchronize("-vv", dir1.path(), dir2.path()).stdout).unwrap();
+    // already synchronized
+    assert!(!stdout.contains("COPY"));
+    assert!(stdout.contains("CHECK a"));
+    assert!(stdout.contains("WHY a: already in sync"));
+}
+
+#[test]
+fn quiet_prints_nothing() {
+    let dir1 = tempfile::tempdir().unwrap();
+    let dir2 = tempfile::tempdir().unwrap();
+    std::fs::write(dir1.path().join("a"), "a").unwrap();
+
+    let output = synchronize("-q", dir1.path(), dir2.path());
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+}
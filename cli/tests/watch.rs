@@ -0,0 +1,46 @@
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// Wait for `path` to contain `content`, returning whether it did before timing out.
+fn wait_for_content(path: &Path, content: &str) -> bool {
+    let start = Instant::now();
+
+    while start.elapsed() < Duration::from_secs(10) {
+        if std::fs::read_to_string(path).is_ok_and(|read| read == content) {
+            return true;
+        }
+
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    false
+}
+
+#[test]
+fn watch_synchronizes_changes() {
+    let dir1 = tempfile::tempdir().unwrap();
+    let dir2 = tempfile::tempdir().unwrap();
+    let file1 = dir1.path().join("file");
+    let file2 = dir2.path().join("file");
+    std::fs::write(&file1, "first").unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_keep-keeping"))
+        .arg("--watch")
+        .arg(dir1.path())
+        .arg(dir2.path())
+        .spawn()
+        .unwrap();
+
+    let first_sync = wait_for_content(&file2, "first");
+    // let the watcher start and the events of the first synchronization be dropped
+    std::thread::sleep(Duration::from_secs(2));
+    std::fs::write(&file1, "second").unwrap();
+    let second_sync = wait_for_content(&file2, "second");
+
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    assert!(first_sync);
+    assert!(second_sync);
+}
@@ -0,0 +1,20 @@
+#![no_main]
+
+use keep_keeping_lib::pathutil::trim_base_path;
+use libfuzzer_sys::fuzz_target;
+use std::path::PathBuf;
+
+/// Both separators are accepted by `trim_base_path` whatever the platform.
+fn normalize(path: &str) -> PathBuf {
+    PathBuf::from(path.replace('\\', "/"))
+}
+
+fuzz_target!(|input: (String, String)| {
+    let (base, entry) = input;
+
+    if let Some(relative) = trim_base_path(&base, &entry) {
+        let relative = relative.to_str().unwrap();
+
+        assert_eq!(normalize(&base).join(normalize(relative)), normalize(&entry));
+    }
+});
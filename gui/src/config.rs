@@ -0,0 +1,74 @@
+//! Paths remembered between launches.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Name of the configuration file, in the configuration directory of the platform.
+const CONFIG_NAME: &str = "directories.json";
+
+/// Pairs of paths last synchronized successfully.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Config {
+    pub pairs: Vec<Pair>,
+}
+
+/// Paths synchronized together, as entered.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pair {
+    pub path1: String,
+    pub path2: String,
+}
+
+impl Config {
+    /// Read the configuration stored at `path`, `None` if there's none or it's invalid.
+    pub fn load_from(path: &Path) -> Option<Self> {
+        let content = fs::read(path).ok()?;
+
+        serde_json::from_slice(&content).ok()
+    }
+
+    /// Store the configuration at `path`, creating its parent directories if needed.
+    pub fn save_to(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+}
+
+/// Get where the configuration is stored, `None` if the platform has no configuration
+/// directory.
+pub fn config_path() -> Option<PathBuf> {
+    directories::ProjectDirs::from("", "", "Keep Keeping")
+        .map(|dirs| dirs.config_dir().join(CONFIG_NAME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, Pair};
+
+    #[test]
+    fn config_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join(super::CONFIG_NAME);
+        let config = Config {
+            pairs: vec![
+                Pair {
+                    path1: "/path/1".to_owned(),
+                    path2: "C:\\path 2".to_owned(),
+                },
+                Pair::default(),
+            ],
+        };
+
+        assert_eq!(Config::load_from(&path), None);
+        config.save_to(&path).unwrap();
+        assert_eq!(Config::load_from(&path), Some(config));
+
+        std::fs::write(&path, "not JSON").unwrap();
+        assert_eq!(Config::load_from(&path), None);
+    }
+}
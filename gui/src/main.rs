@@ -1,9 +1,52 @@
 #![forbid(unsafe_code)]
 
+mod config;
+
 use keep_keeping_lib as keep_keeping;
 
 use iui::controls::*;
 use iui::prelude::*;
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the UI checks how the synchronization is going, in milliseconds.
+const POLL_DELAY: u32 = 100;
+/// How long the full progress bar stays visible once synchronized.
+const PROGRESS_DONE_DELAY: Duration = Duration::from_millis(500);
+/// How many error messages are shown once synchronized.
+const SHOWN_ERRORS: usize = 5;
+
+/// Sent by the synchronization thread to the UI.
+enum Message {
+    Progress { processed: u64, total: u64 },
+    Done(Vec<Outcome>),
+}
+
+/// Outcome of the synchronization of a pair of paths.
+struct Outcome {
+    succeeded: bool,
+    errors: Vec<String>,
+}
+
+/// Entries of a pair of paths to synchronize.
+#[derive(Clone)]
+struct PairEntries {
+    path1: Entry,
+    path2: Entry,
+}
+
+impl PairEntries {
+    fn value(&self, ui: &UI) -> config::Pair {
+        config::Pair {
+            path1: self.path1.value(ui),
+            path2: self.path2.value(ui),
+        }
+    }
+}
 
 pub fn main() {
     let ui = UI::init().expect("Failed to initialize UI");
@@ -19,83 +62,128 @@ pub fn main() {
     let mut box_v = VerticalBox::new(&ui);
     box_v.set_padded(&ui, true);
 
-    let mut box_path1 = HorizontalBox::new(&ui);
-    let mut box_path2 = HorizontalBox::new(&ui);
+    let mut box_pairs = VerticalBox::new(&ui);
+    box_pairs.set_padded(&ui, true);
 
-    box_path2.set_padded(&ui, true);
-    box_path1.set_padded(&ui, true);
+    // saved paths are filled in even if they no longer exist, telling what happened to them
+    let saved_pairs = config::config_path()
+        .and_then(|path| config::Config::load_from(&path))
+        .map(|config| config.pairs)
+        .filter(|pairs| !pairs.is_empty())
+        .unwrap_or_else(|| vec![config::Pair::default()]);
 
-    let label_dir1 = Label::new(&ui, "Path 1");
-    let entry_dir1 = Entry::new(&ui);
-    let mut button_select_dir1 = Button::new(&ui, "…");
-    button_select_dir1.on_clicked(&ui, {
-        let mut entry_dir1 = entry_dir1.clone();
-        let ui = ui.clone();
-        move |_| {
-            if let Some(path) = pick_folder() {
-                entry_dir1.set_value(&ui, &path);
-            }
-        }
-    });
+    let pairs = Rc::new(RefCell::new(Vec::new()));
+    for pair in &saved_pairs {
+        let entries = append_pair(&ui, &mut box_pairs, pair);
+        pairs.borrow_mut().push(entries);
+    }
 
-    let label_dir2 = Label::new(&ui, "Path 2");
-    let entry_dir2 = Entry::new(&ui);
-    let mut button_select_dir2 = Button::new(&ui, "…");
-    button_select_dir2.on_clicked(&ui, {
-        let mut entry_dir2 = entry_dir2.clone();
+    let mut button_add_pair = Button::new(&ui, "Add pair");
+    button_add_pair.on_clicked(&ui, {
+        let mut box_pairs = box_pairs.clone();
+        let pairs = pairs.clone();
         let ui = ui.clone();
         move |_| {
-            if let Some(path) = pick_folder() {
-                entry_dir2.set_value(&ui, &path);
-            }
+            box_pairs.append(&ui, HorizontalSeparator::new(&ui), LayoutStrategy::Compact);
+
+            let entries = append_pair(&ui, &mut box_pairs, &config::Pair::default());
+            pairs.borrow_mut().push(entries);
         }
     });
 
-    box_path1.append(&ui, label_dir1, LayoutStrategy::Compact);
-    box_path2.append(&ui, label_dir2, LayoutStrategy::Compact);
-
-    box_path1.append(&ui, entry_dir1.clone(), LayoutStrategy::Stretchy);
-    box_path2.append(&ui, entry_dir2.clone(), LayoutStrategy::Stretchy);
+    // synchronizations run on another thread to keep the window responsive
+    let (sender, receiver) = mpsc::channel();
 
-    box_path1.append(&ui, button_select_dir1, LayoutStrategy::Compact);
-    box_path2.append(&ui, button_select_dir2, LayoutStrategy::Compact);
+    let mut progress_bar = ProgressBar::new();
+    let mut entry_errors = MultilineEntry::new(&ui);
 
     let mut button_synchronize = Button::new(&ui, "Synchronize");
     button_synchronize.on_clicked(&ui, {
         let mut win = win.clone();
+        let mut progress_bar = progress_bar.clone();
+        let mut entry_errors = entry_errors.clone();
         let ui = ui.clone();
-        move |_| {
-            use std::path::Path;
+        move |button| {
+            let config = config::Config {
+                pairs: pairs
+                    .borrow()
+                    .iter()
+                    .map(|entries| entries.value(&ui))
+                    .collect(),
+            };
 
-            let path1 = &entry_dir1.value(&ui);
-            let path2 = &entry_dir2.value(&ui);
+            let missing =
+                config.pairs.iter().enumerate().find_map(|(i, pair)| {
+                    missing_paths_message(pair).map(|message| (i + 1, message))
+                });
 
-            let path1 = Path::new(path1);
-            let path2 = Path::new(path2);
+            match missing {
+                Some((_, message)) if config.pairs.len() == 1 => {
+                    win.set_title(&ui, &format!("Keep Keeping – {}", message))
+                }
+                Some((number, message)) => {
+                    win.set_title(&ui, &format!("Keep Keeping – Pair {}: {}", number, message))
+                }
+                None => {
+                    win.set_title(&ui, "Keep Keeping – Synchronizing…");
+                    // re-enabled once done
+                    button.disable(&ui);
+                    progress_bar.set_value(&ui, 0);
+                    progress_bar.show(&ui);
+                    entry_errors.hide(&ui);
 
-            if !path1.exists() {
-                if path2.exists() {
-                    win.set_title(&ui, "Keep Keeping – Path 1 does not exist");
-                } else {
-                    win.set_title(&ui, "Keep Keeping – Path 1 & Path 2 do not exist");
+                    let sender = sender.clone();
+                    thread::spawn(move || {
+                        let outcomes: Vec<_> = config
+                            .pairs
+                            .iter()
+                            .map(|pair| {
+                                let on_progress = {
+                                    let sender = sender.clone();
+                                    move |progress: &keep_keeping::Progress| {
+                                        let _ = sender.send(Message::Progress {
+                                            processed: progress.processed,
+                                            total: progress.total.unwrap_or(progress.discovered),
+                                        });
+                                    }
+                                };
+
+                                let (result, errors) = synchronize(
+                                    Path::new(&pair.path1),
+                                    Path::new(&pair.path2),
+                                    on_progress,
+                                );
+
+                                Outcome {
+                                    succeeded: result.is_ok(),
+                                    errors,
+                                }
+                            })
+                            .collect();
+
+                        let succeeded = outcomes.iter().all(|outcome| outcome.succeeded);
+                        if let (true, Some(config_path)) = (succeeded, config::config_path()) {
+                            // forgetting the paths isn't worth bothering the user
+                            let _ = config.save_to(&config_path);
+                        }
+
+                        // the window may have been closed meanwhile
+                        let _ = sender.send(Message::Done(outcomes));
+                    });
                 }
-            } else if !path2.exists() {
-                win.set_title(&ui, "Keep Keeping – Path 2 does not exist");
-            } else {
-                use keep_keeping::{synchronize, ErrorHandlingType};
-
-                win.set_title(&ui, "Keep Keeping – Synchronizing…");
-                match synchronize(path1, path2, |_| ErrorHandlingType::Fail) {
-                    Ok(_) => win.set_title(&ui, "Keep Keeping – Done"),
-                    Err(_) => win.set_title(&ui, "Keep Keeping – Failure"),
-                };
             }
         }
     });
 
-    box_v.append(&ui, box_path1, LayoutStrategy::Compact);
-    box_v.append(&ui, box_path2, LayoutStrategy::Compact);
-    box_v.append(&ui, button_synchronize, LayoutStrategy::Compact);
+    box_v.append(&ui, box_pairs, LayoutStrategy::Compact);
+    box_v.append(&ui, button_add_pair, LayoutStrategy::Compact);
+    box_v.append(&ui, button_synchronize.clone(), LayoutStrategy::Compact);
+    box_v.append(&ui, progress_bar.clone(), LayoutStrategy::Compact);
+    box_v.append(&ui, entry_errors.clone(), LayoutStrategy::Stretchy);
+    // only shown while synchronizing
+    progress_bar.hide(&ui);
+    // only shown when there is something to tell
+    entry_errors.hide(&ui);
 
     win.set_child(&ui, box_v);
     win.on_closing(&ui, {
@@ -103,17 +191,264 @@ pub fn main() {
         move |_| ui.quit()
     });
     win.show(&ui);
-    ui.main();
+
+    let mut event_loop = ui.event_loop();
+    event_loop.on_tick(&ui, {
+        let mut win = win.clone();
+        let ui = ui.clone();
+        let mut hide_progress_at = None;
+        move || {
+            for message in receiver.try_iter() {
+                match message {
+                    Message::Progress { processed, total } => {
+                        // the precounted total may miss entries, the bar stops at 100%
+                        let percentage = processed * 100 / total.max(processed).max(1);
+                        progress_bar.set_value(&ui, percentage as u32);
+                        // a new synchronization may have started meanwhile
+                        hide_progress_at = None;
+                    }
+                    Message::Done(outcomes) => {
+                        win.set_title(&ui, &format!("Keep Keeping – {}", summarize(&outcomes)));
+
+                        let details = format_outcomes(&outcomes);
+                        if !details.is_empty() {
+                            entry_errors.set_value(&ui, &details);
+                            entry_errors.show(&ui);
+                        }
+
+                        progress_bar.set_value(&ui, 100);
+                        hide_progress_at = Some(Instant::now() + PROGRESS_DONE_DELAY);
+                        button_synchronize.enable(&ui);
+                    }
+                }
+            }
+
+            if hide_progress_at.is_some_and(|hide_at| Instant::now() >= hide_at) {
+                progress_bar.hide(&ui);
+                hide_progress_at = None;
+            }
+        }
+    });
+    event_loop.run_delay(&ui, POLL_DELAY);
 }
 
-pub fn pick_folder() -> Option<String> {
-    if let Ok(response) = nfd::open_pick_folder(None) {
-        if let nfd::Response::Okay(path) = response {
-            Some(path)
-        } else {
-            None
+/// Append the controls of a pair of paths to `box_pairs`, filled in with `pair`.
+fn append_pair(ui: &UI, box_pairs: &mut VerticalBox, pair: &config::Pair) -> PairEntries {
+    let mut entries = PairEntries {
+        path1: Entry::new(ui),
+        path2: Entry::new(ui),
+    };
+    entries.path1.set_value(ui, &pair.path1);
+    entries.path2.set_value(ui, &pair.path2);
+
+    for (label, entry) in [("Path 1", &entries.path1), ("Path 2", &entries.path2)] {
+        let mut box_path = HorizontalBox::new(ui);
+        box_path.set_padded(ui, true);
+
+        // the library synchronizes files as well as folders
+        let mut button_select_folder = Button::new(ui, "Folder…");
+        button_select_folder.on_clicked(ui, on_pick(ui, entry, pick_folder));
+        let mut button_select_file = Button::new(ui, "File…");
+        button_select_file.on_clicked(ui, on_pick(ui, entry, pick_file));
+
+        box_path.append(ui, Label::new(ui, label), LayoutStrategy::Compact);
+        box_path.append(ui, entry.clone(), LayoutStrategy::Stretchy);
+        box_path.append(ui, button_select_folder, LayoutStrategy::Compact);
+        box_path.append(ui, button_select_file, LayoutStrategy::Compact);
+        box_pairs.append(ui, box_path, LayoutStrategy::Compact);
+    }
+
+    entries
+}
+
+/// Handle the clicks of a button filling in `entry` with the path picked by `pick`, leaving it
+/// unchanged if the dialog is cancelled.
+fn on_pick(ui: &UI, entry: &Entry, pick: fn() -> Option<String>) -> impl FnMut(&mut Button) {
+    let mut entry = entry.clone();
+    let ui = ui.clone();
+
+    move |_| {
+        if let Some(path) = pick() {
+            entry.set_value(&ui, &path);
         }
-    } else {
-        None
+    }
+}
+
+/// Tell which paths of `pair` don't exist, if any.
+fn missing_paths_message(pair: &config::Pair) -> Option<&'static str> {
+    match (
+        Path::new(&pair.path1).exists(),
+        Path::new(&pair.path2).exists(),
+    ) {
+        (true, true) => None,
+        (false, true) => Some("Path 1 does not exist"),
+        (true, false) => Some("Path 2 does not exist"),
+        (false, false) => Some("Path 1 & Path 2 do not exist"),
+    }
+}
+
+/// Summarize the synchronization of every pair, for the window title.
+fn summarize(outcomes: &[Outcome]) -> String {
+    let failed = outcomes.iter().filter(|outcome| !outcome.succeeded).count();
+    let error_count: usize = outcomes.iter().map(|outcome| outcome.errors.len()).sum();
+
+    match outcomes {
+        _ if failed == 0 && error_count == 0 => "Done".to_owned(),
+        _ if failed == 0 => format!("Completed with {} errors", error_count),
+        [outcome] => match outcome.errors.first() {
+            Some(error) => format!("Failure: {}", error),
+            None => "Failure".to_owned(),
+        },
+        _ => format!("{} of {} pairs failed", failed, outcomes.len()),
+    }
+}
+
+/// Describe the outcome of each pair when there are several of them, along with their
+/// errors. Empty if there's nothing more to tell than the summary.
+fn format_outcomes(outcomes: &[Outcome]) -> String {
+    match outcomes {
+        [outcome] if outcome.errors.is_empty() => String::new(),
+        [outcome] => format_errors(&outcome.errors),
+        _ => outcomes
+            .iter()
+            .enumerate()
+            .map(|(i, outcome)| {
+                let summary = summarize(std::slice::from_ref(outcome));
+
+                if outcome.errors.is_empty() {
+                    format!("Pair {}: {}", i + 1, summary)
+                } else {
+                    format!(
+                        "Pair {}: {}\n{}",
+                        i + 1,
+                        summary,
+                        format_errors(&outcome.errors)
+                    )
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+/// Synchronize 2 paths, returning the messages of the errors which happened.
+fn synchronize(
+    path1: &Path,
+    path2: &Path,
+    on_progress: impl Fn(&keep_keeping::Progress) + 'static,
+) -> (
+    Result<keep_keeping::SyncReport, keep_keeping::SyncError>,
+    Vec<String>,
+) {
+    let errors = Rc::new(RefCell::new(Vec::new()));
+    let options = keep_keeping::SyncOptions {
+        error_handler: Box::new({
+            let errors = errors.clone();
+            move |err| {
+                errors.borrow_mut().push(err.to_string());
+                // every error is shown once done
+                keep_keeping::ErrorHandlingType::Skip
+            }
+        }),
+        on_progress: Some(Box::new(on_progress)),
+        // the progress bar needs the total from the start
+        precount: true,
+        ..Default::default()
+    };
+
+    let result = keep_keeping::synchronize_with(path1, path2, &options);
+    (result, errors.take())
+}
+
+/// Format the first error messages, one per line.
+fn format_errors(errors: &[String]) -> String {
+    let mut formatted = errors[..errors.len().min(SHOWN_ERRORS)].join("\n");
+
+    if errors.len() > SHOWN_ERRORS {
+        formatted += &format!("\n…and {} more", errors.len() - SHOWN_ERRORS);
+    }
+
+    formatted
+}
+
+/// Let the user pick a folder, `None` if cancelled.
+pub fn pick_folder() -> Option<String> {
+    picked_path(nfd::open_pick_folder(None))
+}
+
+/// Let the user pick a file, `None` if cancelled.
+pub fn pick_file() -> Option<String> {
+    picked_path(nfd::open_file_dialog(None, None))
+}
+
+/// Get the path picked in a dialog, `None` if it has been cancelled or has failed.
+fn picked_path<E>(response: Result<nfd::Response, E>) -> Option<String> {
+    match response {
+        Ok(nfd::Response::Okay(path)) => Some(path),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn synchronize() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        std::fs::write(dir1.path().join("a"), "a").unwrap();
+
+        let (result, errors) = super::synchronize(dir1.path(), dir2.path(), |_| ());
+
+        assert!(result.is_ok());
+        assert!(errors.is_empty());
+        assert!(dir2.path().join("a").exists());
+    }
+
+    #[test]
+    fn picked_path() {
+        let picked = |response| super::picked_path::<()>(Ok(response));
+
+        assert_eq!(
+            picked(nfd::Response::Okay("/a".to_owned())),
+            Some("/a".to_owned())
+        );
+        assert_eq!(picked(nfd::Response::Cancel), None);
+        assert_eq!(super::picked_path::<()>(Err(())), None);
+    }
+
+    #[test]
+    fn summarize() {
+        let outcome = |succeeded, error_count| super::Outcome {
+            succeeded,
+            errors: (1..=error_count).map(|i| format!("Error {}", i)).collect(),
+        };
+
+        assert_eq!(super::summarize(&[outcome(true, 0)]), "Done");
+        assert_eq!(
+            super::summarize(&[outcome(true, 2), outcome(true, 1)]),
+            "Completed with 3 errors"
+        );
+        assert_eq!(super::summarize(&[outcome(false, 1)]), "Failure: Error 1");
+        assert_eq!(
+            super::summarize(&[outcome(true, 0), outcome(false, 1)]),
+            "1 of 2 pairs failed"
+        );
+
+        assert_eq!(super::format_outcomes(&[outcome(true, 0)]), "");
+        assert_eq!(
+            super::format_outcomes(&[outcome(true, 0), outcome(false, 1)]),
+            "Pair 1: Done\nPair 2: Failure: Error 1\nError 1"
+        );
+    }
+
+    #[test]
+    fn format_errors() {
+        let errors: Vec<_> = (1..=7).map(|i| format!("Error {}", i)).collect();
+
+        assert_eq!(super::format_errors(&errors[..1]), "Error 1");
+        assert_eq!(
+            super::format_errors(&errors),
+            "Error 1\nError 2\nError 3\nError 4\nError 5\n…and 2 more"
+        );
     }
 }
@@ -0,0 +1,178 @@
+//! Tar archives a directory can be exported into, see `synchronize_into`.
+
+use crate::SyncError;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Entry of a `TarArchive`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    /// Path of the entry, relative to the root of the archive.
+    pub path: PathBuf,
+    pub is_dir: bool,
+    /// Size of the content of a file, `0` for a directory.
+    pub size: u64,
+    pub mtime: SystemTime,
+}
+
+/// Tar archive, read when opened and written again as a whole once exported into.
+///
+/// The whole content of the archive is kept in memory. Only files and directories are kept,
+/// modification times are rounded down to the second.
+pub struct TarArchive {
+    path: PathBuf,
+    /// Content of each file, `None` for directories.
+    entries: BTreeMap<PathBuf, (Option<Vec<u8>>, SystemTime)>,
+}
+
+impl TarArchive {
+    /// Read the archive at `path`, which is created once exported into if it doesn't exist yet.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, SyncError> {
+        let path = path.into();
+        let mut entries = BTreeMap::new();
+
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(TarArchive { path, entries })
+            }
+            Err(err) => return Err(SyncError::io(&path, err)),
+        };
+
+        let io_error = |err| SyncError::io(&path, err);
+        let mut archive = tar::Archive::new(file);
+        for entry in archive.entries().map_err(io_error)? {
+            let mut entry = entry.map_err(io_error)?;
+            // directories are stored with a trailing separator, and paths may start with `./`
+            // (e.g. `tar -C dir .`)
+            let entry_path: PathBuf = entry
+                .path()
+                .map_err(io_error)?
+                .components()
+                .filter(|component| *component != Component::CurDir)
+                .collect();
+            // the root itself
+            if entry_path.as_os_str().is_empty() {
+                continue;
+            }
+            let mtime = UNIX_EPOCH + Duration::from_secs(entry.header().mtime().map_err(io_error)?);
+
+            let content = match entry.header().entry_type() {
+                tar::EntryType::Directory => None,
+                tar::EntryType::Regular => {
+                    let mut content = Vec::new();
+                    entry.read_to_end(&mut content).map_err(io_error)?;
+                    Some(content)
+                }
+                _ => continue,
+            };
+
+            entries.insert(entry_path, (content, mtime));
+        }
+
+        Ok(TarArchive { path, entries })
+    }
+
+    /// List every file and directory, directories before their content.
+    pub fn list(&self) -> Vec<ArchiveEntry> {
+        self.entries
+            .iter()
+            .map(|(path, (content, mtime))| ArchiveEntry {
+                path: path.clone(),
+                is_dir: content.is_none(),
+                size: content.as_ref().map_or(0, |content| content.len() as u64),
+                mtime: *mtime,
+            })
+            .collect()
+    }
+
+    /// Get the content of the file at `path`, `None` if there's none.
+    pub fn read(&self, path: &Path) -> Option<&[u8]> {
+        match self.entries.get(path) {
+            Some((Some(content), _)) => Some(content),
+            _ => None,
+        }
+    }
+
+    /// Write a file at `path` with the given content and modification time, replacing any
+    /// existing one.
+    pub(crate) fn write(
+        &mut self,
+        path: &Path,
+        content: &mut dyn Read,
+        mtime: SystemTime,
+    ) -> Result<(), SyncError> {
+        let mut buffer = Vec::new();
+        content
+            .read_to_end(&mut buffer)
+            .map_err(|err| SyncError::io(&self.path.join(path), err))?;
+
+        self.entries
+            .insert(path.to_owned(), (Some(buffer), truncated(mtime)));
+        Ok(())
+    }
+
+    /// Create a directory at `path`.
+    pub(crate) fn create_dir(&mut self, path: &Path, mtime: SystemTime) {
+        self.entries
+            .insert(path.to_owned(), (None, truncated(mtime)));
+    }
+
+    /// Remove the file or directory at `path`, along with the content of a directory.
+    pub(crate) fn remove(&mut self, path: &Path) {
+        self.entries
+            .retain(|entry_path, _| !entry_path.starts_with(path));
+    }
+
+    /// Write the archive with the modifications made so far.
+    pub(crate) fn finish(&mut self) -> Result<(), SyncError> {
+        // the previous archive is only replaced once the new one is complete
+        let partial = crate::partial_path(&self.path);
+        let io_error = |err| SyncError::io(&partial, err);
+
+        let mut builder = tar::Builder::new(File::create(&partial).map_err(io_error)?);
+        for (path, (content, mtime)) in &self.entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_mtime(
+                mtime
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |since_epoch| since_epoch.as_secs()),
+            );
+
+            let data = match content {
+                Some(content) => {
+                    header.set_entry_type(tar::EntryType::Regular);
+                    header.set_mode(0o644);
+                    &content[..]
+                }
+                None => {
+                    header.set_entry_type(tar::EntryType::Directory);
+                    header.set_mode(0o755);
+                    &[][..]
+                }
+            };
+            header.set_size(data.len() as u64);
+
+            builder
+                .append_data(&mut header, path, data)
+                .map_err(io_error)?;
+        }
+        builder
+            .into_inner()
+            .and_then(|file| file.sync_all())
+            .map_err(io_error)?;
+
+        fs::rename(&partial, &self.path).map_err(|err| SyncError::io(&self.path, err))
+    }
+}
+
+/// Round `time` down to the second, like tar archives do.
+fn truncated(time: SystemTime) -> SystemTime {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(UNIX_EPOCH, |since_epoch| {
+            UNIX_EPOCH + Duration::from_secs(since_epoch.as_secs())
+        })
+}
@@ -0,0 +1,219 @@
+//! Chainable construction of `SyncOptions`, for callers only changing a few of them.
+
+use crate::compare::{CompareMode, TimeField};
+use crate::error::ErrorContext;
+use crate::hash::HashAlgorithm;
+use crate::options::{
+    ConflictStrategy, ContentFilter, DirInfo, Direction, ReadonlyPolicy, Rebase, ReplaceDecision,
+    SideOptions, SparseMode, SyncOptions, TypeMismatchPolicy,
+};
+use crate::report::{Decision, PlannedAction, Progress, SyncPlan};
+use crate::sanitize::SanitizePolicy;
+use crate::ErrorHandlingType;
+use glob::Pattern;
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Builder of `SyncOptions`, every option left unset keeps its default value.
+///
+/// ```no_run
+/// use keep_keeping_lib::{synchronize_with, ErrorHandlingType, Pattern, SyncOptions};
+/// use std::path::Path;
+///
+/// let options = SyncOptions::builder()
+///     .error_handler(|err| {
+///         eprintln!("{}", err);
+///         ErrorHandlingType::Skip
+///     })
+///     .dry_run(true)
+///     .exclude(Pattern::new("**/target").unwrap())
+///     .follow_symlinks(true)
+///     .build();
+///
+/// let report = synchronize_with(Path::new("a"), Path::new("b"), &options).unwrap();
+/// println!("{} files to copy", report.planned.len());
+/// ```
+#[derive(Default)]
+pub struct SyncOptionsBuilder {
+    options: SyncOptions,
+}
+
+/// Define methods setting options to the value they are given.
+macro_rules! setters {
+    ($($field:ident: $ty:ty),* $(,)?) => {
+        $(
+            #[doc = concat!("See `SyncOptions::", stringify!($field), "`.")]
+            pub fn $field(mut self, $field: $ty) -> Self {
+                self.options.$field = $field;
+                self
+            }
+        )*
+    };
+}
+
+impl SyncOptionsBuilder {
+    /// See `SyncOptions::error_handler`, only giving the error to `error_handler`.
+    ///
+    /// Kept for compatibility, `error_handler_with_context` also tells what the error is about.
+    pub fn error_handler<F>(mut self, error_handler: F) -> Self
+    where
+        F: Fn(&(dyn Error + 'static)) -> ErrorHandlingType + 'static,
+    {
+        self.options.error_handler = Box::new(move |context| error_handler(context.error));
+        self
+    }
+
+    /// See `SyncOptions::error_handler`.
+    pub fn error_handler_with_context<F>(mut self, error_handler: F) -> Self
+    where
+        F: Fn(&ErrorContext) -> ErrorHandlingType + 'static,
+    {
+        self.options.error_handler = Box::new(error_handler);
+        self
+    }
+
+    /// See `SyncOptions::on_progress`.
+    pub fn on_progress<F>(mut self, on_progress: F) -> Self
+    where
+        F: Fn(&Progress) + 'static,
+    {
+        self.options.on_progress = Some(Box::new(on_progress));
+        self
+    }
+
+    /// See `SyncOptions::on_action`.
+    pub fn on_action<F>(mut self, on_action: F) -> Self
+    where
+        F: Fn(&PlannedAction) + 'static,
+    {
+        self.options.on_action = Some(Box::new(on_action));
+        self
+    }
+
+    /// See `SyncOptions::on_plan`.
+    pub fn on_plan<F>(mut self, on_plan: F) -> Self
+    where
+        F: Fn(&SyncPlan) -> bool + 'static,
+    {
+        self.options.on_plan = Some(Box::new(on_plan));
+        self
+    }
+
+    /// See `SyncOptions::on_decision`.
+    pub fn on_decision<F>(mut self, on_decision: F) -> Self
+    where
+        F: Fn(&Decision) + 'static,
+    {
+        self.options.on_decision = Some(Box::new(on_decision));
+        self
+    }
+
+    /// See `SyncOptions::should_replace`.
+    pub fn should_replace<F>(mut self, should_replace: F) -> Self
+    where
+        F: Fn(&DirInfo, &DirInfo) -> ReplaceDecision + 'static,
+    {
+        self.options.should_replace = Some(Box::new(should_replace));
+        self
+    }
+
+    /// Add a path to `SyncOptions::exclude_paths`.
+    pub fn exclude_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.exclude_paths.push(path.into());
+        self
+    }
+
+    /// Add a pattern to `SyncOptions::exclude`.
+    pub fn exclude(mut self, pattern: Pattern) -> Self {
+        self.options.exclude.push(pattern);
+        self
+    }
+
+    /// Add a pattern to `SyncOptions::include`.
+    pub fn include(mut self, pattern: Pattern) -> Self {
+        self.options.include.push(pattern);
+        self
+    }
+
+    setters! {
+        precount: bool,
+        cancel: Option<Arc<AtomicBool>>,
+        deadline: Option<Instant>,
+        skip_hidden: bool,
+        direction: Direction,
+        dry_run: bool,
+        propagate_deletions: bool,
+        initialize: bool,
+        rsync_trailing_slash: bool,
+        create_missing_target: bool,
+        merge_root_bundle: bool,
+        rebase: Option<Rebase>,
+        flatten: bool,
+        preserve_dir_timestamps: bool,
+        follow_symlinks: bool,
+        case_insensitive: bool,
+        max_depth: Option<usize>,
+        max_file_size: Option<u64>,
+        regular_files_only: bool,
+        metadata_only: bool,
+        content_filter: Option<ContentFilter>,
+        low_memory: bool,
+        parallel: bool,
+        jobs: Option<usize>,
+        compare: CompareMode,
+        conflict_strategy: ConflictStrategy,
+        verify: bool,
+        compare_time_field: TimeField,
+        time_tolerance: Duration,
+        type_mismatch: TypeMismatchPolicy,
+        readonly_dest: ReadonlyPolicy,
+        side1: SideOptions,
+        side2: SideOptions,
+        hash_algorithm: Option<Arc<dyn HashAlgorithm>>,
+        checksum_xattrs: bool,
+        preserve_xattrs: bool,
+        preserve_hardlinks: bool,
+        atomic: bool,
+        sparse: SparseMode,
+        copy_buffer_size: usize,
+        rate_limit: Option<u64>,
+        sanitize: Option<SanitizePolicy>,
+    }
+
+    /// Get the options built.
+    pub fn build(self) -> SyncOptions {
+        self.options
+    }
+}
+
+impl SyncOptions {
+    /// Start building options from the default ones.
+    pub fn builder() -> SyncOptionsBuilder {
+        SyncOptionsBuilder::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SyncOptions;
+
+    #[test]
+    fn builder() {
+        let options = SyncOptions::builder()
+            .dry_run(true)
+            .exclude(glob::Pattern::new("*.tmp").unwrap())
+            .exclude(glob::Pattern::new("target").unwrap())
+            .max_depth(Some(2))
+            .build();
+
+        assert!(options.dry_run);
+        assert_eq!(options.exclude.len(), 2);
+        assert_eq!(options.max_depth, Some(2));
+        // left as default
+        assert!(options.atomic);
+        assert!(!options.follow_symlinks);
+    }
+}
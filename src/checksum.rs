@@ -0,0 +1,60 @@
+//! Content checksums stored in extended attributes to detect bit rot.
+
+use crate::hash::HashAlgorithm;
+use std::io;
+use std::path::Path;
+
+/// Name of the extended attribute storing checksums computed with `algorithm`.
+#[cfg_attr(not(all(unix, feature = "xattr")), allow(dead_code))]
+fn attribute_name(algorithm: &dyn HashAlgorithm) -> String {
+    format!("user.keepkeeping.{}", algorithm.name())
+}
+
+/// Whether the error only means extended attributes can't be used on this file system.
+#[cfg(all(unix, feature = "xattr"))]
+pub(crate) fn is_unsupported(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::Unsupported || err.raw_os_error() == Some(95) // EOPNOTSUPP
+}
+
+/// Store the checksum of `source`'s content on `target`.
+///
+/// Does nothing where extended attributes are not supported.
+#[cfg(all(unix, feature = "xattr"))]
+pub(crate) fn store(source: &Path, target: &Path, algorithm: &dyn HashAlgorithm) -> io::Result<()> {
+    let checksum = crate::hash::hash_file(source, algorithm)?;
+
+    match xattr::set(target, attribute_name(algorithm), &checksum) {
+        Err(err) if is_unsupported(&err) => Ok(()),
+        result => result,
+    }
+}
+
+/// Check the content of `path` against its stored checksum.
+///
+/// Returns `None` when no checksum is stored (or extended attributes are not supported),
+/// `Some(false)` when the content changed without its modification time being updated.
+#[cfg(all(unix, feature = "xattr"))]
+pub(crate) fn verify(path: &Path, algorithm: &dyn HashAlgorithm) -> io::Result<Option<bool>> {
+    let stored = match xattr::get(path, attribute_name(algorithm)) {
+        Ok(Some(stored)) => stored,
+        Ok(None) => return Ok(None),
+        Err(err) if is_unsupported(&err) => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    Ok(Some(crate::hash::hash_file(path, algorithm)? == stored))
+}
+
+#[cfg(not(all(unix, feature = "xattr")))]
+pub(crate) fn store(
+    _source: &Path,
+    _target: &Path,
+    _algorithm: &dyn HashAlgorithm,
+) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(all(unix, feature = "xattr")))]
+pub(crate) fn verify(_path: &Path, _algorithm: &dyn HashAlgorithm) -> io::Result<Option<bool>> {
+    Ok(None)
+}
@@ -0,0 +1,266 @@
+//! Comparison of 2 files without modifying them.
+
+use crate::SyncError;
+use filetime::FileTime;
+use std::cmp::Ordering;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::Duration;
+
+/// Size of the buffers used when comparing file contents.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// How files are compared.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompareMode {
+    /// Only compare modification times, files with the same time but different sizes still
+    /// differ.
+    #[default]
+    MTime,
+    /// Also compare contents: files with the same content are equal whatever their
+    /// modification times.
+    Content,
+}
+
+/// Timestamp deciding which file is the newest.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimeField {
+    /// Last modification of the content.
+    #[default]
+    Mtime,
+    /// Last change of the content or metadata (Unix only).
+    ///
+    /// Only allowed when pushing or mirroring: synchronizing a file changes it.
+    Ctime,
+    /// Creation.
+    ///
+    /// Only allowed when pushing or mirroring: a copied file is created anew.
+    Birthtime,
+}
+
+/// Get the `field` timestamp, `None` if the platform or file system doesn't provide it.
+pub(crate) fn file_time(metadata: &fs::Metadata, field: TimeField) -> Option<FileTime> {
+    match field {
+        TimeField::Mtime => Some(FileTime::from_last_modification_time(metadata)),
+        TimeField::Ctime => change_time(metadata),
+        TimeField::Birthtime => FileTime::from_creation_time(metadata),
+    }
+}
+
+#[cfg(unix)]
+fn change_time(metadata: &fs::Metadata) -> Option<FileTime> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some(FileTime::from_unix_time(
+        metadata.ctime(),
+        metadata.ctime_nsec() as u32,
+    ))
+}
+
+#[cfg(not(unix))]
+fn change_time(_metadata: &fs::Metadata) -> Option<FileTime> {
+    None
+}
+
+/// Result of comparing 2 files, see `compare_files`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    /// Both files are already synchronized.
+    Equal,
+    /// The first file is the most recent one.
+    FirstNewer,
+    /// The second file is the most recent one.
+    SecondNewer,
+    /// Both files have the same modification time but different contents: told apart by their
+    /// sizes with `CompareMode::MTime`, by their contents with `CompareMode::Content`.
+    DiffersButEqualMtime,
+}
+
+/// Compare 2 files the same way a synchronization does, without modifying anything.
+pub fn compare_files(
+    path1: &Path,
+    path2: &Path,
+    mode: CompareMode,
+) -> Result<Comparison, SyncError> {
+    let metadata = |path| fs::metadata(path).map_err(|err| SyncError::io(path, err));
+
+    compare(
+        path1,
+        &metadata(path1)?,
+        path2,
+        &metadata(path2)?,
+        mode,
+        TimeField::Mtime,
+        Duration::ZERO,
+    )
+}
+
+/// Compare 2 files whose metadata has already been read.
+///
+/// Modification times are compared instead of `field` when it's not available, times at most
+/// `tolerance` apart are equal.
+pub(crate) fn compare(
+    path1: &Path,
+    metadata1: &fs::Metadata,
+    path2: &Path,
+    metadata2: &fs::Metadata,
+    mode: CompareMode,
+    field: TimeField,
+    tolerance: Duration,
+) -> Result<Comparison, SyncError> {
+    let (time1, time2) = match (file_time(metadata1, field), file_time(metadata2, field)) {
+        (Some(time1), Some(time2)) => (time1, time2),
+        _ => (
+            FileTime::from_last_modification_time(metadata1),
+            FileTime::from_last_modification_time(metadata2),
+        ),
+    };
+
+    let by_time = match time1.cmp(&time2) {
+        _ if nanoseconds_between(time1, time2) <= tolerance.as_nanos() => Comparison::Equal,
+        Ordering::Greater => Comparison::FirstNewer,
+        Ordering::Less => Comparison::SecondNewer,
+        Ordering::Equal => Comparison::Equal,
+    };
+
+    match mode {
+        // a truncated copy keeps the time it was given
+        CompareMode::MTime
+            if by_time == Comparison::Equal && metadata1.len() != metadata2.len() =>
+        {
+            Ok(Comparison::DiffersButEqualMtime)
+        }
+        CompareMode::MTime => Ok(by_time),
+        CompareMode::Content => {
+            let same_content = metadata1.len() == metadata2.len() && same_content(path1, path2)?;
+
+            Ok(match (by_time, same_content) {
+                (_, true) => Comparison::Equal,
+                (Comparison::Equal, false) => Comparison::DiffersButEqualMtime,
+                (by_time, false) => by_time,
+            })
+        }
+    }
+}
+
+/// Get how far apart 2 times are.
+fn nanoseconds_between(time1: FileTime, time2: FileTime) -> u128 {
+    let nanoseconds =
+        |time: FileTime| time.unix_seconds() as i128 * 1_000_000_000 + time.nanoseconds() as i128;
+
+    (nanoseconds(time1) - nanoseconds(time2)).unsigned_abs()
+}
+
+/// Compare the contents of 2 files chunk by chunk.
+pub(crate) fn same_content(path1: &Path, path2: &Path) -> Result<bool, SyncError> {
+    let error1 = |err| SyncError::io(path1, err);
+    let error2 = |err| SyncError::io(path2, err);
+
+    let mut file1 = File::open(path1).map_err(error1)?;
+    let mut file2 = File::open(path2).map_err(error2)?;
+    let mut buffer1 = vec![0; BUFFER_SIZE];
+    let mut buffer2 = vec![0; BUFFER_SIZE];
+
+    loop {
+        let read1 = read_full(&mut file1, &mut buffer1).map_err(error1)?;
+        let read2 = read_full(&mut file2, &mut buffer2).map_err(error2)?;
+
+        if buffer1[..read1] != buffer2[..read2] {
+            return Ok(false);
+        } else if read1 == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Fill `buffer` as much as possible, returning how many bytes were read.
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare, compare_files, CompareMode, Comparison, TimeField};
+    use std::time::Duration;
+
+    fn write_with_mtime(path: &std::path::Path, content: &str, unix_time: i64) {
+        std::fs::write(path, content).unwrap();
+        filetime::set_file_mtime(path, filetime::FileTime::from_unix_time(unix_time, 0)).unwrap();
+    }
+
+    #[test]
+    fn compare_modes() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        let c = dir.path().join("c");
+
+        write_with_mtime(&a, "same", 1_000);
+        write_with_mtime(&b, "same", 2_000);
+        write_with_mtime(&c, "diff", 1_000);
+
+        let compare = |path1, path2, mode| compare_files(path1, path2, mode).unwrap();
+
+        assert_eq!(compare(&a, &b, CompareMode::MTime), Comparison::SecondNewer);
+        assert_eq!(compare(&b, &a, CompareMode::MTime), Comparison::FirstNewer);
+        assert_eq!(compare(&a, &b, CompareMode::Content), Comparison::Equal);
+        assert_eq!(compare(&a, &c, CompareMode::MTime), Comparison::Equal);
+
+        // different sizes can't be the same content
+        write_with_mtime(&c, "truncated", 1_000);
+        assert_eq!(
+            compare(&a, &c, CompareMode::MTime),
+            Comparison::DiffersButEqualMtime
+        );
+        assert_eq!(
+            compare(&a, &c, CompareMode::Content),
+            Comparison::DiffersButEqualMtime
+        );
+    }
+
+    #[test]
+    fn time_tolerance() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a");
+        let b = dir.path().join("b");
+        let tolerance = Duration::from_secs(2);
+
+        std::fs::write(&a, "a").unwrap();
+        std::fs::write(&b, "b").unwrap();
+        filetime::set_file_mtime(&a, filetime::FileTime::from_unix_time(1_000, 0)).unwrap();
+
+        let compare_with_b_at = |seconds, nanoseconds| {
+            let time = filetime::FileTime::from_unix_time(seconds, nanoseconds);
+            filetime::set_file_mtime(&b, time).unwrap();
+
+            let (metadata_a, metadata_b) = (a.metadata().unwrap(), b.metadata().unwrap());
+            compare(
+                &a,
+                &metadata_a,
+                &b,
+                &metadata_b,
+                CompareMode::MTime,
+                TimeField::Mtime,
+                tolerance,
+            )
+            .unwrap()
+        };
+
+        assert_eq!(compare_with_b_at(1_002, 0), Comparison::Equal);
+        assert_eq!(compare_with_b_at(998, 0), Comparison::Equal);
+        assert_eq!(compare_with_b_at(1_002, 1), Comparison::SecondNewer);
+        assert_eq!(compare_with_b_at(997, 999_999_999), Comparison::FirstNewer);
+    }
+}
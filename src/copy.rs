@@ -0,0 +1,214 @@
+//! Copy of file contents.
+
+use crate::options::SparseMode;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// Copy files chunk by chunk, reporting their progress, instead of letting the OS copy them at
+/// once.
+pub(crate) struct ChunkedCopy<'a> {
+    pub(crate) buffer_size: usize,
+    /// Called with the number of bytes copied so far after each chunk, an error stops the copy.
+    pub(crate) on_chunk: &'a dyn Fn(u64) -> io::Result<()>,
+}
+
+/// Copy the extended attributes of a file, see `SyncOptions::preserve_xattrs`.
+///
+/// Does nothing where extended attributes are not supported.
+#[cfg(all(unix, feature = "xattr"))]
+pub(crate) fn copy_xattrs(from: &Path, to: &Path) -> io::Result<()> {
+    let names = match xattr::list(from) {
+        Ok(names) => names,
+        Err(err) if crate::checksum::is_unsupported(&err) => return Ok(()),
+        Err(err) => return Err(err),
+    };
+
+    for name in names {
+        if let Some(value) = xattr::get(from, &name)? {
+            match xattr::set(to, &name, &value) {
+                Err(err) if crate::checksum::is_unsupported(&err) => return Ok(()),
+                result => result?,
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(all(unix, feature = "xattr")))]
+pub(crate) fn copy_xattrs(_from: &Path, _to: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Copy the content and permissions of a file like `fs::copy`, preserving holes of sparse files
+/// depending on `sparse`.
+pub(crate) fn copy_file(
+    from: &Path,
+    to: &Path,
+    sparse: SparseMode,
+    chunked: Option<&ChunkedCopy>,
+) -> io::Result<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(copied) = sparse::copy_if_sparse(from, to, sparse)? {
+            return Ok(copied);
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = sparse;
+    }
+
+    match chunked {
+        Some(chunked) => copy_file_with_progress(from, to, chunked),
+        None => fs::copy(from, to),
+    }
+}
+
+/// Copy the content and permissions of a file `chunked.buffer_size` bytes at a time.
+fn copy_file_with_progress(from: &Path, to: &Path, chunked: &ChunkedCopy) -> io::Result<u64> {
+    let mut source = File::open(from)?;
+    let permissions = source.metadata()?.permissions();
+    let mut target = File::create(to)?;
+    let mut buffer = vec![0; chunked.buffer_size.max(1)];
+    let mut copied = 0;
+
+    loop {
+        let read = match source.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+
+        target.write_all(&buffer[..read])?;
+        copied += read as u64;
+        (chunked.on_chunk)(copied)?;
+    }
+
+    target.flush()?;
+    fs::set_permissions(to, permissions)?;
+
+    Ok(copied)
+}
+
+#[cfg(target_os = "linux")]
+mod sparse {
+    use crate::options::SparseMode;
+    use rustix::fs::SeekFrom;
+    use std::fs::{self, File};
+    use std::io::{self, Read, Seek, Write};
+    use std::os::unix::fs::MetadataExt;
+    use std::path::Path;
+
+    /// Copy `from` extent by extent if it should be handled as a sparse file.
+    ///
+    /// Returns `None` when the file should be copied normally instead, including when the file
+    /// system doesn't support looking for holes.
+    pub(super) fn copy_if_sparse(
+        from: &Path,
+        to: &Path,
+        sparse: SparseMode,
+    ) -> io::Result<Option<u64>> {
+        let mut source = File::open(from)?;
+        let metadata = source.metadata()?;
+
+        let is_sparse = match sparse {
+            SparseMode::Never => return Ok(None),
+            SparseMode::Always => true,
+            // `blocks` is always counted in 512-byte units
+            SparseMode::Auto => metadata.blocks() * 512 < metadata.len(),
+        };
+
+        if !is_sparse || !metadata.is_file() {
+            return Ok(None);
+        }
+
+        let len = metadata.len();
+        let extents = match data_extents(&source, len) {
+            Ok(extents) => extents,
+            Err(err) if err.raw_os_error() == Some(rustix::io::Errno::INVAL.raw_os_error()) => {
+                // SEEK_DATA/SEEK_HOLE not supported by this file system
+                return Ok(None);
+            }
+            Err(err) => return Err(err),
+        };
+
+        // Truncating then extending the target leaves it entirely made of holes, only the data
+        // extents are written over them.
+        let mut target = File::create(to)?;
+        target.set_len(len)?;
+
+        for (start, end) in extents {
+            source.seek(io::SeekFrom::Start(start))?;
+            target.seek(io::SeekFrom::Start(start))?;
+            io::copy(&mut (&mut source).take(end - start), &mut target)?;
+        }
+
+        target.flush()?;
+        fs::set_permissions(to, metadata.permissions())?;
+
+        Ok(Some(len))
+    }
+
+    /// List the `(start, end)` ranges of `file` actually containing data.
+    fn data_extents(file: &File, len: u64) -> io::Result<Vec<(u64, u64)>> {
+        let mut extents = Vec::new();
+        let mut offset = 0;
+
+        while offset < len {
+            let start = match rustix::fs::seek(file, SeekFrom::Data(offset)) {
+                Ok(start) => start,
+                // only holes left
+                Err(rustix::io::Errno::NXIO) => break,
+                Err(err) => return Err(err.into()),
+            };
+            let end = rustix::fs::seek(file, SeekFrom::Hole(start))?;
+
+            extents.push((start, end));
+            offset = end;
+        }
+
+        Ok(extents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn copy_sparse_file() {
+        use std::io::{Seek, Write};
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        let target = dir.path().join("target");
+
+        let len = 16 * 1024 * 1024;
+        let mut file = std::fs::File::create(&source).unwrap();
+        file.set_len(len).unwrap();
+        file.seek(std::io::SeekFrom::Start(len / 2)).unwrap();
+        file.write_all(b"data in the middle").unwrap();
+        drop(file);
+
+        let source_blocks = std::fs::metadata(&source).unwrap().blocks();
+        if source_blocks * 512 >= len {
+            // Sparse files not supported by the file system
+            return;
+        }
+
+        let copied = super::copy_file(&source, &target, super::SparseMode::Always, None).unwrap();
+        let target_metadata = std::fs::metadata(&target).unwrap();
+
+        assert_eq!(copied, len);
+        assert_eq!(target_metadata.len(), len);
+        assert!(target_metadata.blocks() * 512 < len);
+        assert_eq!(
+            std::fs::read(&source).unwrap(),
+            std::fs::read(&target).unwrap()
+        );
+    }
+}
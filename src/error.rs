@@ -0,0 +1,185 @@
+use crate::report::SyncStats;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Errors of a synchronization, given to `SyncOptions::error_handler` and returned when it
+/// decides to stop.
+#[derive(Debug)]
+pub enum SyncError {
+    /// An I/O operation on `path` failed.
+    Io { path: PathBuf, source: io::Error },
+    /// One of the synchronized paths doesn't exist, nothing is synchronized (see
+    /// `SyncOptions::create_missing_target`).
+    NotFound(PathBuf),
+    /// A directory couldn't be walked, the error tells which entry.
+    WalkDir(walkdir::Error),
+    /// The synchronization was stopped without any error being handled, e.g. cancelled (see
+    /// `SyncOptions::cancel`).
+    Aborted,
+    /// `SyncOptions::on_plan` rejected the plan of the synchronization, nothing has been
+    /// modified.
+    Rejected,
+    /// The synchronization was stopped once `SyncOptions::deadline` was reached, `stats`
+    /// counting the modifications made until then.
+    TimedOut { stats: SyncStats },
+    /// Attempted to modify a path on a side marked as read-only, this is a logic error.
+    ReadOnlySide(PathBuf),
+    /// An entry has been replaced by an entry of another type (e.g. a file by a directory)
+    /// while being synchronized, it's skipped and will be handled by the next synchronization.
+    TypeChangedDuringSync(PathBuf),
+    /// A name can't be stored on the second path according to `SyncOptions::sanitize`, the
+    /// entry is skipped.
+    IllegalDestinationName(PathBuf),
+    /// The given `SyncOptions` can't be used together.
+    InvalidOptions(&'static str),
+    /// The synchronized paths are the same or one of them contains the other, nothing is
+    /// synchronized.
+    OverlappingPaths(PathBuf, PathBuf),
+    /// `path` doesn't match the entry it was synchronized with once the synchronization is over
+    /// (missing, different size or contents), see `SyncOptions::verify`.
+    VerificationFailed { path: PathBuf },
+    /// The ignore file at `path` (see `IGNORE_FILE_NAME`) can't be read or holds an invalid
+    /// pattern.
+    IgnoreFile {
+        path: PathBuf,
+        source: ignore::Error,
+    },
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SyncError::Io { path, source } => write!(f, "'{}': {}", path.display(), source),
+            SyncError::NotFound(path) => write!(f, "'{}' does not exist", path.display()),
+            SyncError::WalkDir(err) => err.fmt(f),
+            SyncError::Aborted => write!(f, "Synchronization aborted"),
+            SyncError::Rejected => write!(f, "Synchronization plan rejected"),
+            SyncError::TimedOut { .. } => write!(f, "Synchronization timed out"),
+            SyncError::ReadOnlySide(path) => write!(
+                f,
+                "Refusing to modify '{}' which is on a read-only side",
+                path.display()
+            ),
+            SyncError::TypeChangedDuringSync(path) => write!(
+                f,
+                "'{}' changed type while being synchronized",
+                path.display()
+            ),
+            SyncError::IllegalDestinationName(path) => write!(
+                f,
+                "'{}' can't be stored on the destination file system",
+                path.display()
+            ),
+            SyncError::InvalidOptions(reason) => write!(f, "Invalid options: {}", reason),
+            SyncError::OverlappingPaths(path1, path2) => write!(
+                f,
+                "'{}' and '{}' are the same path or one of them contains the other",
+                path1.display(),
+                path2.display()
+            ),
+            SyncError::VerificationFailed { path } => write!(
+                f,
+                "'{}' doesn't match the entry it was synchronized with",
+                path.display()
+            ),
+            SyncError::IgnoreFile { path, source } => {
+                write!(f, "Invalid ignore file '{}': {}", path.display(), source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SyncError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SyncError::Io { source, .. } => Some(source),
+            SyncError::WalkDir(err) => Some(err),
+            SyncError::IgnoreFile { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl SyncError {
+    /// Path the error is about, if any.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            SyncError::Io { path, .. }
+            | SyncError::NotFound(path)
+            | SyncError::ReadOnlySide(path)
+            | SyncError::TypeChangedDuringSync(path)
+            | SyncError::IllegalDestinationName(path)
+            | SyncError::OverlappingPaths(path, _)
+            | SyncError::VerificationFailed { path }
+            | SyncError::IgnoreFile { path, .. } => Some(path),
+            SyncError::WalkDir(err) => err.path(),
+            SyncError::Aborted
+            | SyncError::Rejected
+            | SyncError::TimedOut { .. }
+            | SyncError::InvalidOptions(_) => None,
+        }
+    }
+
+    /// Make an error from an I/O error which happened on `path`.
+    pub(crate) fn io(path: &Path, source: io::Error) -> Self {
+        SyncError::Io {
+            path: path.to_owned(),
+            source,
+        }
+    }
+}
+
+/// What the synchronization was doing when an error happened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    /// Copying a file or a symbolic link.
+    Copy,
+    /// Deleting a file or a directory.
+    Delete,
+    /// Creating a directory.
+    CreateDir,
+    /// Listing the entries of a directory.
+    Walk,
+    /// Moving an entry, e.g. a staged replacement over the entry it replaces.
+    Rename,
+    /// Setting the modification time of an entry.
+    SetTimes,
+    /// Setting the permissions of an entry.
+    SetPermissions,
+    /// Anything else, e.g. reading metadata or checking options.
+    Other,
+}
+
+/// An error along with what it's about, given to `SyncOptions::error_handler`.
+#[derive(Debug)]
+pub struct ErrorContext<'a> {
+    pub error: &'a SyncError,
+    pub operation: Operation,
+    /// Path of the entry the error is about, relative to the synchronized path it's part of
+    /// (`None` if it isn't about an entry).
+    pub relative_path: Option<&'a Path>,
+}
+
+impl<'a> ErrorContext<'a> {
+    /// Give `error` as is, without telling what it's about.
+    pub(crate) fn without_context(error: &'a SyncError) -> Self {
+        ErrorContext {
+            error,
+            operation: Operation::Other,
+            relative_path: None,
+        }
+    }
+}
+
+impl fmt::Display for ErrorContext<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl From<walkdir::Error> for SyncError {
+    fn from(err: walkdir::Error) -> Self {
+        SyncError::WalkDir(err)
+    }
+}
@@ -0,0 +1,183 @@
+//! Pluggable content hashing.
+//!
+//! Features comparing file contents never hardcode an algorithm, they take an
+//! `Arc<dyn HashAlgorithm>` instead so that checksums can match the ones produced by other tools.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Size of the buffer used when hashing a file.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Incremental hash state.
+pub trait Hasher {
+    /// Feed some bytes to the hash.
+    fn update(&mut self, bytes: &[u8]);
+
+    /// Consume the state and return the digest.
+    fn finalize(self: Box<Self>) -> Vec<u8>;
+}
+
+/// Hash algorithm, creates a new `Hasher` for every hashed content.
+pub trait HashAlgorithm: Send + Sync {
+    /// Short lowercase name of the algorithm (e.g. `"sha256"`).
+    fn name(&self) -> &str;
+
+    /// Create a fresh hash state.
+    fn hasher(&self) -> Box<dyn Hasher>;
+}
+
+/// The BLAKE3 hash algorithm, fast and used by default.
+#[cfg(feature = "blake3")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Blake3;
+
+#[cfg(feature = "blake3")]
+impl Hasher for blake3::Hasher {
+    fn update(&mut self, bytes: &[u8]) {
+        blake3::Hasher::update(self, bytes);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        blake3::Hasher::finalize(&self).as_bytes().to_vec()
+    }
+}
+
+#[cfg(feature = "blake3")]
+impl HashAlgorithm for Blake3 {
+    fn name(&self) -> &str {
+        "blake3"
+    }
+
+    fn hasher(&self) -> Box<dyn Hasher> {
+        Box::new(blake3::Hasher::new())
+    }
+}
+
+/// The SHA-256 hash algorithm, for compatibility with existing checksums.
+#[cfg(feature = "sha256")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sha256;
+
+#[cfg(feature = "sha256")]
+impl Hasher for sha2::Sha256 {
+    fn update(&mut self, bytes: &[u8]) {
+        sha2::Digest::update(self, bytes);
+    }
+
+    fn finalize(self: Box<Self>) -> Vec<u8> {
+        sha2::Digest::finalize(*self).to_vec()
+    }
+}
+
+#[cfg(feature = "sha256")]
+impl HashAlgorithm for Sha256 {
+    fn name(&self) -> &str {
+        "sha256"
+    }
+
+    fn hasher(&self) -> Box<dyn Hasher> {
+        Box::new(<sha2::Sha256 as sha2::Digest>::new())
+    }
+}
+
+/// Get the algorithm used when none is specified: BLAKE3 if enabled, else SHA-256 if enabled.
+pub fn default_algorithm() -> Option<Arc<dyn HashAlgorithm>> {
+    #[cfg(feature = "blake3")]
+    {
+        Some(Arc::new(Blake3))
+    }
+
+    #[cfg(all(not(feature = "blake3"), feature = "sha256"))]
+    {
+        Some(Arc::new(Sha256))
+    }
+
+    #[cfg(not(any(feature = "blake3", feature = "sha256")))]
+    {
+        None
+    }
+}
+
+/// Hash the whole content of a reader.
+pub fn hash_reader<R: Read>(mut reader: R, algorithm: &dyn HashAlgorithm) -> io::Result<Vec<u8>> {
+    let mut hasher = algorithm.hasher();
+    let mut buffer = vec![0; BUFFER_SIZE];
+
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => return Ok(hasher.finalize()),
+            Ok(read) => hasher.update(&buffer[..read]),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Hash the content of a file without loading it entirely in memory.
+pub fn hash_file(path: &Path, algorithm: &dyn HashAlgorithm) -> io::Result<Vec<u8>> {
+    hash_reader(File::open(path)?, algorithm)
+}
+
+#[cfg(test)]
+mod tests {
+    #[allow(dead_code)]
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[cfg(feature = "blake3")]
+    #[test]
+    fn blake3_abc() {
+        let digest = super::hash_reader(&b"abc"[..], &super::Blake3).unwrap();
+
+        assert_eq!(
+            hex(&digest),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        )
+    }
+
+    #[cfg(feature = "sha256")]
+    #[test]
+    fn sha256_abc() {
+        let digest = super::hash_reader(&b"abc"[..], &super::Sha256).unwrap();
+
+        assert_eq!(
+            hex(&digest),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        )
+    }
+
+    #[test]
+    fn custom_hasher() {
+        struct Sum(u8);
+
+        impl super::Hasher for Sum {
+            fn update(&mut self, bytes: &[u8]) {
+                self.0 = bytes.iter().fold(self.0, |sum, b| sum.wrapping_add(*b));
+            }
+
+            fn finalize(self: Box<Self>) -> Vec<u8> {
+                vec![self.0]
+            }
+        }
+
+        struct SumAlgorithm;
+
+        impl super::HashAlgorithm for SumAlgorithm {
+            fn name(&self) -> &str {
+                "sum"
+            }
+
+            fn hasher(&self) -> Box<dyn super::Hasher> {
+                Box::new(Sum(0))
+            }
+        }
+
+        let digest = super::hash_reader(&[1u8, 2, 3][..], &SumAlgorithm).unwrap();
+
+        assert_eq!(digest, vec![6])
+    }
+}
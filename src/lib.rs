@@ -1,11 +1,54 @@
 #![forbid(unsafe_code)]
 
 use filetime::FileTime;
-use std::cell::RefCell;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use walkdir::{DirEntry, WalkDir};
 
+#[cfg(feature = "tar")]
+pub mod archive;
+mod builder;
+mod checksum;
+mod compare;
+mod copy;
+mod error;
+pub mod hash;
+mod manifest;
+mod options;
+pub mod pathutil;
+mod rate_limit;
+mod report;
+mod sanitize;
+
+#[cfg(feature = "tar")]
+use archive::{ArchiveEntry, TarArchive};
+pub use builder::SyncOptionsBuilder;
+pub use compare::{compare_files, CompareMode, Comparison, TimeField};
+pub use error::{ErrorContext, Operation, SyncError};
+pub use glob::Pattern;
+use manifest::{Manifest, MANIFEST_NAME};
+pub use options::{
+    ConflictStrategy, ContentFilter, ContentPredicate, DirInfo, Direction, ErrorHandler, OnAction,
+    OnDecision, OnPlan, OnProgress, ReadonlyPolicy, Rebase, ReplaceDecision, ShouldReplace,
+    SideOptions, SparseMode, SyncOptions, TypeMismatchPolicy,
+};
+use pathutil::{is_mac_app, is_part_of_mac_app, path_has_extension, trim_base_path};
+use rate_limit::RateLimiter;
+use report::SkipReason;
+pub use report::{
+    Decision, DiffEntry, DiffStatus, FilterStats, PlannedAction, Progress, SyncPlan, SyncReport,
+    SyncStats,
+};
+pub use sanitize::{SanitizeAction, SanitizePolicy};
+
 /// Precise how should an error be handled.
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum ErrorHandlingType {
@@ -24,565 +67,7072 @@ macro_rules! unused {
     };
 }
 
-/// Remove the a base path from another path, making it relative the the base path.
-fn trim_base_path(base_path: &str, entry_path: &str) -> Option<PathBuf> {
-    let mut base_bytes = base_path.bytes();
-    let entry_bytes = entry_path.bytes();
+fn has_trailing_separator(path: &Path) -> bool {
+    path.as_os_str()
+        .to_string_lossy()
+        .ends_with(std::path::is_separator)
+}
 
-    let trimmed_path_bytes: Vec<_> = entry_bytes
-        .skip_while(|b| Some(*b) == base_bytes.next())
-        .skip(1)
-        .collect();
+/// Synchronize 2 paths with the default options, errors being given to `on_err`.
+///
+/// Kept for compatibility, `synchronize_with` also tells which error stopped the
+/// synchronization.
+#[allow(clippy::result_unit_err)]
+pub fn synchronize<FErr>(path1: &Path, path2: &Path, on_err: FErr) -> Result<(), ()>
+where
+    FErr: Fn(&dyn std::error::Error) -> ErrorHandlingType,
+{
+    synchronize_paths(path1, path2, &SyncOptions::default(), &|context| {
+        on_err(context.error)
+    })
+    .map(|_| ())
+    .map_err(|_| ())
+}
+
+/// Synchronize 2 paths, configuring the synchronization with `options`.
+///
+/// Errors are given to `SyncOptions::error_handler`, the one it decides to stop the
+/// synchronization for is returned.
+pub fn synchronize_with(
+    path1: &Path,
+    path2: &Path,
+    options: &SyncOptions,
+) -> Result<SyncReport, SyncError> {
+    synchronize_paths(path1, path2, options, &*options.error_handler)
+}
+
+/// Tell how 2 paths differ without modifying anything, entries being filtered and compared
+/// like when synchronizing them with `options`.
+///
+/// Statuses describe the entries as they are, not what a synchronization would do with them
+/// (see `SyncOptions::dry_run` for that). Directories on both sides aren't listed, their
+/// content is. Errors are given to `SyncOptions::error_handler`, skipped entries are left out.
+pub fn diff(
+    path1: &Path,
+    path2: &Path,
+    options: &SyncOptions,
+) -> Result<Vec<DiffEntry>, SyncError> {
+    let on_err = &*options.error_handler;
+
+    if let Some(missing) = [path1, path2].iter().find(|path| !path.exists()) {
+        let err = SyncError::NotFound(missing.to_path_buf());
+        on_err(&ErrorContext::without_context(&err));
+        return Err(err);
+    }
+
+    let ctx = &Context::new((path1, path2), options, on_err, None)?;
+
+    diff_paths(path1, path2, ctx).map_err(|()| ctx.failure())
+}
+
+/// Export the content of the directory `source` into the tar archive `target`, configuring the
+/// export with `options`.
+///
+/// Unlike a synchronization, it only goes one way: missing files and files whose size or
+/// modification time differs are written, entries missing from `source` are removed from
+/// `target` only with `Direction::Mirror`. Nothing is ever read back from `target`, which is
+/// written once exported. Errors are given to `SyncOptions::error_handler`.
+///
+/// Only the options choosing the walked entries (e.g. exclusions, `max_depth`,
+/// `follow_symlinks`, `max_file_size`, `content_filter`), `time_tolerance`, `dry_run`,
+/// `on_decision`, `on_action`, `cancel` and `deadline` apply, the others (e.g. `compare`,
+/// `atomic`, `parallel`, `propagate_deletions`, `verify`) are ignored. Symbolic links which
+/// aren't followed and special files are never written.
+#[cfg(feature = "tar")]
+pub fn synchronize_into(
+    source: &Path,
+    target: &mut TarArchive,
+    options: &SyncOptions,
+) -> Result<SyncReport, SyncError> {
+    let on_err = &*options.error_handler;
 
-    if base_bytes.next().is_none() {
-        if let Ok(trimmed_path) = String::from_utf8(trimmed_path_bytes) {
-            Some(PathBuf::from(trimmed_path))
+    if !source.is_dir() {
+        let err = if source.exists() {
+            SyncError::InvalidOptions("Only directories can be exported into an archive")
         } else {
-            None
-        }
-    } else {
-        None
+            SyncError::NotFound(source.to_owned())
+        };
+        on_err(&ErrorContext::without_context(&err));
+        return Err(err);
     }
+
+    // the archive has no path of its own, both sides are the walked directory
+    let ctx = &Context::new((source, source), options, on_err, None)?;
+
+    export_dir(source, target, ctx)
+        .map(|()| ctx.report.take())
+        .map_err(|()| ctx.failure())
+}
+
+/// Make `target` a replica of `source` with the default options, errors being given to
+/// `on_err`, see `Direction::Mirror`.
+pub fn synchronize_mirror<FErr>(
+    source: &Path,
+    target: &Path,
+    on_err: FErr,
+) -> Result<SyncReport, SyncError>
+where
+    FErr: Fn(&dyn std::error::Error) -> ErrorHandlingType,
+{
+    let options = SyncOptions {
+        direction: Direction::Mirror,
+        ..Default::default()
+    };
+
+    synchronize_paths(source, target, &options, &|context| on_err(context.error))
 }
 
-#[inline]
-fn path_has_extension(path: &Path, extension: &str) -> bool {
-    path.extension() == Some(std::ffi::OsStr::new(extension))
+/// Synchronize any number of paths together, each of them ending up with the newest version
+/// of every entry.
+///
+/// Kept for compatibility, `synchronize_many_with` also tells which error stopped the
+/// synchronization.
+#[allow(clippy::result_unit_err)]
+pub fn synchronize_many<FErr>(paths: &[&Path], on_err: FErr) -> Result<(), ()>
+where
+    FErr: Fn(&dyn std::error::Error) -> ErrorHandlingType,
+{
+    synchronize_many_paths(paths, &SyncOptions::default(), &|context| {
+        on_err(context.error)
+    })
+    .map(|_| ())
+    .map_err(|_| ())
 }
 
-#[inline]
-fn is_mac_app(path: &Path) -> bool {
-    // A macOS app must have the 'app' extension and be a directory.
-    path_has_extension(path, "app") && path.is_dir()
+/// Synchronize any number of paths together, configuring each synchronization with `options`.
+pub fn synchronize_many_with(
+    paths: &[&Path],
+    options: &SyncOptions,
+) -> Result<SyncReport, SyncError> {
+    synchronize_many_paths(paths, options, &*options.error_handler)
 }
 
-fn is_part_of_mac_app(path: &Path) -> bool {
-    let mut current_path_option = path.parent();
+fn synchronize_many_paths(
+    paths: &[&Path],
+    options: &SyncOptions,
+    on_err: &dyn Fn(&ErrorContext) -> ErrorHandlingType,
+) -> Result<SyncReport, SyncError> {
+    let (first, others) = match paths.split_first() {
+        Some((first, others)) if !others.is_empty() => (first, others),
+        _ => {
+            let err = SyncError::InvalidOptions("At least 2 paths are needed");
+            on_err(&ErrorContext::without_context(&err));
+            return Err(err);
+        }
+    };
+
+    let mut report = SyncReport::default();
 
-    while let Some(current_path) = current_path_option {
-        if is_mac_app(current_path) {
-            return true;
+    // The first path gathers the newest version of every entry while being synchronized with
+    // each other path, then gives them back to the ones synchronized before the last one.
+    for other in others.iter().chain(others.iter().rev().skip(1)) {
+        report.merge(synchronize_paths(first, other, options, on_err)?);
+    }
+
+    Ok(report)
+}
+
+fn synchronize_paths(
+    path1: &Path,
+    path2: &Path,
+    options: &SyncOptions,
+    on_err: &dyn Fn(&ErrorContext) -> ErrorHandlingType,
+) -> Result<SyncReport, SyncError> {
+    // nothing may be modified before the options are known to be valid
+    if let Err(err) = validate_options(options) {
+        on_err(&ErrorContext::without_context(&err));
+        return Err(err);
+    }
+
+    // Like rsync, "src" is synchronized with "dst/src" while "src/" is synchronized with "dst".
+    let nested_path2 =
+        (options.rsync_trailing_slash && path1.is_dir() && !has_trailing_separator(path1))
+            .then(|| path1.file_name().map(|name| path2.join(name)))
+            .flatten();
+    let path2 = nested_path2.as_deref().unwrap_or(path2);
+
+    // the walks would go through the synchronized entries themselves
+    let (canonical1, canonical2) = (canonical_path(path1), canonical_path(path2));
+    if canonical1.starts_with(&canonical2) || canonical2.starts_with(&canonical1) {
+        let err = SyncError::OverlappingPaths(path1.to_owned(), path2.to_owned());
+        on_err(&ErrorContext::without_context(&err));
+        return Err(err);
+    }
+
+    let propagates_deletions = options.propagate_deletions
+        && options.direction == Direction::Bidirectional
+        && path1.is_dir()
+        && path2.is_dir();
+    // without the manifest, nothing looks deleted and a new one is recorded
+    let manifest = if propagates_deletions && !options.initialize {
+        match Manifest::read(path1, path2) {
+            Ok(manifest) => manifest,
+            Err(err) => match on_err(&ErrorContext::without_context(&err)) {
+                ErrorHandlingType::Fail => return Err(err),
+                ErrorHandlingType::Skip | ErrorHandlingType::Ignore => None,
+            },
         }
+    } else {
+        None
+    };
+
+    let synchronize = |planning| {
+        let ctx = &Context {
+            planning,
+            ..Context::new((path1, path2), options, on_err, manifest.clone())?
+        };
+
+        synchronize_roots(
+            path1,
+            path2,
+            nested_path2.is_some(),
+            propagates_deletions,
+            ctx,
+        )
+    };
 
-        current_path_option = current_path.parent();
+    // nothing is modified until the plan is approved
+    if let Some(on_plan) = options.on_plan.as_ref().filter(|_| !options.dry_run) {
+        if !on_plan(&SyncPlan::new(synchronize(true)?.planned)) {
+            return Err(SyncError::Rejected);
+        }
     }
 
-    false
+    synchronize(false)
 }
 
-pub fn synchronize<FErr>(path1: &Path, path2: &Path, on_err: FErr) -> Result<(), ()>
-where
-    FErr: Fn(&dyn std::error::Error) -> ErrorHandlingType,
-{
-    if path1.is_dir() {
+/// Synchronize `path1` and `path2` once they are known to be valid, `path2` being nested in the
+/// second path given with `SyncOptions::rsync_trailing_slash`.
+fn synchronize_roots(
+    path1: &Path,
+    path2: &Path,
+    nested_path2: bool,
+    propagates_deletions: bool,
+    ctx: &Context,
+) -> Result<SyncReport, SyncError> {
+    let (options, on_err) = (ctx.options, ctx.error_handler);
+
+    for excluded_path in &options.exclude_paths {
+        if excluded_path.is_absolute()
+            && !excluded_path.starts_with(path1)
+            && !excluded_path.starts_with(path2)
+        {
+            ctx.warn(format!(
+                "Excluded path '{}' is not inside the synchronized paths",
+                excluded_path.display()
+            ));
+        }
+    }
+
+    if nested_path2 && !path2.exists() {
+        if let Err(err) = ctx.create_dir(path2) {
+            on_err(&ErrorContext {
+                error: &err,
+                operation: Operation::CreateDir,
+                relative_path: None,
+            });
+            return Err(err);
+        }
+    }
+
+    if options.create_missing_target {
+        for (existing, missing) in [(path1, path2), (path2, path1)] {
+            if existing.is_dir() && fs::symlink_metadata(missing).is_err() {
+                return create_missing_target(existing, missing, ctx)
+                    .map(|_| ctx.report.take())
+                    .map_err(|()| ctx.failure());
+            }
+        }
+    }
+
+    // the type of both paths decides how they're synchronized
+    if let Some(missing) = [path1, path2].iter().find(|path| !path.exists()) {
+        let err = SyncError::NotFound(missing.to_path_buf());
+        on_err(&ErrorContext::without_context(&err));
+        return Err(err);
+    }
+
+    if options.precount
+        && options.on_progress.is_some()
+        && !ctx.planning
+        && path1.is_dir()
+        && path2.is_dir()
+    {
+        ctx.total.set(Some(precount(path1, path2, ctx)));
+    }
+
+    let result = if path1.is_dir() {
         if path2.is_dir() {
             // path1 & path2: dir
 
-            if path_has_extension(path1, "app") || path_has_extension(path2, "app") {
+            if options.flatten {
+                synchronize_dirs_flattened(path1, path2, ctx)
+            } else if !options.merge_root_bundle
+                && (path_has_extension(path1, "app") || path_has_extension(path2, "app"))
+            {
                 // macOS app(s)
-                synchronize_dirs_replace(path1, path2, &on_err)
+                synchronize_dirs_replace(path1, path2, ctx)
             } else {
                 // regular dir(s)
-                synchronize_dirs(path1, path2, &on_err)
+                synchronize_dirs(path1, path2, ctx)
+                    .and_then(|_| {
+                        if options.preserve_dir_timestamps
+                            && options.direction != Direction::Bidirectional
+                        {
+                            copy_dir_times(path1, path2, ctx)
+                        } else {
+                            Ok(())
+                        }
+                    })
+                    .and_then(|_| {
+                        if propagates_deletions {
+                            record_manifest(path1, path2, ctx)
+                        } else {
+                            Ok(())
+                        }
+                    })
             }
         } else {
             // path1: dir, path2: file
-            synchronize_file_with_dir(path2, path1, &on_err)
+            synchronize_file_with_dir(path2, path1, ctx)
         }
     } else if path2.is_file() {
         // path1 & path2: file
-        synchronize_files(path1, path2, &on_err)
+        let metadata = |path| fs::metadata(path).map_err(|err| SyncError::io(path, err));
+
+        match (metadata(path1), metadata(path2)) {
+            (Ok(metadata1), Ok(metadata2)) => {
+                synchronize_files(path1, &metadata1, path2, &metadata2, ctx)
+            }
+            (Err(err), _) | (_, Err(err)) => match ctx.on_err(err) {
+                ErrorHandlingType::Fail => Err(()),
+                ErrorHandlingType::Skip | ErrorHandlingType::Ignore => Ok(()),
+            },
+        }
     } else {
         // path1: file, path2: dir
-        synchronize_file_with_dir(path1, path2, &on_err)
-    }
-}
+        synchronize_file_with_dir(path1, path2, ctx)
+    };
 
-const DIR1_NOT_SYMLINK_ID: u8 = 0;
-// const DIR2_NOT_SYMLINK_ID: u8 = 1;
-const DIR1_SYMLINK_ID: u8 = 2;
-// const DIR2_SYMLINK_ID: u8 = 3;
+    let result = result.and_then(|_| {
+        if options.verify && !ctx.dry_run() && !options.metadata_only {
+            verify(path1, path2, ctx)
+        } else {
+            Ok(())
+        }
+    });
 
-fn id_and_relative_path_from_dir_entry<FErr>(
-    entry: &walkdir::Result<DirEntry>,
-    base_path: &Path,
-    dir_id_no_symlink: u8,
-    on_err: &FErr,
-) -> Result<(u8, PathBuf), ErrorHandlingType>
-where
-    FErr: Fn(&dyn std::error::Error) -> ErrorHandlingType,
-{
-    match entry {
-        Err(err) => Err(on_err(err)),
-        Ok(entry) => {
-            let dir_id = if entry.path_is_symlink() { DIR1_SYMLINK_ID } else { DIR1_NOT_SYMLINK_ID } + dir_id_no_symlink;
-            let path: &Path = entry.path();
+    result
+        .map(|_| ctx.report.take())
+        .map_err(|()| ctx.failure())
+}
 
-            macro_rules! some_or_return {
-                ($e:expr) => {
-                    match $e {
-                        Some(x) => x,
-                        None => return Err(ErrorHandlingType::Ignore),
-                    }
-                };
-            }
+/// Count the entries processed by synchronizing `dir1` and `dir2`: the ones of `dir1` and the
+/// ones only in `dir2`, see `SyncOptions::precount`.
+///
+/// Walk errors are left to the synchronization, the count may then be off.
+fn precount(dir1: &Path, dir2: &Path, ctx: &Context) -> u64 {
+    let walk = |dir| {
+        ctx.walk(dir)
+            .min_depth(1)
+            .into_iter()
+            .filter_entry(|entry| {
+                let file_type = entry.file_type();
 
-            let path_str = some_or_return!(path.to_str());
-            let base_path_str = some_or_return!(base_path.to_str());
-            let trimmed = some_or_return!(trim_base_path(base_path_str, path_str));
+                !(entry.depth() == 1 && entry.file_name() == MANIFEST_NAME)
+                    && unstaged_path(entry.path()).is_none()
+                    && !is_partial_path(entry.path())
+                    && ctx.exclusion_reason(entry).is_none()
+                    && !(ctx.options.regular_files_only
+                        && !file_type.is_file()
+                        && !file_type.is_dir())
+            })
+            .filter_map(Result::ok)
+    };
 
-            Ok((dir_id, trimmed))
-        }
-    }
+    let in_dir1 = walk(dir1).count();
+    let only_in_dir2 = walk(dir2)
+        .filter(|entry| {
+            entry
+                .path()
+                .strip_prefix(dir2)
+                .is_ok_and(|relative_path| fs::symlink_metadata(dir1.join(relative_path)).is_err())
+        })
+        .count();
+
+    (in_dir1 + only_in_dir2) as u64
 }
 
-/// Synchronize 2 directories, merging their files and keeping only their newest versions.
-fn synchronize_dirs<FErr>(dir1: &Path, dir2: &Path, on_err: &FErr) -> Result<(), ()>
-where
-    FErr: Fn(&dyn std::error::Error) -> ErrorHandlingType,
-{
-    let skip = RefCell::from(false);
-    let fail = RefCell::from(false);
+/// Check that `options` can be used together, whatever the synchronized paths.
+fn validate_options(options: &SyncOptions) -> Result<(), SyncError> {
+    if options.jobs == Some(0) {
+        return Err(SyncError::InvalidOptions("At least 1 job is needed"));
+    }
 
-    macro_rules! id_and_relative_path {
-        ($e:expr, $dir:expr, $id:expr, $on_err:expr) => {
-            match id_and_relative_path_from_dir_entry($e, $dir, $id, $on_err) {
-                Ok(x) => Some(x),
-                Err(handle) => {
-                    use ErrorHandlingType::*;
+    if options.flatten && options.direction != Direction::Push {
+        return Err(SyncError::InvalidOptions(
+            "Flattening is only possible when pushing",
+        ));
+    }
 
-                    match handle {
-                        Fail => *fail.borrow_mut() = true,
-                        Skip => *skip.borrow_mut() = true,
-                        Ignore => (),
-                    };
+    if options.checksum_xattrs && options.hash_algorithm.is_none() {
+        return Err(SyncError::InvalidOptions(
+            "No hash algorithm to compute checksums",
+        ));
+    }
 
-                    None
-                }
-            }
-        };
+    // synchronizing a file changes its change time and a copy is created anew, so the
+    // destination would always look newer the next time
+    if options.compare_time_field != TimeField::Mtime
+        && !matches!(options.direction, Direction::Push | Direction::Mirror)
+    {
+        return Err(SyncError::InvalidOptions(
+            "Change and creation times can only be compared when pushing or mirroring",
+        ));
     }
 
-    macro_rules! handle_error {
-        ($err:expr) => {
-            use ErrorHandlingType::*;
+    Ok(())
+}
 
-            match on_err($err) {
-                Fail => return Err(()),
-                Skip => return Ok(()),
-                Ignore => (),
-            };
-        };
+/// Get the canonical form of `path`, which may not exist yet (e.g. a directory to create).
+fn canonical_path(path: &Path) -> PathBuf {
+    match (path.canonicalize(), path.parent(), path.file_name()) {
+        (Ok(canonical), _, _) => canonical,
+        (Err(_), Some(parent), Some(name)) => canonical_path(parent).join(name),
+        (Err(_), _, _) => path.to_owned(),
     }
+}
 
-    let dir_iterator = WalkDir::new(dir1)
-        .min_depth(1)
-        .into_iter()
-        .filter_map(|e| id_and_relative_path!(&e, dir1, 0, on_err))
-        .chain(
-            WalkDir::new(dir2)
-                .min_depth(1)
-                .into_iter()
-                .filter_map(|e| id_and_relative_path!(&e, dir2, 1, on_err))
-                // never synchronize the same path twice
-                .filter(|(_, rel_path)| !dir1.join(rel_path).exists()),
-        );
+/// Name of the file listing the entries to exclude from the synchronized path it's at the root
+/// of, with the syntax of `.gitignore` files. Its patterns only apply to that path, entries
+/// matching them on the other path are synchronized as usual.
+pub const IGNORE_FILE_NAME: &str = ".keepignore";
 
-    if *fail.borrow() {
-        return Err(());
-    } else if *skip.borrow() {
-        return Ok(());
+/// Read the ignore file at the root of `root`, if any, see `IGNORE_FILE_NAME`.
+fn read_ignore_file(root: &Path) -> Result<Option<Gitignore>, SyncError> {
+    let path = root.join(IGNORE_FILE_NAME);
+    if !path.is_file() {
+        return Ok(None);
     }
 
-    for (dir_id, relative_path) in dir_iterator {
-        let path_in_dir1 = dir1.join(&relative_path);
-        let path_in_dir2 = dir2.join(&relative_path);
-
-        // `path_in_dir` is where the element is in the scanned directory,
-        // `path_in_other_dir` is where the element should be in the other directory.
-        let (path_in_dir, path_in_other_dir) = if dir_id == DIR1_SYMLINK_ID || dir_id == DIR1_NOT_SYMLINK_ID {
-            (path_in_dir1, path_in_dir2)
-        } else {
-            (path_in_dir2, path_in_dir1)
-        };
+    let mut builder = GitignoreBuilder::new(root);
+    let ignore_file = match builder.add(&path) {
+        Some(err) => Err(err),
+        None => builder.build(),
+    };
 
-        // Paths that are part of a macOS app are already handled if they exists in both dirs => skip.
-        if is_part_of_mac_app(&path_in_dir) && path_in_other_dir.exists() {
-            continue;
-        }
+    ignore_file
+        .map(Some)
+        .map_err(|source| SyncError::IgnoreFile { path, source })
+}
 
-        if path_in_dir.is_file() {
-            if path_in_other_dir.is_file() {
-                // `path_in_other_dir` exists and points to a file
-                // Check timestamps, and overwrite the older with the recent one.
+/// Get the exact paths to exclude, relative paths being excluded from both roots.
+fn resolve_excluded_paths(exclude_paths: &[PathBuf], roots: (&Path, &Path)) -> HashSet<PathBuf> {
+    let (root1, root2) = roots;
 
-                synchronize_files(&path_in_dir, &path_in_other_dir, on_err)?;
-            } else if path_in_other_dir.is_dir() {
-                synchronize_file_with_dir(&path_in_dir, &path_in_other_dir, on_err)?;
+    exclude_paths
+        .iter()
+        .flat_map(|path| {
+            if path.is_absolute() {
+                vec![path.clone()]
             } else {
-                // path does not exist in other dir
-
-                if let Err(err) = std::fs::copy(path_in_dir, path_in_other_dir) {
-                    handle_error!(&err);
-                }
+                vec![root1.join(path), root2.join(path)]
             }
-        } else if !path_in_other_dir.exists() {
-            // path_in_dir: dir, path_in_other_dir: nothing
+        })
+        .collect()
+}
 
-            if let Err(err) = std::fs::create_dir(path_in_other_dir) {
-                handle_error!(&err);
-            }
-        } else if path_in_other_dir.is_file() {
-            // path_in_dir: dir, path_in_other_dir: file
+/// Everything the synchronization steps need to share.
+struct Context<'a> {
+    options: &'a SyncOptions,
+    /// Whether the synchronization is only planned for `SyncOptions::on_plan`, like a dry run
+    /// without calling the other callbacks.
+    planning: bool,
+    error_handler: &'a dyn Fn(&ErrorContext) -> ErrorHandlingType,
+    /// Error the synchronization has been stopped for, see `Context::on_err`.
+    failure: RefCell<Option<SyncError>>,
+    /// Modifications of the file system which failed and the path of their error, telling the
+    /// error handler what the error is about, see `Context::attempt`.
+    failed_operations: RefCell<Vec<(Operation, PathBuf)>>,
+    /// The 2 synchronized paths.
+    roots: (&'a Path, &'a Path),
+    /// `SyncOptions::exclude_paths` resolved against both roots.
+    excluded_paths: HashSet<PathBuf>,
+    /// Patterns of the ignore file of each root, see `IGNORE_FILE_NAME`.
+    ignore_files: [Option<Gitignore>; 2],
+    /// Entries seen by the last synchronization, when deletions are propagated.
+    manifest: Option<Manifest>,
+    /// Entries processed so far, see `SyncOptions::on_progress`.
+    processed: Cell<u64>,
+    /// Entries found so far while walking, see `SyncOptions::on_progress`.
+    discovered: Cell<u64>,
+    /// Entries to process, see `SyncOptions::precount`.
+    total: Cell<Option<u64>>,
+    /// See `SyncOptions::rate_limit`.
+    rate_limiter: Option<RateLimiter>,
+    /// Threads copying files with `SyncOptions::jobs`, `None` to use the global pool.
+    #[cfg(feature = "parallel")]
+    thread_pool: Option<rayon::ThreadPool>,
+    report: RefCell<SyncReport>,
+}
 
-            synchronize_file_with_dir(&path_in_other_dir, &path_in_dir, on_err)?;
-        } else if is_mac_app(&path_in_dir) {
-            // path_in_dir: dir (macOS app), path_in_other_dir: dir (macOS app)
+impl<'a> Context<'a> {
+    /// Prepare the synchronization of `roots`, reading their ignore files.
+    fn new(
+        roots: (&'a Path, &'a Path),
+        options: &'a SyncOptions,
+        on_err: &'a dyn Fn(&ErrorContext) -> ErrorHandlingType,
+        manifest: Option<Manifest>,
+    ) -> Result<Self, SyncError> {
+        let read_ignore_file = |root| match read_ignore_file(root) {
+            Ok(ignore_file) => Ok(ignore_file),
+            Err(err) => match on_err(&ErrorContext::without_context(&err)) {
+                ErrorHandlingType::Fail => Err(err),
+                ErrorHandlingType::Skip | ErrorHandlingType::Ignore => Ok(None),
+            },
+        };
+        let ignore_files = [read_ignore_file(roots.0)?, read_ignore_file(roots.1)?];
 
-            synchronize_dirs_replace(&path_in_dir, &path_in_other_dir, on_err)?;
-        } // else path_in_dir: dir, path_in_other_dir: dir => ignore.
+        Ok(Context {
+            options,
+            planning: false,
+            error_handler: on_err,
+            failure: RefCell::default(),
+            failed_operations: RefCell::default(),
+            roots,
+            excluded_paths: resolve_excluded_paths(&options.exclude_paths, roots),
+            ignore_files,
+            manifest,
+            processed: Cell::default(),
+            discovered: Cell::default(),
+            total: Cell::default(),
+            rate_limiter: options.rate_limit.map(RateLimiter::new),
+            // the global pool is used if the threads can't be started
+            #[cfg(feature = "parallel")]
+            thread_pool: options.jobs.filter(|_| options.parallel).and_then(|jobs| {
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(jobs)
+                    .build()
+                    .ok()
+            }),
+            report: RefCell::default(),
+        })
     }
-
-    Ok(())
 }
 
-/// Synchronize 2 files, replacing the oldest by the newest.
-fn synchronize_files<FErr>(path1: &Path, path2: &Path, on_err: &FErr) -> Result<(), ()>
-where
-    FErr: Fn(&dyn std::error::Error) -> ErrorHandlingType,
-{
-    macro_rules! handle_error {
-        (use $on_err:ident for $err:ident) => {
-            use ErrorHandlingType::*;
+impl Context<'_> {
+    /// Let the error handler decide how to handle `err`, keeping it to be returned if the
+    /// synchronization is stopped. Errors the synchronization goes on after are logged as
+    /// warnings.
+    fn on_err(&self, err: impl Into<SyncError>) -> ErrorHandlingType {
+        // errors caused by the cancellation (e.g. interrupted copies) aren't worth handling
+        if self.is_cancelled() {
+            return ErrorHandlingType::Fail;
+        }
 
-            let handle = $on_err(&$err);
-            match handle {
-                Fail => return Err(()),
-                Skip | Ignore => (),
-            }
+        let err = err.into();
+        let operation = match &err {
+            SyncError::WalkDir(_) => Operation::Walk,
+            _ => self.failed_operation(&err),
         };
+        let handling = (self.error_handler)(&ErrorContext {
+            error: &err,
+            operation,
+            relative_path: err.path().and_then(|path| self.relative_path(path)),
+        });
+
+        if handling == ErrorHandlingType::Fail {
+            log::error!("{}", err);
+            self.failure.borrow_mut().get_or_insert(err);
+        } else {
+            log::warn!("{}", err);
+        }
+
+        handling
     }
 
-    let time_in_dir = FileTime::from_last_modification_time(
-        &std::fs::metadata(path1).expect("This should never happen"),
-    );
+    /// Get the operation which failed with `err`, recorded by `Context::attempt`.
+    fn failed_operation(&self, err: &SyncError) -> Operation {
+        let mut failed_operations = self.failed_operations.borrow_mut();
+        let index = failed_operations
+            .iter()
+            .position(|(_, path)| Some(path.as_path()) == err.path());
 
-    let time_in_other_dir = FileTime::from_last_modification_time(
-        &std::fs::metadata(path2).expect("This should never happen"),
-    );
+        index.map_or(Operation::Other, |index| {
+            failed_operations.swap_remove(index).0
+        })
+    }
 
-    use std::cmp::Ordering;
-    let (source_path, target_path, max_time) = match time_in_dir.cmp(&time_in_other_dir) {
-        Ordering::Greater => (path1, path2, time_in_dir),
-        Ordering::Less => (path2, path1, time_in_other_dir),
-        Ordering::Equal => return Ok(()), // already synchronized => skip
-    };
+    /// Get the error the synchronization has been stopped for.
+    fn failure(&self) -> SyncError {
+        match self.failure.take() {
+            Some(err) => err,
+            None if self.is_timed_out() => SyncError::TimedOut {
+                stats: self.report.borrow().stats,
+            },
+            None => SyncError::Aborted,
+        }
+    }
 
-    if let Some(parent_path) = target_path.parent() {
-        if !parent_path.exists() {
-            // should be created before => should never happen
-            if let Err(err) = std::fs::create_dir_all(parent_path) {
-                handle_error!(use on_err for err);
+    /// Whether nothing may be modified, see `SyncOptions::dry_run` and `SyncOptions::on_plan`.
+    fn dry_run(&self) -> bool {
+        self.options.dry_run || self.planning
+    }
+
+    /// Whether the synchronization has been cancelled, see `SyncOptions::cancel` and
+    /// `SyncOptions::deadline`.
+    fn is_cancelled(&self) -> bool {
+        self.options
+            .cancel
+            .as_ref()
+            .is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+            || self.is_timed_out()
+    }
+
+    /// Whether `SyncOptions::deadline` has been reached.
+    fn is_timed_out(&self) -> bool {
+        self.options
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Whether `path` may be updated according to the direction of the synchronization.
+    fn accepts_changes(&self, path: &Path) -> bool {
+        match self.options.direction {
+            Direction::Bidirectional => true,
+            Direction::Push | Direction::Mirror => {
+                !path.starts_with(self.roots.0) || path.starts_with(self.roots.1)
             }
         }
     }
 
-    if let Err(err) = std::fs::copy(source_path, target_path) {
-        handle_error!(use on_err for err);
+    /// Whether an entry missing from the other side has been deleted from it since the last
+    /// synchronization, see `SyncOptions::propagate_deletions`.
+    fn was_deleted(&self, relative_path_in_dir1: &Path, metadata: &fs::Metadata) -> bool {
+        self.manifest
+            .as_ref()
+            .is_some_and(|manifest| manifest.is_unchanged(relative_path_in_dir1, metadata))
     }
 
-    if let Err(err) = filetime::set_file_times(source_path, max_time, max_time) {
-        handle_error!(use on_err for err);
+    /// Record an entry excluded from the synchronization.
+    fn skipped(&self, reason: SkipReason) {
+        self.report.borrow_mut().filtered.record(reason);
     }
 
-    Ok(())
-}
+    /// Check whether `entry` is excluded by `SyncOptions::exclude_paths`,
+    /// `SyncOptions::exclude`, `SyncOptions::include` or `SyncOptions::skip_hidden`, recording
+    /// it if so.
+    fn is_excluded(&self, entry: &DirEntry) -> bool {
+        match self.exclusion_reason(entry) {
+            Some(reason) => {
+                self.skipped(reason);
+                true
+            }
+            None => false,
+        }
+    }
 
-fn synchronize_file_with_dir<FErr>(
-    file_path: &Path,
-    dir_path: &Path,
-    on_err: &FErr,
-) -> Result<(), ()>
-where
-    FErr: Fn(&dyn std::error::Error) -> ErrorHandlingType,
-{
-    macro_rules! unwrap_result {
-        ($e:expr) => {
-            match $e {
-                Err(err) => {
-                    use ErrorHandlingType::*;
+    /// Get `path` relative to the synchronized path it's part of.
+    fn relative_path<'p>(&self, path: &'p Path) -> Option<&'p Path> {
+        self.side_index(path)
+            .and_then(|index| path.strip_prefix([self.roots.0, self.roots.1][index]).ok())
+    }
 
-                    match on_err(&err) {
-                        Fail => return Err(()),
-                        Skip | Ignore => return Ok(()),
-                    }
-                }
-                Ok(x) => x,
-            }
-        };
+    /// Count an entry found while walking, see `SyncOptions::on_progress`.
+    fn discovered(&self) {
+        self.discovered.set(self.discovered.get() + 1);
     }
 
-    let file_time = FileTime::from_last_modification_time(&unwrap_result!(file_path.metadata()));
+    /// Report that the entry at `path` is being processed, see `SyncOptions::on_progress`.
+    fn progress(&self, path: &Path) {
+        self.processed.set(self.processed.get() + 1);
+        self.report_progress(path, None);
+    }
 
-    let dir_time = match dir_latest_modification_time(dir_path, on_err) {
-        Ok(x) => x,
-        Err(err) => {
-            use ErrorHandlingType::*;
+    /// Report the progress of the entry being processed, optionally with the progress of its
+    /// copy.
+    fn report_progress(&self, path: &Path, bytes: Option<(u64, u64)>) {
+        if let Some(on_progress) = self.options.on_progress.as_ref().filter(|_| !self.planning) {
+            on_progress(&Progress {
+                path: self.relative_path(path).unwrap_or(path),
+                processed: self.processed.get(),
+                // entries aren't always discovered by walking (e.g. single files)
+                discovered: self.discovered.get().max(self.processed.get()),
+                total: self.total.get(),
+                bytes,
+                stats: self.report.borrow().stats,
+            });
+        }
+    }
 
-            return match err {
-                Fail => Err(()),
-                Skip => Ok(()),
-                _ => unreachable!(),
-            };
+    /// Explain why the entry at `path` is synchronized the way it is, see
+    /// `SyncOptions::on_decision`.
+    fn decided<'p>(&self, decision: fn(&'p Path) -> Decision<'p>, path: &'p Path) {
+        let decision = decision(self.relative_path(path).unwrap_or(path));
+        log::trace!("{:?}", decision);
+
+        if let Some(on_decision) = self.options.on_decision.as_ref().filter(|_| !self.planning) {
+            on_decision(&decision);
         }
-    };
+    }
 
-    if file_time > dir_time {
-        unwrap_result!(fs::remove_dir_all(dir_path));
-        unwrap_result!(fs::copy(file_path, dir_path));
-        unwrap_result!(filetime::set_file_times(dir_path, file_time, file_time));
-    } else {
-        unwrap_result!(fs::remove_file(file_path));
-        unwrap_result!(fs::create_dir(file_path));
-        match copy_dir(dir_path, file_path, dir_time, on_err) {
-            Ok(_) => (),
-            Err(_) => return Err(()),
+    /// Check whether the file at `path` is too large to be copied (see
+    /// `SyncOptions::max_file_size`), recording it if so.
+    fn is_too_large(&self, path: &Path, metadata: &fs::Metadata) -> bool {
+        let too_large = self
+            .options
+            .max_file_size
+            .is_some_and(|max_file_size| metadata.len() > max_file_size);
+
+        if too_large {
+            self.skipped(SkipReason::TooLarge);
+            self.decided(Decision::SkippedTooLarge, path);
         }
+
+        too_large
     }
 
-    Ok(())
-}
+    /// Get why `entry` is excluded from the synchronization, if it is.
+    fn exclusion_reason(&self, entry: &DirEntry) -> Option<SkipReason> {
+        const MATCH_OPTIONS: glob::MatchOptions = glob::MatchOptions {
+            case_sensitive: true,
+            require_literal_separator: true,
+            require_literal_leading_dot: false,
+        };
 
-/// Copy a directory, preserving the timestamps.
-fn copy_dir<'t1, 't2, FErr>(
-    source: &'t1 Path,
-    target: &'t2 Path,
-    time: FileTime,
-    on_err: &FErr,
-) -> Result<(), ()>
-where
-    FErr: Fn(&dyn std::error::Error) -> ErrorHandlingType,
-{
-    let skip = RefCell::from(false);
-    let fail = RefCell::from(false);
+        let path = entry.path();
 
-    let relative_path_iter = WalkDir::new(source)
-        .min_depth(1)
-        .into_iter()
+        if self.excluded_paths.contains(path) {
+            return Some(SkipReason::ExcludedPath);
+        } else if self.options.skip_hidden && entry.depth() > 0 && is_hidden(entry) {
+            return Some(SkipReason::Hidden);
+        }
+
+        // patterns match paths relative to the synchronized path they are part of
+        let relative_path = self.relative_path(path)?;
+
+        let matches = |patterns: &[Pattern]| {
+            patterns
+                .iter()
+                .any(|pattern| pattern.matches_path_with(relative_path, MATCH_OPTIONS))
+        };
+
+        let is_ignored = || {
+            let ignore_file = self
+                .side_index(path)
+                .and_then(|index| self.ignore_files[index].as_ref());
+
+            ignore_file.is_some_and(|ignore_file| {
+                entry.depth() > 0
+                    && ignore_file
+                        .matched(path, entry.file_type().is_dir())
+                        .is_ignore()
+            })
+        };
+
+        if matches(&self.options.exclude) || is_ignored() {
+            Some(SkipReason::ExcludedPattern)
+        } else if self.options.include.is_empty()
+            || entry.depth() == 0
+            || entry.file_type().is_dir()
+            || matches(&self.options.include)
+        {
+            None
+        } else {
+            Some(SkipReason::NotIncluded)
+        }
+    }
+
+    /// Record a non-fatal problem, only once.
+    fn warn(&self, warning: String) {
+        let warnings = &mut self.report.borrow_mut().warnings;
+
+        if !warnings.contains(&warning) {
+            log::warn!("{}", warning);
+            warnings.push(warning);
+        }
+    }
+
+    /// Record an entry copied under another name.
+    fn renamed(&self, source: &Path, target: &Path) {
+        self.report
+            .borrow_mut()
+            .renamed
+            .push((source.to_owned(), target.to_owned()));
+    }
+
+    /// Start walking `root`, see `SyncOptions::low_memory`, `SyncOptions::follow_symlinks` and
+    /// `SyncOptions::max_depth`.
+    fn walk(&self, root: &Path) -> WalkDir {
+        let walk_dir = WalkDir::new(root).follow_links(self.options.follow_symlinks);
+
+        let walk_dir = match self.options.max_depth {
+            Some(max_depth) => {
+                // the limit is counted from the synchronized path `root` is part of
+                let depth = self
+                    .relative_path(root)
+                    .map_or(0, |path| path.components().count());
+
+                walk_dir.max_depth(max_depth.saturating_sub(depth))
+            }
+            None => walk_dir,
+        };
+
+        if self.options.low_memory {
+            // never read the rest of a directory in advance to close it
+            walk_dir.max_open(usize::MAX)
+        } else {
+            walk_dir
+        }
+    }
+
+    /// Get where `relative_path` should be under `base`, sanitizing its name if `base` is on
+    /// the second side.
+    fn destination_path(&self, base: &Path, relative_path: &Path) -> Result<PathBuf, SyncError> {
+        match &self.options.sanitize {
+            Some(policy) if self.side_index(base) == Some(1) => policy
+                .sanitize_path(relative_path)
+                .map(|relative_path| base.join(relative_path))
+                .ok_or_else(|| SyncError::IllegalDestinationName(base.join(relative_path))),
+            _ => Ok(base.join(relative_path)),
+        }
+    }
+}
+
+/// Every modification of the file system goes through these methods.
+impl Context<'_> {
+    /// Run `operation`, recording it if it fails so that the error handler can tell what the
+    /// error is about.
+    fn attempt<T, F>(&self, operation: Operation, attempt: F) -> Result<T, SyncError>
+    where
+        F: FnOnce() -> Result<T, SyncError>,
+    {
+        attempt().inspect_err(|err| {
+            if let Some(path) = err.path() {
+                self.failed_operations
+                    .borrow_mut()
+                    .push((operation, path.to_owned()));
+            }
+        })
+    }
+
+    /// Get the side `path` is part of: `0` for the first path, `1` for the second one.
+    fn side_index(&self, path: &Path) -> Option<usize> {
+        let (root1, root2) = self.roots;
+
+        // The deepest root wins when one root contains the other.
+        [root1, root2]
+            .iter()
+            .enumerate()
+            .filter(|(_, root)| path.starts_with(root))
+            .max_by_key(|(_, root)| root.components().count())
+            .map(|(index, _)| index)
+    }
+
+    /// Get the options of the side `path` is part of.
+    fn side_of(&self, path: &Path) -> Option<&SideOptions> {
+        self.side_index(path)
+            .map(|index| [&self.options.side1, &self.options.side2][index])
+    }
+
+    /// Refuse to modify `path` if it's part of a read-only side.
+    fn check_writable(&self, path: &Path) -> Result<(), SyncError> {
+        match self.side_of(path) {
+            Some(side) if side.read_only => Err(SyncError::ReadOnlySide(path.to_owned())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Copy a file, storing its checksum on the copy if enabled.
+    fn copy(&self, from: &Path, to: &Path) -> Result<u64, SyncError> {
+        self.attempt(Operation::Copy, || {
+            self.check_writable(to)?;
+            if self.planned(|| PlannedAction::Copy {
+                from: from.to_owned(),
+                to: to.to_owned(),
+            }) {
+                return fs::metadata(from)
+                    .map(|metadata| metadata.len())
+                    .map_err(|err| SyncError::io(from, err));
+            }
+
+            let size = match self.options.on_progress {
+                Some(_) => fs::metadata(from)
+                    .map_err(|err| SyncError::io(from, err))?
+                    .len(),
+                None => 0,
+            };
+            let previously_copied = Cell::new(0);
+            let on_chunk = |copied| {
+                self.report_progress(from, Some((copied, size)));
+
+                if let Some(rate_limiter) = &self.rate_limiter {
+                    rate_limiter.consume(copied - previously_copied.replace(copied));
+                }
+
+                if self.is_cancelled() {
+                    Err(io::Error::new(
+                        io::ErrorKind::Interrupted,
+                        "Synchronization cancelled",
+                    ))
+                } else {
+                    Ok(())
+                }
+            };
+            let chunked = copy::ChunkedCopy {
+                buffer_size: self.options.copy_buffer_size,
+                on_chunk: &on_chunk,
+            };
+
+            let copied = copy_file(
+                &long_path(from),
+                &long_path(to),
+                self.options.atomic,
+                self.options.sparse,
+                self.options.preserve_xattrs,
+                self.checksum_algorithm()?,
+                (self.options.on_progress.is_some()
+                    || self.options.cancel.is_some()
+                    || self.options.deadline.is_some()
+                    || self.rate_limiter.is_some())
+                .then_some(&chunked),
+            )
+            .map_err(|err| SyncError::io(to, err))?;
+
+            self.count(|stats| {
+                stats.files_copied += 1;
+                stats.bytes_copied += copied;
+            });
+
+            Ok(copied)
+        })
+    }
+
+    /// Copy files independently of each other, see `SyncOptions::parallel`.
+    fn copy_many(&self, copies: &[(PathBuf, PathBuf)]) -> Vec<Result<u64, SyncError>> {
+        #[cfg(feature = "parallel")]
+        if self.options.parallel && !self.dry_run() && self.rate_limiter.is_none() {
+            return self.copy_in_parallel(copies);
+        }
+
+        copies
+            .iter()
+            .map(|(from, to)| self.copy(from, to))
+            .collect()
+    }
+
+    /// Copy files across a thread pool.
+    ///
+    /// Only the copies themselves are run on the thread pool, everything else (including
+    /// counting them) is done on the calling thread.
+    #[cfg(feature = "parallel")]
+    fn copy_in_parallel(&self, copies: &[(PathBuf, PathBuf)]) -> Vec<Result<u64, SyncError>> {
+        use rayon::prelude::*;
+
+        let (atomic, sparse, preserve_xattrs, buffer_size) = (
+            self.options.atomic,
+            self.options.sparse,
+            self.options.preserve_xattrs,
+            self.options.copy_buffer_size,
+        );
+        // the context can't be shared between threads, unlike what cancels the copies
+        let (cancel, deadline) = (self.options.cancel.as_deref(), self.options.deadline);
+        let is_cancelled = || {
+            cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+                || deadline.is_some_and(|deadline| Instant::now() >= deadline)
+        };
+        let interrupted =
+            || io::Error::new(io::ErrorKind::Interrupted, "Synchronization cancelled");
+        let on_chunk = |_| {
+            if is_cancelled() {
+                Err(interrupted())
+            } else {
+                Ok(())
+            }
+        };
+
+        let checked: Vec<_> = copies
+            .iter()
+            .map(|(from, to)| {
+                self.check_writable(to)?;
+                self.planned(|| PlannedAction::Copy {
+                    from: from.to_owned(),
+                    to: to.to_owned(),
+                });
+
+                self.checksum_algorithm()
+            })
+            .collect();
+
+        let copy_all = || -> Vec<_> {
+            copies
+                .par_iter()
+                .zip(checked)
+                .map(|((from, to), checked)| {
+                    let checksum_algorithm = checked?;
+                    if is_cancelled() {
+                        return Err(SyncError::io(to, interrupted()));
+                    }
+
+                    let chunked = copy::ChunkedCopy {
+                        buffer_size,
+                        on_chunk: &on_chunk,
+                    };
+                    copy_file(
+                        &long_path(from),
+                        &long_path(to),
+                        atomic,
+                        sparse,
+                        preserve_xattrs,
+                        checksum_algorithm,
+                        (cancel.is_some() || deadline.is_some()).then_some(&chunked),
+                    )
+                    .map_err(|err| SyncError::io(to, err))
+                })
+                .collect()
+        };
+        let results = match &self.thread_pool {
+            Some(thread_pool) => thread_pool.install(copy_all),
+            None => copy_all(),
+        };
+
+        for copied in results.iter().flatten() {
+            self.count(|stats| {
+                stats.files_copied += 1;
+                stats.bytes_copied += copied;
+            });
+        }
+
+        results
+            .into_iter()
+            .map(|result| self.attempt(Operation::Copy, || result))
+            .collect()
+    }
+
+    /// Get the algorithm checksums of copied files are computed with, `None` when they aren't
+    /// stored.
+    fn checksum_algorithm(&self) -> Result<Option<&dyn hash::HashAlgorithm>, SyncError> {
+        if !self.options.checksum_xattrs {
+            return Ok(None);
+        }
+
+        self.options
+            .hash_algorithm
+            .as_deref()
+            .map(Some)
+            .ok_or(SyncError::InvalidOptions(
+                "No hash algorithm to compute checksums",
+            ))
+    }
+
+    /// Recreate the symbolic link `from` at `to`, pointing to the same target.
+    fn copy_symlink(&self, from: &Path, to: &Path) -> Result<(), SyncError> {
+        self.attempt(Operation::Copy, || {
+            self.check_writable(to)?;
+            let target = fs::read_link(from).map_err(|err| SyncError::io(from, err))?;
+            if self.planned(|| PlannedAction::CreateSymlink {
+                link: to.to_owned(),
+                target: target.clone(),
+            }) {
+                return Ok(());
+            }
+
+            create_symlink(&target, &long_path(from), &long_path(to))
+                .map_err(|err| SyncError::io(to, err))
+        })
+    }
+
+    /// Create a hard link at `link` to the existing file `original`.
+    fn create_hard_link(&self, original: &Path, link: &Path) -> Result<(), SyncError> {
+        self.attempt(Operation::Copy, || {
+            self.check_writable(link)?;
+            if self.planned(|| PlannedAction::CreateHardLink {
+                link: link.to_owned(),
+                original: original.to_owned(),
+            }) {
+                return Ok(());
+            }
+
+            fs::hard_link(long_path(original), long_path(link))
+                .map_err(|err| SyncError::io(link, err))
+        })
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<(), SyncError> {
+        self.attempt(Operation::CreateDir, || {
+            self.check_writable(path)?;
+            if self.planned(|| PlannedAction::CreateDir(path.to_owned())) {
+                return Ok(());
+            }
+
+            fs::create_dir(long_path(path)).map_err(|err| SyncError::io(path, err))?;
+            self.count(|stats| stats.dirs_created += 1);
+            Ok(())
+        })
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<(), SyncError> {
+        self.attempt(Operation::CreateDir, || {
+            self.check_writable(path)?;
+            if self.planned(|| PlannedAction::CreateDir(path.to_owned())) {
+                return Ok(());
+            }
+
+            let missing_dirs = path.ancestors().take_while(|dir| !dir.exists()).count() as u64;
+            fs::create_dir_all(long_path(path)).map_err(|err| SyncError::io(path, err))?;
+            self.count(|stats| stats.dirs_created += missing_dirs);
+            Ok(())
+        })
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<(), SyncError> {
+        self.attempt(Operation::Delete, || {
+            self.check_writable(path)?;
+            if self.planned(|| PlannedAction::Delete(path.to_owned())) {
+                return Ok(());
+            }
+
+            fs::remove_file(long_path(path)).map_err(|err| SyncError::io(path, err))?;
+            self.count(|stats| stats.files_deleted += 1);
+            Ok(())
+        })
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<(), SyncError> {
+        self.attempt(Operation::Delete, || {
+            self.check_writable(path)?;
+            if self.planned(|| PlannedAction::Delete(path.to_owned())) {
+                return Ok(());
+            }
+
+            let (mut files, mut dirs) = (0, 0);
+            for entry in WalkDir::new(path).into_iter().filter_map(Result::ok) {
+                if entry.file_type().is_dir() {
+                    dirs += 1;
+                } else {
+                    files += 1;
+                }
+            }
+
+            fs::remove_dir_all(long_path(path)).map_err(|err| SyncError::io(path, err))?;
+            self.count(|stats| {
+                stats.files_deleted += files;
+                stats.dirs_deleted += dirs;
+            });
+            Ok(())
+        })
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), SyncError> {
+        self.attempt(Operation::Rename, || {
+            self.check_writable(from)?;
+            self.check_writable(to)?;
+            if self.planned(|| PlannedAction::Rename {
+                from: from.to_owned(),
+                to: to.to_owned(),
+            }) {
+                return Ok(());
+            }
+
+            fs::rename(long_path(from), long_path(to)).map_err(|err| SyncError::io(from, err))
+        })
+    }
+
+    fn set_file_times(
+        &self,
+        path: &Path,
+        atime: FileTime,
+        mtime: FileTime,
+    ) -> Result<(), SyncError> {
+        self.attempt(Operation::SetTimes, || {
+            self.check_writable(path)?;
+            if self.planned(|| PlannedAction::SetTimes {
+                path: path.to_owned(),
+                mtime: system_time(mtime),
+            }) {
+                return Ok(());
+            }
+
+            filetime::set_file_times(long_path(path), atime, mtime)
+                .map_err(|err| SyncError::io(path, err))
+        })
+    }
+
+    fn set_permissions(&self, path: &Path, permissions: fs::Permissions) -> Result<(), SyncError> {
+        self.attempt(Operation::SetPermissions, || {
+            self.check_writable(path)?;
+            if self.planned(|| PlannedAction::SetPermissions(path.to_owned())) {
+                return Ok(());
+            }
+
+            fs::set_permissions(long_path(path), permissions)
+                .map_err(|err| SyncError::io(path, err))
+        })
+    }
+
+    /// Store the manifest of the synchronization at the root of `dir`, dry runs leave the
+    /// previous one.
+    fn write_manifest(&self, manifest: &Manifest, dir: &Path) -> Result<(), SyncError> {
+        let path = dir.join(MANIFEST_NAME);
+        self.check_writable(&path)?;
+        if self.dry_run() {
+            return Ok(());
+        }
+
+        manifest.write(dir).map_err(|err| SyncError::io(&path, err))
+    }
+
+    /// Update the statistics of the synchronization.
+    fn count<F>(&self, update: F)
+    where
+        F: FnOnce(&mut SyncStats),
+    {
+        update(&mut self.report.borrow_mut().stats);
+    }
+
+    /// Announce `action` to `SyncOptions::on_action` and the debug log, recording it instead of
+    /// performing it during a dry run. Returns whether it was only recorded.
+    fn planned<F>(&self, action: F) -> bool
+    where
+        F: FnOnce() -> PlannedAction,
+    {
+        if self.planning {
+            self.report.borrow_mut().planned.push(action());
+            return true;
+        }
+
+        if self.options.dry_run
+            || self.options.on_action.is_some()
+            || log::log_enabled!(log::Level::Debug)
+        {
+            let action = action();
+            log::debug!("{:?}", action);
+
+            if let Some(on_action) = &self.options.on_action {
+                on_action(&action);
+            }
+
+            if self.options.dry_run {
+                self.report.borrow_mut().planned.push(action);
+            }
+        }
+
+        self.options.dry_run
+    }
+}
+
+/// Check whether `entry` is hidden, see `SyncOptions::skip_hidden`.
+fn is_hidden(entry: &DirEntry) -> bool {
+    entry.file_name().to_string_lossy().starts_with('.') || has_hidden_attribute(entry)
+}
+
+#[cfg(windows)]
+fn has_hidden_attribute(entry: &DirEntry) -> bool {
+    use std::os::windows::fs::MetadataExt;
+
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+
+    entry
+        .metadata()
+        .is_ok_and(|metadata| metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0)
+}
+
+#[cfg(not(windows))]
+fn has_hidden_attribute(_entry: &DirEntry) -> bool {
+    false
+}
+
+/// Length (in UTF-16 code units) from which paths need the extended-length prefix on Windows.
+#[cfg(windows)]
+const MAX_PATH: usize = 260;
+
+/// Get `path` with the extended-length prefix (`\\?\`) if it's too long for the file system
+/// APIs of Windows, e.g. deep in nested directories, see `MAX_PATH`.
+#[cfg(windows)]
+fn long_path(path: &Path) -> Cow<'_, Path> {
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::{Component, Prefix};
+
+    if path.as_os_str().encode_wide().count() < MAX_PATH || !path.is_absolute() {
+        return Cow::Borrowed(path);
+    }
+
+    let mut components = path.components();
+    let mut prefixed = OsString::from(r"\\?\");
+    match components.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(letter) => prefixed.push(format!("{}:", letter as char)),
+            Prefix::UNC(server, share) => {
+                prefixed.push(r"UNC\");
+                prefixed.push(server);
+                prefixed.push(r"\");
+                prefixed.push(share);
+            }
+            // already extended-length or a device
+            _ => return Cow::Borrowed(path),
+        },
+        _ => return Cow::Borrowed(path),
+    }
+
+    // The prefix turns off the normalization of the path, it's done here instead.
+    let mut names = Vec::new();
+    for component in components {
+        match component {
+            Component::Normal(name) => names.push(name),
+            Component::ParentDir => {
+                names.pop();
+            }
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => (),
+        }
+    }
+    for name in names {
+        prefixed.push(r"\");
+        prefixed.push(name);
+    }
+
+    Cow::Owned(PathBuf::from(prefixed))
+}
+
+/// Get `path` as is, only Windows limits the length of paths.
+#[cfg(not(windows))]
+fn long_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+/// Create a symbolic link at `link` pointing to `target`, like the existing link `original`.
+#[cfg(unix)]
+fn create_symlink(target: &Path, _original: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+/// Create a symbolic link at `link` pointing to `target`, like the existing link `original`.
+#[cfg(windows)]
+fn create_symlink(target: &Path, original: &Path, link: &Path) -> io::Result<()> {
+    // links to directories are a different kind of entry
+    if fs::metadata(original).is_ok_and(|metadata| metadata.is_dir()) {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn create_symlink(_target: &Path, _original: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Symbolic links are not supported on this platform",
+    ))
+}
+
+/// Convert a `FileTime` to a `SystemTime`.
+fn system_time(time: FileTime) -> SystemTime {
+    use std::convert::TryFrom;
+
+    let nanoseconds = Duration::new(0, time.nanoseconds());
+
+    match u64::try_from(time.unix_seconds()) {
+        Ok(seconds) => UNIX_EPOCH + Duration::from_secs(seconds) + nanoseconds,
+        Err(_) => {
+            UNIX_EPOCH - Duration::from_secs(time.unix_seconds().unsigned_abs()) + nanoseconds
+        }
+    }
+}
+
+/// Check whether the content filter asks to skip the file at `path`, recording it if so.
+fn is_filtered_by_content(path: &Path, ctx: &Context) -> Result<bool, SyncError> {
+    let skip = content_filter_skips(path, ctx)?;
+    if skip {
+        ctx.skipped(SkipReason::ContentFilter);
+    }
+
+    Ok(skip)
+}
+
+/// Check whether the content filter asks to skip the file at `path`.
+fn content_filter_skips(path: &Path, ctx: &Context) -> Result<bool, SyncError> {
+    use std::io::Read;
+
+    let filter = match &ctx.options.content_filter {
+        Some(filter) => filter,
+        None => return Ok(false),
+    };
+
+    let mut header = Vec::with_capacity(filter.header_len);
+    fs::File::open(path)
+        .and_then(|file| file.take(filter.header_len as u64).read_to_end(&mut header))
+        .map_err(|err| SyncError::io(path, err))?;
+
+    Ok((filter.skip)(&header))
+}
+
+/// Suffix of the entries staged by `synchronize_file_with_dir`.
+const STAGED_SUFFIX: &str = ".keepkeeping-staged";
+/// Suffix of the files being copied before replacing their target.
+const PARTIAL_SUFFIX: &str = ".keepkeeping-partial";
+
+const DIR1_NOT_SYMLINK_ID: u8 = 0;
+// const DIR2_NOT_SYMLINK_ID: u8 = 1;
+const DIR1_SYMLINK_ID: u8 = 2;
+const DIR2_SYMLINK_ID: u8 = 3;
+
+fn id_and_relative_path_from_dir_entry(
+    entry: walkdir::Result<DirEntry>,
+    base_path: &Path,
+    dir_id_no_symlink: u8,
+    ctx: &Context,
+) -> Result<(u8, PathBuf, fs::Metadata), ErrorHandlingType> {
+    match entry {
+        Err(err) => Err(ctx.on_err(err)),
+        Ok(entry) => {
+            if is_special_file(&entry, ctx) {
+                return Err(ErrorHandlingType::Ignore);
+            }
+
+            // followed links are synchronized like what they point to
+            let is_symlink = entry.path_is_symlink() && !ctx.options.follow_symlinks;
+            let dir_id = if is_symlink {
+                DIR1_SYMLINK_ID
+            } else {
+                DIR1_NOT_SYMLINK_ID
+            } + dir_id_no_symlink;
+            let path: &Path = entry.path();
+
+            macro_rules! some_or_return {
+                ($e:expr) => {
+                    match $e {
+                        Some(x) => x,
+                        None => return Err(ErrorHandlingType::Ignore),
+                    }
+                };
+            }
+
+            let trimmed = some_or_return!(trim_base_path(base_path, path));
+
+            // Symbolic links are followed like `Path::is_file` does, broken ones are kept as is.
+            let metadata = if entry.path_is_symlink() {
+                fs::metadata(path).or_else(|_| entry.metadata())
+            } else {
+                entry.metadata()
+            };
+
+            match metadata {
+                Ok(metadata) => Ok((dir_id, trimmed, metadata)),
+                Err(err) => Err(ctx.on_err(err)),
+            }
+        }
+    }
+}
+
+/// Check whether `regular_files_only` excludes an entry, recording it if so.
+fn is_special_file(entry: &DirEntry, ctx: &Context) -> bool {
+    if !ctx.options.regular_files_only {
+        return false;
+    }
+
+    let file_type = entry.file_type();
+    let reason = if file_type.is_file() || file_type.is_dir() {
+        return false;
+    } else if file_type.is_symlink() {
+        SkipReason::Symlink
+    } else {
+        special_file_reason(file_type)
+    };
+
+    ctx.skipped(reason);
+
+    true
+}
+
+#[cfg(unix)]
+fn special_file_reason(file_type: fs::FileType) -> SkipReason {
+    use std::os::unix::fs::FileTypeExt;
+
+    if file_type.is_fifo() {
+        SkipReason::Fifo
+    } else if file_type.is_socket() {
+        SkipReason::Socket
+    } else if file_type.is_block_device() || file_type.is_char_device() {
+        SkipReason::Device
+    } else {
+        SkipReason::OtherSpecialFile
+    }
+}
+
+#[cfg(not(unix))]
+fn special_file_reason(_file_type: fs::FileType) -> SkipReason {
+    SkipReason::OtherSpecialFile
+}
+
+/// Check that `path` is still of the type it had when it was scanned, another process may
+/// have replaced it since.
+fn check_unchanged_type(path: &Path, scanned: &fs::Metadata) -> Result<(), SyncError> {
+    let type_changed = match fs::metadata(path) {
+        Ok(current) => current.file_type() != scanned.file_type(),
+        // a broken symbolic link is scanned as is
+        Err(_) => fs::symlink_metadata(path)
+            .map_or(true, |current| current.file_type() != scanned.file_type()),
+    };
+
+    if type_changed {
+        Err(SyncError::TypeChangedDuringSync(path.to_owned()))
+    } else {
+        Ok(())
+    }
+}
+
+/// Synchronize 2 directories, merging their files and keeping only their newest versions.
+fn synchronize_dirs(dir1: &Path, dir2: &Path, ctx: &Context) -> Result<(), ()> {
+    let fail = RefCell::from(false);
+
+    macro_rules! id_and_relative_path {
+        ($e:expr, $dir:expr, $id:expr, $ctx:expr) => {
+            match id_and_relative_path_from_dir_entry($e, $dir, $id, $ctx) {
+                Ok(x) => Some(x),
+                Err(handle) => {
+                    use ErrorHandlingType::*;
+
+                    // the entry is skipped either way
+                    match handle {
+                        Fail => *fail.borrow_mut() = true,
+                        Skip | Ignore => (),
+                    };
+
+                    None
+                }
+            }
+        };
+    }
+
+    // Only used in loops, skipping an error moves on to the next entry.
+    macro_rules! handle_error {
+        ($err:expr) => {
+            use ErrorHandlingType::*;
+
+            match ctx.on_err($err) {
+                Fail => return Err(()),
+                Skip => continue,
+                Ignore => (),
+            };
+        };
+    }
+
+    // Walk errors are only handled while iterating over `dir_iterator`.
+    macro_rules! check_walk_errors {
+        () => {
+            if *fail.borrow() {
+                return Err(());
+            }
+        };
+    }
+
+    // Entries staged by an interrupted run are never synchronized, they're recovered once walked.
+    let staged = RefCell::from(Vec::new());
+    // Copies left unfinished by an interrupted run are never synchronized, they're removed.
+    let partial = RefCell::from(Vec::new());
+    // Directories handled as a whole (copied macOS apps, deleted extras), their content isn't
+    // walked.
+    let handled_dirs = RefCell::from(Vec::<PathBuf>::new());
+    let is_walked = |entry: &DirEntry| match unstaged_path(entry.path()) {
+        None if entry.depth() == 1 && entry.file_name() == MANIFEST_NAME => false,
+        None if handled_dirs
+            .borrow()
+            .iter()
+            .any(|app| entry.path().starts_with(app)) =>
+        {
+            false
+        }
+        None if entry.file_type().is_file() && is_partial_path(entry.path()) => {
+            partial.borrow_mut().push(entry.path().to_owned());
+            false
+        }
+        None if ctx.is_excluded(entry) => false,
+        None => {
+            ctx.discovered();
+            true
+        }
+        Some(original) => {
+            staged
+                .borrow_mut()
+                .push((entry.path().to_owned(), original));
+            false
+        }
+    };
+
+    if let Some(rebase) = &ctx.options.rebase {
+        // the rebased directories may not exist yet on one side
+        for rebased_dir in [dir1.join(&rebase.from), dir2.join(&rebase.to)] {
+            if !rebased_dir.exists() && ctx.accepts_changes(&rebased_dir) {
+                if let Err(err) = ctx.create_dir_all(&rebased_dir) {
+                    handle_error!(err);
+                }
+            }
+        }
+    }
+
+    let dir_iterator = ctx
+        .walk(dir1)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(&is_walked)
+        .filter_map(|e| id_and_relative_path!(e, dir1, 0, ctx))
+        .chain(
+            // in push mode, entries only in dir2 are left untouched => don't even list them
+            (ctx.options.direction != Direction::Push)
+                .then(|| {
+                    ctx.walk(dir2)
+                        .min_depth(1)
+                        .into_iter()
+                        .filter_entry(&is_walked)
+                })
+                .into_iter()
+                .flatten()
+                .filter_map(|e| id_and_relative_path!(e, dir2, 1, ctx))
+                // never synchronize the same path twice
+                .filter(|(_, rel_path, _)| {
+                    let rel_path_in_dir1 = match rebased_to_dir1(rel_path, ctx) {
+                        Some(rel_path_in_dir1) => rel_path_in_dir1,
+                        None => return false,
+                    };
+
+                    let path_in_dir1 = dir1.join(&rel_path_in_dir1);
+                    let path_in_dir1 = if ctx.options.case_insensitive {
+                        find_ignoring_case(&path_in_dir1)
+                    } else {
+                        path_in_dir1
+                    };
+
+                    fs::symlink_metadata(path_in_dir1).is_err()
+                        && !ctx.options.sanitize.as_ref().is_some_and(|policy| {
+                            policy
+                                .unsanitize_path(rel_path)
+                                .and_then(|original| rebased_to_dir1(&original, ctx))
+                                .is_some_and(|original| dir1.join(original).exists())
+                        })
+                }),
+        );
+
+    let mut deleted_dirs = Vec::new();
+    // entries only in dir2 when mirroring, deleted once walked
+    let mut extras = Vec::new();
+    // new files, only copied once walked in parallel mode
+    let mut pending_copies = Vec::new();
+    // new directories, given the time of their source once populated
+    let mut created_dirs = Vec::new();
+
+    for (dir_id, relative_path, metadata_in_dir) in dir_iterator {
+        check_walk_errors!();
+
+        if ctx.is_cancelled() {
+            return Err(());
+        }
+
+        // `path_in_dir` is where the element is in the scanned directory,
+        // `path_in_other_dir` is where the element should be in the other directory.
+        let (path_in_dir, path_in_other_dir, relative_path_in_dir1) =
+            if dir_id == DIR1_SYMLINK_ID || dir_id == DIR1_NOT_SYMLINK_ID {
+                let relative_path_in_dir2 = match &ctx.options.rebase {
+                    Some(rebase) => match rebase.apply(&relative_path) {
+                        Some(relative_path_in_dir2) => relative_path_in_dir2,
+                        // outside of the rebased directory
+                        None => continue,
+                    },
+                    None => relative_path.clone(),
+                };
+
+                match ctx.destination_path(dir2, &relative_path_in_dir2) {
+                    Ok(path_in_dir2) if ctx.options.case_insensitive => (
+                        dir1.join(&relative_path),
+                        find_ignoring_case(&path_in_dir2),
+                        relative_path,
+                    ),
+                    Ok(path_in_dir2) => (dir1.join(&relative_path), path_in_dir2, relative_path),
+                    Err(err) => {
+                        handle_error!(err);
+                        continue;
+                    }
+                }
+            } else {
+                // entries outside of the rebased directory are already filtered out, sanitized
+                // names are given back their original one
+                let relative_path_in_dir1 = ctx
+                    .options
+                    .sanitize
+                    .as_ref()
+                    .and_then(|policy| policy.unsanitize_path(&relative_path))
+                    .and_then(|original| rebased_to_dir1(&original, ctx))
+                    .or_else(|| rebased_to_dir1(&relative_path, ctx))
+                    .unwrap_or_default();
+
+                (
+                    dir2.join(&relative_path),
+                    dir1.join(&relative_path_in_dir1),
+                    relative_path_in_dir1,
+                )
+            };
+
+        if ctx.options.low_memory {
+            // the walk never comes back to the directories it has left
+            finish_left_dirs(
+                &path_in_dir,
+                &path_in_other_dir,
+                &mut extras,
+                &mut deleted_dirs,
+                &mut created_dirs,
+                &mut handled_dirs.borrow_mut(),
+                ctx,
+            )?;
+        }
+
+        ctx.progress(&path_in_dir);
+
+        let in_dir1 = dir_id == DIR1_SYMLINK_ID || dir_id == DIR1_NOT_SYMLINK_ID;
+        if ctx.options.direction == Direction::Mirror && !in_dir1 {
+            // without copying data, only files present on both sides are reconciled
+            if !ctx.options.metadata_only {
+                ctx.decided(Decision::Deleted, &path_in_dir);
+
+                if metadata_in_dir.is_dir() {
+                    handled_dirs.borrow_mut().push(path_in_dir.clone());
+                }
+
+                extras.push(path_in_dir);
+            }
+
+            continue;
+        }
+
+        // Stat each side only once.
+        let link_metadata_in_other_dir = fs::symlink_metadata(&path_in_other_dir).ok();
+        let exists_in_other_dir = link_metadata_in_other_dir.is_some();
+        let is_link_in_other_dir = link_metadata_in_other_dir
+            .as_ref()
+            .is_some_and(|metadata| metadata.file_type().is_symlink());
+        // followed links are synchronized like what they point to
+        let is_symlink_in_other_dir = is_link_in_other_dir && !ctx.options.follow_symlinks;
+
+        // Symbolic links are followed like `Path::is_file` does.
+        let metadata_in_other_dir = match &link_metadata_in_other_dir {
+            Some(_) if is_link_in_other_dir => fs::metadata(&path_in_other_dir).ok(),
+            metadata => metadata.clone(),
+        };
+
+        // Never write through a special file of the other side either.
+        let scanned_metadata_in_other_dir = if ctx.options.follow_symlinks {
+            &metadata_in_other_dir
+        } else {
+            &link_metadata_in_other_dir
+        };
+        if ctx.options.regular_files_only
+            && scanned_metadata_in_other_dir
+                .as_ref()
+                .is_some_and(|metadata| !metadata.is_file() && !metadata.is_dir())
+        {
+            continue;
+        }
+
+        // A symbolic link and an entry which isn't one can't be compared meaningfully.
+        let is_symlink_in_dir = dir_id == DIR1_SYMLINK_ID || dir_id == DIR2_SYMLINK_ID;
+        if exists_in_other_dir
+            && is_symlink_in_dir != is_symlink_in_other_dir
+            && ctx.options.type_mismatch == TypeMismatchPolicy::Report
+        {
+            ctx.report
+                .borrow_mut()
+                .type_conflicts
+                .push((path_in_dir, path_in_other_dir));
+            continue;
+        }
+
+        // Without copying data, only files present on both sides can be reconciled.
+        if ctx.options.metadata_only
+            && !(metadata_in_dir.is_file()
+                && metadata_in_other_dir
+                    .as_ref()
+                    .is_some_and(|metadata| metadata.is_file()))
+        {
+            continue;
+        }
+
+        // Paths that are part of a macOS app are already handled if they exists in both dirs => skip.
+        let is_in_mac_app = if ctx.options.merge_root_bundle {
+            // only apps inside the merged roots are replaced as a whole
+            let scanned_dir = if dir_id == DIR1_SYMLINK_ID || dir_id == DIR1_NOT_SYMLINK_ID {
+                dir1
+            } else {
+                dir2
+            };
+
+            path_in_dir
+                .ancestors()
+                .skip(1)
+                .take_while(|ancestor| *ancestor != scanned_dir)
+                .any(is_mac_app)
+        } else {
+            is_part_of_mac_app(&path_in_dir)
+        };
+
+        if is_in_mac_app && metadata_in_other_dir.is_some() {
+            continue;
+        }
+
+        // Symbolic links are recreated as they are instead of copying what they point to.
+        if is_symlink_in_dir && (is_symlink_in_other_dir || !exists_in_other_dir) {
+            synchronize_symlinks(&path_in_dir, &path_in_other_dir, exists_in_other_dir, ctx)?;
+            continue;
+        }
+
+        if metadata_in_dir.is_file() {
+            // Files rejected by the content filter are never copied, whichever side they are on.
+            match is_filtered_by_content(&path_in_dir, ctx) {
+                Ok(false) => (),
+                Ok(true) => continue,
+                Err(err) => {
+                    handle_error!(err);
+                    continue;
+                }
+            }
+
+            match &metadata_in_other_dir {
+                Some(metadata_in_other_dir) if metadata_in_other_dir.is_file() => {
+                    match is_filtered_by_content(&path_in_other_dir, ctx) {
+                        Ok(false) => (),
+                        Ok(true) => continue,
+                        Err(err) => {
+                            handle_error!(err);
+                            continue;
+                        }
+                    }
+
+                    // `path_in_other_dir` exists and points to a file
+                    // Check timestamps, and overwrite the older with the recent one.
+
+                    synchronize_files(
+                        &path_in_dir,
+                        &metadata_in_dir,
+                        &path_in_other_dir,
+                        metadata_in_other_dir,
+                        ctx,
+                    )?;
+                }
+                Some(metadata_in_other_dir) if metadata_in_other_dir.is_dir() => {
+                    synchronize_file_with_dir(&path_in_dir, &path_in_other_dir, ctx)?;
+                }
+                _ if ctx.was_deleted(&relative_path_in_dir1, &metadata_in_dir) => {
+                    // path deleted from other dir since the last synchronization
+
+                    ctx.decided(Decision::Deleted, &path_in_dir);
+                    if let Err(err) = ctx.remove_file(&path_in_dir) {
+                        handle_error!(err);
+                    }
+                }
+                _ => {
+                    // path does not exist in other dir
+
+                    if ctx.is_too_large(&path_in_dir, &metadata_in_dir) {
+                        continue;
+                    }
+
+                    ctx.decided(Decision::CopiedMissing, &path_in_dir);
+
+                    if let Some(parent) = path_in_other_dir.parent() {
+                        // a new file inside a directory deleted since the last synchronization
+                        if ctx.manifest.is_some() && !parent.exists() {
+                            if let Err(err) = ctx.create_dir_all(parent) {
+                                handle_error!(err);
+                                continue;
+                            }
+                        }
+                    }
+
+                    let time = FileTime::from_last_modification_time(&metadata_in_dir);
+
+                    if let Err(err) = check_unchanged_type(&path_in_dir, &metadata_in_dir) {
+                        handle_error!(err);
+                    } else if ctx.options.parallel && !ctx.options.low_memory {
+                        pending_copies.push(((path_in_dir, path_in_other_dir), time));
+                    } else if let Err(err) = ctx.copy(&path_in_dir, &path_in_other_dir) {
+                        handle_error!(err);
+                    } else if let Err(err) = ctx.set_file_times(&path_in_other_dir, time, time) {
+                        handle_error!(err);
+                    } else if path_in_other_dir.file_name() != path_in_dir.file_name() {
+                        ctx.renamed(&path_in_dir, &path_in_other_dir);
+                    }
+                }
+            }
+        } else if metadata_in_other_dir.is_none()
+            && ctx.was_deleted(&relative_path_in_dir1, &metadata_in_dir)
+        {
+            // path_in_dir: dir, path_in_other_dir: deleted since the last synchronization
+            // => removed once its content has been synchronized, unless something new is inside
+
+            ctx.decided(Decision::Deleted, &path_in_dir);
+            deleted_dirs.push(path_in_dir);
+        } else if metadata_in_other_dir.is_none() {
+            // path_in_dir: dir, path_in_other_dir: nothing
+
+            ctx.decided(Decision::CreatedDir, &path_in_dir);
+            if let Err(err) = check_unchanged_type(&path_in_dir, &metadata_in_dir) {
+                handle_error!(err);
+            } else if let Err(err) = ctx.create_dir(&path_in_other_dir) {
+                handle_error!(err);
+            } else {
+                let time = FileTime::from_last_modification_time(&metadata_in_dir);
+
+                // a macOS app is copied as a whole instead of entry by entry
+                if !is_in_mac_app && is_mac_app(&path_in_dir) {
+                    copy_dir(&path_in_dir, &path_in_other_dir, time, ctx)?;
+                    handled_dirs.borrow_mut().push(path_in_dir.clone());
+                } else {
+                    created_dirs.push((path_in_other_dir.clone(), time));
+                }
+
+                if path_in_other_dir.file_name() != path_in_dir.file_name() {
+                    ctx.renamed(&path_in_dir, &path_in_other_dir);
+                }
+            }
+        } else if metadata_in_other_dir.is_some_and(|metadata| metadata.is_file()) {
+            // path_in_dir: dir, path_in_other_dir: file
+
+            synchronize_file_with_dir(&path_in_other_dir, &path_in_dir, ctx)?;
+        } else if is_mac_app(&path_in_dir) {
+            // path_in_dir: dir (macOS app), path_in_other_dir: dir (macOS app)
+
+            synchronize_dirs_replace(&path_in_dir, &path_in_other_dir, ctx)?;
+        } // else path_in_dir: dir, path_in_other_dir: dir => ignore.
+    }
+
+    check_walk_errors!();
+
+    // every directory has been created by now, the copies don't depend on each other anymore
+    let (copies, times): (Vec<_>, Vec<_>) = pending_copies.into_iter().unzip();
+    let results = ctx.copy_many(&copies);
+
+    for (((from, to), time), result) in copies.iter().zip(times).zip(results) {
+        if let Err(err) = result.and_then(|_| ctx.set_file_times(to, time, time)) {
+            handle_error!(err);
+        } else if to.file_name() != from.file_name() {
+            ctx.renamed(from, to);
+        }
+    }
+
+    for extra in extras {
+        if let Err(err) = remove_extra(&extra, ctx) {
+            handle_error!(err);
+        }
+    }
+
+    // deepest directories first
+    for deleted_dir in deleted_dirs.iter().rev() {
+        if let Err(err) = remove_deleted_dir(deleted_dir, ctx) {
+            handle_error!(err);
+        }
+    }
+
+    for partial_path in partial.take() {
+        if let Err(err) = ctx.remove_file(&partial_path) {
+            handle_error!(err);
+        }
+    }
+
+    for (staged_path, original_path) in staged.take() {
+        if let Err(err) = recover_staged(&staged_path, &original_path, ctx) {
+            handle_error!(err);
+        }
+    }
+
+    // Filling a directory changes its time, which is only set once nothing is left to put in
+    // it. Directories are created before their content, so the deepest ones come first here.
+    for (created_dir, time) in created_dirs.iter().rev() {
+        if let Err(err) = ctx.set_file_times(created_dir, *time, *time) {
+            handle_error!(err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Finish what waits for the walk to leave directories before reaching `path_in_dir`, see
+/// `SyncOptions::low_memory`.
+///
+/// `path_in_other_dir` is where `path_in_dir` should be in the other directory.
+fn finish_left_dirs(
+    path_in_dir: &Path,
+    path_in_other_dir: &Path,
+    extras: &mut Vec<PathBuf>,
+    deleted_dirs: &mut Vec<PathBuf>,
+    created_dirs: &mut Vec<(PathBuf, FileTime)>,
+    handled_dirs: &mut Vec<PathBuf>,
+    ctx: &Context,
+) -> Result<(), ()> {
+    // Only used in loops, skipping an error moves on to the next directory.
+    macro_rules! handle_error {
+        ($err:expr) => {
+            use ErrorHandlingType::*;
+
+            match ctx.on_err($err) {
+                Fail => return Err(()),
+                Skip | Ignore => continue,
+            };
+        };
+    }
+
+    // Finished for every previous entry, only the directories containing the previous one are
+    // left, outermost first: the ones containing this entry come first.
+    handled_dirs.retain(|handled_dir| path_in_dir.starts_with(handled_dir));
+
+    let current = extras
+        .iter()
+        .take_while(|extra| path_in_dir.starts_with(extra))
+        .count();
+    for extra in extras.drain(current..) {
+        if let Err(err) = remove_extra(&extra, ctx) {
+            handle_error!(err);
+        }
+    }
+
+    let current = deleted_dirs
+        .iter()
+        .take_while(|deleted_dir| path_in_dir.starts_with(deleted_dir))
+        .count();
+    for deleted_dir in deleted_dirs.drain(current..).rev() {
+        if let Err(err) = remove_deleted_dir(&deleted_dir, ctx) {
+            handle_error!(err);
+        }
+    }
+
+    let current = created_dirs
+        .iter()
+        .take_while(|(created_dir, _)| path_in_other_dir.starts_with(created_dir))
+        .count();
+    for (created_dir, time) in created_dirs.drain(current..).rev() {
+        if let Err(err) = ctx.set_file_times(&created_dir, time, time) {
+            handle_error!(err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove an entry only in the second directory when mirroring.
+fn remove_extra(extra: &Path, ctx: &Context) -> Result<(), SyncError> {
+    // symbolic links are deleted, not what they point to
+    let is_dir = fs::symlink_metadata(extra).is_ok_and(|metadata| metadata.is_dir());
+
+    if is_dir {
+        ctx.remove_dir_all(extra)
+    } else {
+        ctx.remove_file(extra)
+    }
+}
+
+/// Remove a directory deleted on the other side since the last synchronization, unless
+/// something is left inside.
+fn remove_deleted_dir(deleted_dir: &Path, ctx: &Context) -> Result<(), SyncError> {
+    let is_empty = fs::read_dir(deleted_dir)
+        .map(|mut entries| entries.next().is_none())
+        .map_err(|err| SyncError::io(deleted_dir, err))?;
+
+    if is_empty {
+        ctx.remove_dir_all(deleted_dir)
+    } else {
+        Ok(())
+    }
+}
+
+/// Find the entry matching `path` whatever the case of its names, `path` itself if there's none,
+/// see `SyncOptions::case_insensitive`.
+fn find_ignoring_case(path: &Path) -> PathBuf {
+    if fs::symlink_metadata(path).is_ok() {
+        return path.to_owned();
+    }
+
+    let (parent, name) = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => (find_ignoring_case(parent), name),
+        _ => return path.to_owned(),
+    };
+    let folded_name = name.to_string_lossy().to_lowercase();
+
+    fs::read_dir(&parent)
+        .ok()
+        .and_then(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .find(|entry| entry.file_name().to_string_lossy().to_lowercase() == folded_name)
+        })
+        .map_or_else(|| parent.join(name), |entry| entry.path())
+}
+
+/// Get the relative path in the first directory matching `relative_path` in the second one,
+/// `None` if it's outside of the directory the first one is rebased to.
+fn rebased_to_dir1(relative_path: &Path, ctx: &Context) -> Option<PathBuf> {
+    match &ctx.options.rebase {
+        Some(rebase) => rebase.invert(relative_path),
+        None => Some(relative_path.to_owned()),
+    }
+}
+
+/// Record the entries of 2 synchronized directories in their manifests, so that the next
+/// synchronization can tell deleted entries apart from new ones.
+fn record_manifest(dir1: &Path, dir2: &Path, ctx: &Context) -> Result<(), ()> {
+    macro_rules! handle_error {
+        ($err:expr) => {
+            use ErrorHandlingType::*;
+
+            match ctx.on_err($err) {
+                Fail => return Err(()),
+                Skip | Ignore => return Ok(()),
+            }
+        };
+    }
+
+    let mut manifest = Manifest::default();
+
+    for entry in ctx
+        .walk(dir1)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|entry| {
+            !(entry.depth() == 1 && entry.file_name() == MANIFEST_NAME)
+                && unstaged_path(entry.path()).is_none()
+                && !is_partial_path(entry.path())
+                && ctx.exclusion_reason(entry).is_none()
+        })
+    {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                handle_error!(err);
+            }
+        };
+
+        // Symbolic links are followed like when synchronizing, broken ones are kept as is.
+        let metadata = match fs::metadata(entry.path()).or_else(|_| entry.metadata()) {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                handle_error!(err);
+            }
+        };
+
+        if let Ok(relative_path) = entry.path().strip_prefix(dir1) {
+            manifest.insert(relative_path, &metadata);
+        }
+    }
+
+    for dir in [dir1, dir2] {
+        if let Err(err) = ctx.write_manifest(&manifest, dir) {
+            handle_error!(err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that the entries of `path1` and `path2` match once synchronized, see
+/// `SyncOptions::verify`.
+fn verify(path1: &Path, path2: &Path, ctx: &Context) -> Result<(), ()> {
+    // Only used in loops, a skipped mismatch moves on to the next entry.
+    macro_rules! handle_error {
+        ($err:expr) => {
+            use ErrorHandlingType::*;
+
+            match ctx.on_err($err) {
+                Fail => return Err(()),
+                Skip | Ignore => continue,
+            };
+        };
+    }
+
+    if !path1.is_dir() || !path2.is_dir() {
+        // a file replacing a directory (or the other way around) is checked like any pair of
+        // entries
+        return match verify_entry(path1, path2, ctx) {
+            Ok(()) => Ok(()),
+            Err(err) => match ctx.on_err(err) {
+                ErrorHandlingType::Fail => Err(()),
+                ErrorHandlingType::Skip | ErrorHandlingType::Ignore => Ok(()),
+            },
+        };
+    }
+
+    if ctx.options.flatten || ctx.options.rebase.is_some() {
+        ctx.warn("Verification is skipped when flattening or rebasing".to_owned());
+        return Ok(());
+    }
+
+    // Entries only in the second directory are expected when pushing.
+    let sides = match ctx.options.direction {
+        Direction::Push => &[(path1, path2)][..],
+        _ => &[(path1, path2), (path2, path1)][..],
+    };
+
+    for &(dir, other_dir) in sides {
+        for entry in ctx
+            .walk(dir)
+            .min_depth(1)
+            .into_iter()
+            .filter_entry(|entry| {
+                !(entry.depth() == 1 && entry.file_name() == MANIFEST_NAME)
+                    && unstaged_path(entry.path()).is_none()
+                    && !is_partial_path(entry.path())
+                    && ctx.exclusion_reason(entry).is_none()
+            })
+        {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    handle_error!(err);
+                }
+            };
+
+            let relative_path = match entry.path().strip_prefix(dir) {
+                Ok(relative_path) => relative_path,
+                Err(_) => continue,
+            };
+            let other_path = match other_side_path(relative_path, dir == path1, other_dir, ctx) {
+                Some(other_path) => other_path,
+                // never copied in the first place
+                None => continue,
+            };
+
+            if let Err(err) = verify_entry(entry.path(), &other_path, ctx) {
+                handle_error!(err);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Write `source` into `target`, see `synchronize_into`.
+#[cfg(feature = "tar")]
+fn export_dir(source: &Path, target: &mut TarArchive, ctx: &Context) -> Result<(), ()> {
+    // Only used in loops, a skipped entry is left as it is in the archive.
+    macro_rules! handle_error {
+        ($err:expr) => {
+            use ErrorHandlingType::*;
+
+            match ctx.on_err($err) {
+                Fail => return Err(()),
+                Skip | Ignore => continue,
+            };
+        };
+    }
+
+    let mut extras: HashMap<PathBuf, ArchiveEntry> = target
+        .list()
+        .into_iter()
+        .map(|entry| (entry.path.clone(), entry))
+        .collect();
+
+    for entry in ctx
+        .walk(source)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|entry| {
+            unstaged_path(entry.path()).is_none()
+                && !is_partial_path(entry.path())
+                && ctx.exclusion_reason(entry).is_none()
+                && !is_special_file(entry, ctx)
+        })
+    {
+        if ctx.is_cancelled() {
+            return Err(());
+        }
+
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                handle_error!(err);
+            }
+        };
+        let relative_path = match entry.path().strip_prefix(source) {
+            Ok(relative_path) => relative_path,
+            Err(_) => continue,
+        };
+        let existing = extras.remove(relative_path);
+
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                handle_error!(err);
+            }
+        };
+        let mtime = match metadata.modified() {
+            Ok(mtime) => mtime,
+            Err(err) => {
+                handle_error!(SyncError::io(entry.path(), err));
+            }
+        };
+
+        // symbolic links which aren't followed can't be written
+        if !metadata.is_dir() && !metadata.is_file() {
+            continue;
+        }
+
+        if let Some(existing) = &existing {
+            let up_to_date = if metadata.is_dir() {
+                existing.is_dir
+            } else {
+                !existing.is_dir
+                    && existing.size == metadata.len()
+                    && same_archive_time(mtime, existing.mtime, ctx.options.time_tolerance)
+            };
+
+            if up_to_date {
+                ctx.decided(Decision::AlreadyInSync, entry.path());
+                continue;
+            }
+        }
+
+        if metadata.is_file() {
+            if ctx.is_too_large(entry.path(), &metadata) {
+                continue;
+            }
+            match is_filtered_by_content(entry.path(), ctx) {
+                Ok(true) => continue,
+                Ok(false) => (),
+                Err(err) => {
+                    handle_error!(err);
+                }
+            }
+        }
+
+        // a file can't replace a directory (or the other way around) without removing it
+        if existing
+            .as_ref()
+            .is_some_and(|existing| existing.is_dir != metadata.is_dir())
+        {
+            if !ctx.planned(|| PlannedAction::Delete(relative_path.to_owned())) {
+                target.remove(relative_path);
+            }
+            extras.retain(|path, _| !path.starts_with(relative_path));
+        }
+
+        if metadata.is_dir() {
+            ctx.decided(Decision::CreatedDir, entry.path());
+            if !ctx.planned(|| PlannedAction::CreateDir(relative_path.to_owned())) {
+                target.create_dir(relative_path, mtime);
+                ctx.count(|stats| stats.dirs_created += 1);
+            }
+        } else {
+            match existing {
+                Some(_) => ctx.decided(Decision::CopiedNewer, entry.path()),
+                None => ctx.decided(Decision::CopiedMissing, entry.path()),
+            }
+
+            if !ctx.planned(|| PlannedAction::Copy {
+                from: entry.path().to_owned(),
+                to: relative_path.to_owned(),
+            }) {
+                let written = fs::File::open(long_path(entry.path()))
+                    .map_err(|err| SyncError::io(entry.path(), err))
+                    .and_then(|mut file| target.write(relative_path, &mut file, mtime));
+                if let Err(err) = written {
+                    handle_error!(err);
+                }
+                ctx.count(|stats| {
+                    stats.files_copied += 1;
+                    stats.bytes_copied += metadata.len();
+                });
+            }
+        }
+    }
+
+    if ctx.options.direction == Direction::Mirror {
+        let mut extras: Vec<_> = extras.into_values().collect();
+        extras.sort_by(|entry1, entry2| entry1.path.cmp(&entry2.path));
+
+        // the content of a removed directory goes along with it
+        let mut removed_dirs = Vec::<PathBuf>::new();
+        for extra in extras {
+            if removed_dirs.iter().any(|dir| extra.path.starts_with(dir)) {
+                continue;
+            }
+
+            ctx.decided(Decision::Deleted, &extra.path);
+            if !ctx.planned(|| PlannedAction::Delete(extra.path.clone())) {
+                target.remove(&extra.path);
+                ctx.count(|stats| {
+                    if extra.is_dir {
+                        stats.dirs_deleted += 1;
+                    } else {
+                        stats.files_deleted += 1;
+                    }
+                });
+            }
+
+            if extra.is_dir {
+                removed_dirs.push(extra.path);
+            }
+        }
+    }
+
+    if ctx.dry_run() {
+        return Ok(());
+    }
+
+    target.finish().or_else(|err| match ctx.on_err(err) {
+        ErrorHandlingType::Fail => Err(()),
+        ErrorHandlingType::Skip | ErrorHandlingType::Ignore => Ok(()),
+    })
+}
+
+/// Whether a file written into an archive with the modification time `source` still has it,
+/// archives storing whole seconds rounding it down.
+#[cfg(feature = "tar")]
+fn same_archive_time(source: SystemTime, stored: SystemTime, tolerance: Duration) -> bool {
+    let difference = source
+        .duration_since(stored)
+        .unwrap_or_else(|err| err.duration());
+    let whole_seconds = stored
+        .duration_since(UNIX_EPOCH)
+        .is_ok_and(|since_epoch| since_epoch.subsec_nanos() == 0);
+
+    difference <= tolerance
+        || (whole_seconds && source > stored && difference < Duration::from_secs(1))
+}
+
+/// List the differences between `path1` and `path2`, see `diff`.
+fn diff_paths(path1: &Path, path2: &Path, ctx: &Context) -> Result<Vec<DiffEntry>, ()> {
+    // Only used in loops, a skipped entry is left out of the differences.
+    macro_rules! handle_error {
+        ($err:expr) => {
+            use ErrorHandlingType::*;
+
+            match ctx.on_err($err) {
+                Fail => return Err(()),
+                Skip | Ignore => continue,
+            };
+        };
+    }
+
+    if !path1.is_dir() || !path2.is_dir() {
+        return match diff_status(path1, path2, ctx) {
+            Ok(status) => Ok(status
+                .map(|status| DiffEntry {
+                    path: PathBuf::new(),
+                    status,
+                })
+                .into_iter()
+                .collect()),
+            Err(err) => match ctx.on_err(err) {
+                ErrorHandlingType::Fail => Err(()),
+                ErrorHandlingType::Skip | ErrorHandlingType::Ignore => Ok(Vec::new()),
+            },
+        };
+    }
+
+    let mut differences = Vec::new();
+
+    // Entries on both sides are compared while walking the first directory, the second one is
+    // only walked for the entries it alone has.
+    for &(dir, other_dir) in &[(path1, path2), (path2, path1)] {
+        let from_first = dir == path1;
+        let mut entries = ctx
+            .walk(dir)
+            .min_depth(1)
+            .into_iter()
+            .filter_entry(|entry| {
+                !(entry.depth() == 1 && entry.file_name() == MANIFEST_NAME)
+                    && unstaged_path(entry.path()).is_none()
+                    && !is_partial_path(entry.path())
+                    && ctx.exclusion_reason(entry).is_none()
+                    && !is_special_file(entry, ctx)
+            });
+
+        while let Some(entry) = entries.next() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) => {
+                    handle_error!(err);
+                }
+            };
+
+            let relative_path = match entry.path().strip_prefix(dir) {
+                Ok(relative_path) => relative_path,
+                Err(_) => continue,
+            };
+            let other_path = match other_side_path(relative_path, from_first, other_dir, ctx) {
+                Some(other_path) => other_path,
+                None => continue,
+            };
+
+            let status = if from_first {
+                diff_status(entry.path(), &other_path, ctx)
+            } else {
+                diff_status(&other_path, entry.path(), ctx)
+            };
+            let status = match status {
+                Ok(Some(status)) => status,
+                Ok(None) => continue,
+                Err(err) => {
+                    handle_error!(err);
+                }
+            };
+
+            // the content of a directory is only compared with the content of another one
+            if entry.file_type().is_dir() {
+                entries.skip_current_dir();
+            }
+
+            if from_first || status == DiffStatus::OnlyInSecond {
+                differences.push(DiffEntry {
+                    path: relative_path.to_owned(),
+                    status,
+                });
+            }
+        }
+    }
+
+    differences.sort_by(|entry1, entry2| entry1.path.cmp(&entry2.path));
+
+    Ok(differences)
+}
+
+/// Tell how the entries at `path1` and `path2` differ, `None` for directories on both sides
+/// and entries which are never synchronized (e.g. special files, filtered files).
+fn diff_status(path1: &Path, path2: &Path, ctx: &Context) -> Result<Option<DiffStatus>, SyncError> {
+    let metadata = |path| match entry_metadata(path, ctx) {
+        Ok(metadata) => Ok(Some(metadata)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(SyncError::io(path, err)),
+    };
+    let (metadata1, metadata2) = match (metadata(path1)?, metadata(path2)?) {
+        (Some(metadata1), Some(metadata2)) => (metadata1, metadata2),
+        (Some(_), None) => return Ok(Some(DiffStatus::OnlyInFirst)),
+        (None, Some(_)) => return Ok(Some(DiffStatus::OnlyInSecond)),
+        (None, None) => return Ok(None),
+    };
+
+    let is_synchronized = |file_type: fs::FileType| {
+        file_type.is_file() || file_type.is_dir() || file_type.is_symlink()
+    };
+    let (type1, type2) = (metadata1.file_type(), metadata2.file_type());
+    if !is_synchronized(type1) || !is_synchronized(type2) {
+        return Ok(None);
+    }
+    if type1.is_dir() != type2.is_dir() || type1.is_symlink() != type2.is_symlink() {
+        return Ok(Some(DiffStatus::TypeMismatch));
+    }
+    if type1.is_dir() {
+        return Ok(None);
+    }
+
+    if type1.is_symlink() {
+        let target = |path| fs::read_link(path).map_err(|err| SyncError::io(path, err));
+        if target(path1)? == target(path2)? {
+            return Ok(Some(DiffStatus::Identical));
+        }
+    } else if content_filter_skips(path1, ctx)? || content_filter_skips(path2, ctx)? {
+        return Ok(None);
+    }
+
+    // links are only told apart by their time
+    let mode = if type1.is_symlink() {
+        CompareMode::MTime
+    } else {
+        ctx.options.compare
+    };
+    let comparison = compare::compare(
+        path1,
+        &metadata1,
+        path2,
+        &metadata2,
+        mode,
+        ctx.options.compare_time_field,
+        ctx.options.time_tolerance,
+    )?;
+
+    Ok(Some(match comparison {
+        Comparison::Equal => DiffStatus::Identical,
+        Comparison::FirstNewer => DiffStatus::NewerInFirst,
+        Comparison::SecondNewer => DiffStatus::NewerInSecond,
+        Comparison::DiffersButEqualMtime => DiffStatus::Different,
+    }))
+}
+
+/// Get the path matching `relative_path` in `other_dir`, `None` if the entry can't have one
+/// (e.g. a name which can't be sanitized).
+fn other_side_path(
+    relative_path: &Path,
+    from_first: bool,
+    other_dir: &Path,
+    ctx: &Context,
+) -> Option<PathBuf> {
+    let other_path = match (from_first, &ctx.options.sanitize) {
+        (true, _) => ctx.destination_path(other_dir, relative_path).ok()?,
+        (false, Some(policy)) => other_dir.join(
+            policy
+                .unsanitize_path(relative_path)
+                .unwrap_or_else(|| relative_path.to_owned()),
+        ),
+        (false, None) => other_dir.join(relative_path),
+    };
+
+    if ctx.options.case_insensitive {
+        Some(find_ignoring_case(&other_path))
+    } else {
+        Some(other_path)
+    }
+}
+
+/// Check that `other_path` matches the entry at `path`, entries which are never synchronized
+/// (e.g. special files, filtered files) matching anything.
+fn verify_entry(path: &Path, other_path: &Path, ctx: &Context) -> Result<(), SyncError> {
+    let mismatch = || SyncError::VerificationFailed {
+        path: other_path.to_owned(),
+    };
+
+    // entries left as they are on purpose
+    let report = ctx.report.borrow();
+    if report
+        .readonly_skipped
+        .iter()
+        .any(|skipped| skipped == path || skipped == other_path)
+        || report
+            .type_conflicts
+            .iter()
+            .any(|(path1, path2)| path.starts_with(path1) || path.starts_with(path2))
+    {
+        return Ok(());
+    }
+    drop(report);
+
+    let other_metadata = entry_metadata(other_path, ctx);
+    let metadata = entry_metadata(path, ctx).map_err(|err| SyncError::io(path, err))?;
+
+    if metadata.file_type().is_symlink() {
+        return match other_metadata {
+            Ok(_) => Ok(()),
+            Err(_) if ctx.options.regular_files_only => Ok(()),
+            Err(_) => Err(mismatch()),
+        };
+    }
+
+    if metadata.is_dir() {
+        return match other_metadata {
+            Ok(other_metadata) if other_metadata.is_dir() => Ok(()),
+            _ => Err(mismatch()),
+        };
+    }
+
+    if !metadata.is_file() {
+        return Ok(());
+    }
+
+    // files skipped while synchronizing can't match, nor even exist on the other side
+    let too_large = |metadata: &fs::Metadata| {
+        ctx.options
+            .max_file_size
+            .is_some_and(|max_file_size| metadata.len() > max_file_size)
+    };
+    if too_large(&metadata) || content_filter_skips(path, ctx)? {
+        return Ok(());
+    }
+
+    let other_metadata = match other_metadata {
+        Ok(other_metadata) if other_metadata.is_file() => other_metadata,
+        _ => return Err(mismatch()),
+    };
+
+    if too_large(&other_metadata) || content_filter_skips(other_path, ctx)? {
+        return Ok(());
+    }
+
+    if other_metadata.len() != metadata.len()
+        || (ctx.options.compare == CompareMode::Content
+            && !compare::same_content(path, other_path)?)
+    {
+        return Err(mismatch());
+    }
+
+    Ok(())
+}
+
+/// Read the metadata of `path`, symbolic links being followed like when synchronizing, broken
+/// ones being kept as is.
+fn entry_metadata(path: &Path, ctx: &Context) -> io::Result<fs::Metadata> {
+    if ctx.options.follow_symlinks {
+        fs::metadata(path).or_else(|_| fs::symlink_metadata(path))
+    } else {
+        fs::symlink_metadata(path)
+    }
+}
+
+/// Give every directory of `target` the modification time of the matching directory of
+/// `source`, only for pushing and mirroring.
+///
+/// Directories are walked bottom-up since setting the time of a directory doesn't change the
+/// time of its parent.
+fn copy_dir_times(source: &Path, target: &Path, ctx: &Context) -> Result<(), ()> {
+    // Only used in loops, skipping an error moves on to the next entry.
+    macro_rules! handle_error {
+        ($err:expr) => {
+            use ErrorHandlingType::*;
+
+            match ctx.on_err($err) {
+                Fail => return Err(()),
+                Skip | Ignore => continue,
+            };
+        };
+    }
+
+    for entry in ctx.walk(source).contents_first(true) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                handle_error!(err);
+            }
+        };
+
+        if !entry.file_type().is_dir() {
+            continue;
+        }
+
+        let relative_path = entry.path().strip_prefix(source).unwrap_or(Path::new(""));
+        let target_path = match ctx.destination_path(target, relative_path) {
+            Ok(target_path) if target_path.is_dir() => target_path,
+            Ok(_) => continue,
+            Err(err) => {
+                handle_error!(err);
+            }
+        };
+
+        let time = match entry.metadata() {
+            Ok(metadata) => FileTime::from_last_modification_time(&metadata),
+            Err(err) => {
+                handle_error!(err);
+            }
+        };
+
+        if let Err(err) = ctx.set_file_times(&target_path, time, time) {
+            handle_error!(err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Copy every file of `source` directly into `target` without recreating the directory
+/// structure, only for pushing.
+///
+/// Files having the same name get a `-1`, `-2`... suffix. Entries are walked in order so
+/// that the same file gets the same name every time.
+fn synchronize_dirs_flattened(source: &Path, target: &Path, ctx: &Context) -> Result<(), ()> {
+    // Only used in loops, skipping an error moves on to the next entry.
+    macro_rules! handle_error {
+        ($err:expr) => {
+            use ErrorHandlingType::*;
+
+            match ctx.on_err($err) {
+                Fail => return Err(()),
+                Skip | Ignore => continue,
+            };
+        };
+    }
+
+    let mut used_names = HashSet::new();
+
+    let walk_dir = ctx.walk(source).min_depth(1);
+    // sorting reads whole directories at once but keeps suffixes stable between runs
+    let walk_dir = if ctx.options.low_memory {
+        walk_dir
+    } else {
+        walk_dir.sort_by_file_name()
+    };
+
+    for entry in walk_dir
+        .into_iter()
+        .filter_entry(|entry| !ctx.is_excluded(entry))
+    {
+        if ctx.is_cancelled() {
+            return Err(());
+        }
+
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                handle_error!(err);
+            }
+        };
+
+        if is_special_file(&entry, ctx) {
+            continue;
+        }
+
+        let metadata = match fs::metadata(entry.path()) {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                handle_error!(SyncError::io(entry.path(), err));
+            }
+        };
+
+        if !metadata.is_file() {
+            continue;
+        }
+
+        match is_filtered_by_content(entry.path(), ctx) {
+            Ok(false) => (),
+            Ok(true) => continue,
+            Err(err) => {
+                handle_error!(err);
+            }
+        }
+
+        let name = flattened_name(entry.file_name(), &mut used_names);
+        let target_path = match ctx.destination_path(target, Path::new(&name)) {
+            Ok(target_path) => target_path,
+            Err(err) => {
+                handle_error!(err);
+            }
+        };
+
+        if target_path.file_name() != Some(entry.file_name()) {
+            ctx.renamed(entry.path(), &target_path);
+        }
+
+        match fs::metadata(&target_path) {
+            Ok(target_metadata) if target_metadata.is_file() => {
+                synchronize_files(entry.path(), &metadata, &target_path, &target_metadata, ctx)?
+            }
+            Ok(_) => synchronize_file_with_dir(entry.path(), &target_path, ctx)?,
+            Err(_) if ctx.options.metadata_only => (),
+            Err(_) => {
+                let time = FileTime::from_last_modification_time(&metadata);
+
+                if let Err(err) = ctx.copy(entry.path(), &target_path) {
+                    handle_error!(err);
+                } else if let Err(err) = ctx.set_file_times(&target_path, time, time) {
+                    handle_error!(err);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Get a name not used yet by adding a `-1`, `-2`... suffix to `name` when needed.
+fn flattened_name(name: &OsStr, used_names: &mut HashSet<OsString>) -> OsString {
+    let mut candidate = name.to_owned();
+    let mut counter = 0;
+
+    while !used_names.insert(candidate.clone()) {
+        counter += 1;
+
+        let name = Path::new(name);
+        candidate = name.file_stem().unwrap_or_default().to_owned();
+        candidate.push(format!("-{}", counter));
+
+        if let Some(extension) = name.extension() {
+            candidate.push(".");
+            candidate.push(extension);
+        }
+    }
+
+    candidate
+}
+
+/// Synchronize 2 files, replacing the oldest by the newest.
+///
+/// The metadata of both files is given by the caller which has already read it.
+fn synchronize_files(
+    path1: &Path,
+    metadata1: &fs::Metadata,
+    path2: &Path,
+    metadata2: &fs::Metadata,
+    ctx: &Context,
+) -> Result<(), ()> {
+    macro_rules! handle_error {
+        (use $ctx:ident for $err:ident) => {
+            use ErrorHandlingType::*;
+
+            let handle = $ctx.on_err($err);
+            match handle {
+                Fail => return Err(()),
+                Skip | Ignore => (),
+            }
+        };
+    }
+
+    let time_in_dir = FileTime::from_last_modification_time(metadata1);
+    let time_in_other_dir = FileTime::from_last_modification_time(metadata2);
+
+    let field = ctx.options.compare_time_field;
+    if compare::file_time(metadata1, field).is_none()
+        || compare::file_time(metadata2, field).is_none()
+    {
+        ctx.warn(format!(
+            "{:?} timestamps are not available, comparing modification times instead",
+            field
+        ));
+    }
+
+    let comparison = match compare::compare(
+        path1,
+        metadata1,
+        path2,
+        metadata2,
+        ctx.options.compare,
+        field,
+        ctx.options.time_tolerance,
+    ) {
+        Ok(comparison) => comparison,
+        Err(err) => {
+            handle_error!(use ctx for err);
+            return Ok(());
+        }
+    };
+
+    let comparison = match ctx.options.direction {
+        // the replica follows the first path even when it's older
+        Direction::Mirror if comparison != Comparison::Equal => Comparison::FirstNewer,
+        _ => comparison,
+    };
+
+    let comparison = match comparison {
+        Comparison::FirstNewer | Comparison::SecondNewer | Comparison::DiffersButEqualMtime
+            if ctx.options.conflict_strategy == ConflictStrategy::KeepLargest
+                && ctx.options.direction != Direction::Mirror =>
+        {
+            // the same size falls back to the modification times
+            match metadata1.len().cmp(&metadata2.len()) {
+                std::cmp::Ordering::Greater => Comparison::FirstNewer,
+                std::cmp::Ordering::Less => Comparison::SecondNewer,
+                std::cmp::Ordering::Equal => comparison,
+            }
+        }
+        comparison => comparison,
+    };
+
+    let comparison = match comparison {
+        Comparison::DiffersButEqualMtime
+            if ctx.options.conflict_strategy == ConflictStrategy::KeepBoth
+                && ctx.options.direction == Direction::Bidirectional =>
+        {
+            ctx.decided(Decision::KeptBoth, path1);
+            return keep_both(path1, path2, time_in_dir, time_in_other_dir, ctx);
+        }
+        // same modification time but different contents => the largest file wins
+        Comparison::DiffersButEqualMtime => match metadata1.len().cmp(&metadata2.len()) {
+            std::cmp::Ordering::Greater => Comparison::FirstNewer,
+            std::cmp::Ordering::Less => Comparison::SecondNewer,
+            std::cmp::Ordering::Equal => {
+                ctx.warn(format!(
+                    "'{}' and '{}' differ but have the same modification time and size, \
+                    leaving them as they are",
+                    path1.display(),
+                    path2.display()
+                ));
+                ctx.decided(Decision::LeftAsIs, path1);
+                return Ok(());
+            }
+        },
+        comparison => comparison,
+    };
+
+    let (source_path, source_metadata, target_path, target_metadata, max_time) = match comparison {
+        Comparison::FirstNewer => (path1, metadata1, path2, metadata2, time_in_dir),
+        Comparison::SecondNewer => (path2, metadata2, path1, metadata1, time_in_other_dir),
+        // different contents are already settled
+        Comparison::Equal | Comparison::DiffersButEqualMtime => {
+            // already synchronized
+            ctx.decided(Decision::AlreadyInSync, path1);
+
+            // neither file is the newest, the permissions of the first one win
+            if ctx.options.metadata_only {
+                if !ctx.accepts_changes(path2) {
+                    return Ok(());
+                }
+
+                return reconcile_metadata(path1, metadata1, path2, metadata2, ctx);
+            }
+
+            if ctx.options.direction == Direction::Mirror
+                && metadata1.permissions() != metadata2.permissions()
+            {
+                if let Err(err) = ctx.set_permissions(path2, metadata1.permissions()) {
+                    handle_error!(use ctx for err);
+                }
+            }
+
+            return repair_bit_rot(path1, path2, time_in_dir, ctx);
+        }
+    };
+
+    if !ctx.accepts_changes(target_path) {
+        ctx.decided(Decision::LeftAsIs, source_path);
+        return Ok(());
+    }
+
+    if ctx.options.metadata_only {
+        return reconcile_metadata(
+            source_path,
+            source_metadata,
+            target_path,
+            target_metadata,
+            ctx,
+        );
+    }
+
+    if ctx.is_too_large(source_path, source_metadata) {
+        return Ok(());
+    }
+
+    if let Err(err) = check_unchanged_type(source_path, source_metadata) {
+        use ErrorHandlingType::*;
+
+        return match ctx.on_err(err) {
+            Fail => Err(()),
+            Skip | Ignore => Ok(()),
+        };
+    }
+
+    if let Some(parent_path) = target_path.parent() {
+        if !parent_path.exists() {
+            // should be created before => should never happen
+            if let Err(err) = ctx.create_dir_all(parent_path) {
+                handle_error!(use ctx for err);
+            }
+        }
+    }
+
+    ctx.decided(Decision::CopiedNewer, source_path);
+
+    let target_permissions = target_metadata.permissions();
+    let is_readonly_target = target_permissions.readonly();
+
+    match ctx.options.readonly_dest {
+        ReadonlyPolicy::Skip if is_readonly_target => {
+            ctx.report
+                .borrow_mut()
+                .readonly_skipped
+                .push(target_path.to_owned());
+            return Ok(());
+        }
+        ReadonlyPolicy::Overwrite if is_readonly_target => {
+            if let Err(err) = ctx.set_permissions(target_path, writable(target_permissions.clone()))
+            {
+                handle_error!(use ctx for err);
+            }
+
+            let copied = ctx.copy(source_path, target_path);
+            if let Err(err) = ctx.set_permissions(target_path, target_permissions) {
+                handle_error!(use ctx for err);
+            }
+
+            match copied {
+                Ok(_) => ctx
+                    .report
+                    .borrow_mut()
+                    .readonly_overwritten
+                    .push(target_path.to_owned()),
+                Err(err) => {
+                    handle_error!(use ctx for err);
+                }
+            }
+        }
+        _ => {
+            if let Err(err) = ctx.copy(source_path, target_path) {
+                handle_error!(use ctx for err);
+            }
+        }
+    }
+
+    if let Err(err) = ctx.set_file_times(target_path, max_time, max_time) {
+        handle_error!(use ctx for err);
+    }
+
+    Ok(())
+}
+
+/// Give `target_path` the modification time and permissions of `source_path` without copying
+/// any data, see `SyncOptions::metadata_only`.
+fn reconcile_metadata(
+    source_path: &Path,
+    source_metadata: &fs::Metadata,
+    target_path: &Path,
+    target_metadata: &fs::Metadata,
+    ctx: &Context,
+) -> Result<(), ()> {
+    macro_rules! handle_error {
+        ($err:expr) => {
+            use ErrorHandlingType::*;
+
+            match ctx.on_err($err) {
+                Fail => return Err(()),
+                Skip | Ignore => return Ok(()),
+            }
+        };
+    }
+
+    // the contents can't be the same => only a content synchronization can fix it
+    if source_metadata.len() != target_metadata.len() {
+        ctx.report
+            .borrow_mut()
+            .size_mismatches
+            .push((source_path.to_owned(), target_path.to_owned()));
+        return Ok(());
+    }
+
+    let source_time = FileTime::from_last_modification_time(source_metadata);
+    let mut updated = false;
+
+    if source_time != FileTime::from_last_modification_time(target_metadata) {
+        if let Err(err) = ctx.set_file_times(target_path, source_time, source_time) {
+            handle_error!(err);
+        }
+        updated = true;
+    }
+
+    if source_metadata.permissions() != target_metadata.permissions() {
+        if let Err(err) = ctx.set_permissions(target_path, source_metadata.permissions()) {
+            handle_error!(err);
+        }
+        updated = true;
+    }
+
+    if updated {
+        ctx.report
+            .borrow_mut()
+            .metadata_updated
+            .push(target_path.to_owned());
+    }
+
+    Ok(())
+}
+
+/// Get `permissions` allowing the owner to write.
+fn writable(permissions: fs::Permissions) -> fs::Permissions {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::Permissions::from_mode(permissions.mode() | 0o200)
+    }
+
+    #[cfg(not(unix))]
+    {
+        let mut permissions = permissions;
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        permissions
+    }
+}
+
+/// Keep both versions of 2 conflicting files on both sides, see `ConflictStrategy::KeepBoth`.
+fn keep_both(
+    path1: &Path,
+    path2: &Path,
+    time1: FileTime,
+    time2: FileTime,
+    ctx: &Context,
+) -> Result<(), ()> {
+    macro_rules! unwrap_result {
+        ($e:expr) => {
+            match $e {
+                Ok(x) => x,
+                Err(err) => {
+                    use ErrorHandlingType::*;
+
+                    match ctx.on_err(err) {
+                        Fail => return Err(()),
+                        Skip | Ignore => return Ok(()),
+                    }
+                }
+            }
+        };
+    }
+
+    let (conflict_path1, conflict_path2) = conflict_paths(path1, path2);
+
+    unwrap_result!(ctx.rename(path2, &conflict_path2));
+    unwrap_result!(ctx.copy(&conflict_path2, &conflict_path1));
+    unwrap_result!(ctx.set_file_times(&conflict_path1, time2, time2));
+    unwrap_result!(ctx.copy(path1, path2));
+    unwrap_result!(ctx.set_file_times(path2, time1, time1));
+
+    ctx.warn(format!(
+        "'{}' and '{}' differ but have the same modification time, kept the second one as '{}'",
+        path1.display(),
+        path2.display(),
+        conflict_path2.display()
+    ));
+
+    Ok(())
+}
+
+/// Get where the second version of 2 conflicting files is kept on each side, e.g.
+/// `foo.conflict-<host>.txt` for `foo.txt`, numbered if it's already taken on either side.
+fn conflict_paths(path1: &Path, path2: &Path) -> (PathBuf, PathBuf) {
+    let host = host_name();
+    let file_name = path2.file_name().unwrap_or_default();
+    let stem = Path::new(file_name).file_stem().unwrap_or_default();
+    let extension = Path::new(file_name).extension();
+
+    (0..)
+        .map(|i| {
+            let mut conflict_name = stem.to_owned();
+            conflict_name.push(".conflict-");
+            conflict_name.push(&host);
+            if i > 0 {
+                conflict_name.push(format!("-{}", i));
+            }
+            if let Some(extension) = extension {
+                conflict_name.push(".");
+                conflict_name.push(extension);
+            }
+
+            (
+                path1.with_file_name(&conflict_name),
+                path2.with_file_name(&conflict_name),
+            )
+        })
+        .find(|(conflict_path1, conflict_path2)| {
+            fs::symlink_metadata(conflict_path1).is_err()
+                && fs::symlink_metadata(conflict_path2).is_err()
+        })
+        .unwrap_or_default()
+}
+
+/// Get the name of this computer, `unknown` if it can't be found.
+fn host_name() -> String {
+    #[cfg(target_os = "linux")]
+    let host_name = Some(
+        rustix::system::uname()
+            .nodename()
+            .to_string_lossy()
+            .into_owned(),
+    );
+    #[cfg(not(target_os = "linux"))]
+    let host_name = std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .ok();
+
+    host_name
+        .filter(|name| !name.is_empty() && !name.contains(std::path::is_separator))
+        .unwrap_or_else(|| "unknown".to_owned())
+}
+
+/// Replace synchronized files whose content doesn't match their stored checksum anymore by the
+/// version from the other side, recording them in the report.
+fn repair_bit_rot(path1: &Path, path2: &Path, time: FileTime, ctx: &Context) -> Result<(), ()> {
+    macro_rules! unwrap_result {
+        ($e:expr) => {
+            match $e {
+                Ok(x) => x,
+                Err(err) => {
+                    use ErrorHandlingType::*;
+
+                    match ctx.on_err(err) {
+                        Fail => return Err(()),
+                        Skip | Ignore => return Ok(()),
+                    }
+                }
+            }
+        };
+    }
+
+    let algorithm = match (&ctx.options.hash_algorithm, ctx.options.checksum_xattrs) {
+        (Some(algorithm), true) => algorithm.as_ref(),
+        _ => return Ok(()),
+    };
+
+    let verify = |path| checksum::verify(path, algorithm).map_err(|err| SyncError::io(path, err));
+    let intact1 = unwrap_result!(verify(path1));
+    let intact2 = unwrap_result!(verify(path2));
+
+    let (intact_path, rotten_path) = match (intact1, intact2) {
+        (Some(false), Some(false)) => {
+            // Nothing left to repair from
+            let mut report = ctx.report.borrow_mut();
+            report.bit_rot.push(path1.to_owned());
+            report.bit_rot.push(path2.to_owned());
+            return Ok(());
+        }
+        (Some(false), _) => (path2, path1),
+        (_, Some(false)) => (path1, path2),
+        _ => return Ok(()),
+    };
+
+    ctx.report.borrow_mut().bit_rot.push(rotten_path.to_owned());
+    if !ctx.accepts_changes(rotten_path) {
+        return Ok(());
+    }
+
+    unwrap_result!(ctx.copy(intact_path, rotten_path));
+    unwrap_result!(ctx.set_file_times(rotten_path, time, time));
+
+    Ok(())
+}
+
+/// Synchronize the symbolic link `link` with `other_link`, which may not exist, recreating
+/// the newest one when they point to different targets.
+fn synchronize_symlinks(
+    link: &Path,
+    other_link: &Path,
+    other_exists: bool,
+    ctx: &Context,
+) -> Result<(), ()> {
+    macro_rules! unwrap_result {
+        ($e:expr) => {
+            match $e {
+                Ok(x) => x,
+                Err(err) => {
+                    use ErrorHandlingType::*;
+
+                    match ctx.on_err(err) {
+                        Fail => return Err(()),
+                        Skip | Ignore => return Ok(()),
+                    }
+                }
+            }
+        };
+    }
+
+    // links hold no data
+    if ctx.options.metadata_only {
+        return Ok(());
+    }
+
+    let read_link = |path| fs::read_link(path).map_err(|err| SyncError::io(path, err));
+    let symlink_metadata =
+        |path| fs::symlink_metadata(path).map_err(|err| SyncError::io(path, err));
+
+    let (source, target) = if !other_exists {
+        (link, other_link)
+    } else if unwrap_result!(read_link(link)) == unwrap_result!(read_link(other_link)) {
+        // already synchronized
+        return Ok(());
+    } else {
+        let time = FileTime::from_last_modification_time(&unwrap_result!(symlink_metadata(link)));
+        let other_time =
+            FileTime::from_last_modification_time(&unwrap_result!(symlink_metadata(other_link)));
+
+        // the replica follows the first path, which is the only one walked when mirroring
+        if other_time > time && ctx.options.direction != Direction::Mirror {
+            (other_link, link)
+        } else {
+            (link, other_link)
+        }
+    };
+
+    if !ctx.accepts_changes(target) {
+        return Ok(());
+    }
+
+    if other_exists {
+        unwrap_result!(ctx.remove_file(target));
+    }
+
+    unwrap_result!(ctx.copy_symlink(source, target));
+
+    Ok(())
+}
+
+/// Replace a file by a directory or the other way around, keeping the newest one.
+fn synchronize_file_with_dir(file_path: &Path, dir_path: &Path, ctx: &Context) -> Result<(), ()> {
+    // either way, data would be copied
+    if ctx.options.metadata_only {
+        return Ok(());
+    }
+
+    macro_rules! unwrap_result {
+        ($e:expr) => {
+            match $e {
+                Err(err) => {
+                    use ErrorHandlingType::*;
+
+                    match ctx.on_err(err) {
+                        Fail => return Err(()),
+                        Skip | Ignore => return Ok(()),
+                    }
+                }
+                Ok(x) => x,
+            }
+        };
+    }
+
+    let file_time = FileTime::from_last_modification_time(&unwrap_result!(file_path
+        .metadata()
+        .map_err(|err| SyncError::io(file_path, err))));
+
+    let dir_time = match dir_latest_modification_time(dir_path, ctx) {
+        Ok(x) => x,
+        Err(err) => {
+            use ErrorHandlingType::*;
+
+            return match err {
+                Fail => Err(()),
+                Skip => Ok(()),
+                _ => unreachable!(),
+            };
+        }
+    };
+
+    // The replacement is fully staged next to the replaced entry before removing anything, an
+    // interrupted run is then finished or rolled back by `recover_staged`.
+    if file_time > dir_time {
+        if !ctx.accepts_changes(dir_path) {
+            ctx.decided(Decision::LeftAsIs, file_path);
+            return Ok(());
+        }
+
+        ctx.decided(Decision::ReplacedDirWithFile, dir_path);
+
+        let staged = staged_path(dir_path);
+        unwrap_result!(recover_staged(&staged, dir_path, ctx));
+        unwrap_result!(ctx.copy(file_path, &staged));
+        unwrap_result!(ctx.set_file_times(&staged, file_time, file_time));
+        unwrap_result!(ctx.remove_dir_all(dir_path));
+        unwrap_result!(ctx.rename(&staged, dir_path));
+    } else {
+        if !ctx.accepts_changes(file_path) {
+            ctx.decided(Decision::LeftAsIs, dir_path);
+            return Ok(());
+        }
+
+        ctx.decided(Decision::ReplacedFileWithDir, file_path);
+
+        let staged = staged_path(file_path);
+        unwrap_result!(recover_staged(&staged, file_path, ctx));
+        unwrap_result!(ctx.create_dir(&staged));
+        let copied = copy_dir(dir_path, &staged, dir_time, ctx);
+        if copied != Ok(true) {
+            // incomplete copy => keep the file
+            let _ = ctx.remove_dir_all(&staged);
+            return copied.map(|_| ());
+        }
+        unwrap_result!(ctx.remove_file(file_path));
+        unwrap_result!(ctx.rename(&staged, file_path));
+    }
+
+    Ok(())
+}
+
+/// Get where the replacement of `path` by an entry of another type is staged.
+fn staged_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(STAGED_SUFFIX);
+
+    path.with_file_name(name)
+}
+
+/// Copy a file, next to its target first when `atomic` is set (see `SyncOptions::atomic`),
+/// along with its extended attributes if `preserve_xattrs` is set and storing its checksum on
+/// the copy if `checksum_algorithm` is given.
+///
+/// Doesn't depend on the context, copies can run on any thread.
+fn copy_file(
+    from: &Path,
+    to: &Path,
+    atomic: bool,
+    sparse: SparseMode,
+    preserve_xattrs: bool,
+    checksum_algorithm: Option<&dyn hash::HashAlgorithm>,
+    chunked: Option<&copy::ChunkedCopy>,
+) -> io::Result<u64> {
+    let copy_to = |to: &Path| -> io::Result<u64> {
+        let copied = copy::copy_file(from, to, sparse, chunked)?;
+
+        if preserve_xattrs {
+            copy::copy_xattrs(from, to)?;
+        }
+
+        if let Some(algorithm) = checksum_algorithm {
+            checksum::store(from, to, algorithm)?;
+        }
+
+        Ok(copied)
+    };
+
+    // replacing a read-only file must keep failing like a direct copy does
+    let is_readonly = fs::metadata(to).is_ok_and(|metadata| metadata.permissions().readonly());
+
+    if atomic && !is_readonly {
+        let partial = partial_path(to);
+
+        match copy_to(&partial) {
+            Ok(copied) => match fs::rename(&partial, to) {
+                Ok(()) => Ok(copied),
+                Err(err) => {
+                    let _ = fs::remove_file(&partial);
+                    Err(err)
+                }
+            },
+            // cancelled (see `SyncOptions::cancel`) => leave the target untouched
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                let _ = fs::remove_file(&partial);
+                Err(err)
+            }
+            // e.g. files can't be created next to the target => copy directly
+            Err(_) => {
+                let _ = fs::remove_file(&partial);
+                copy_to(to)
+            }
+        }
+    } else {
+        copy_to(to)
+    }
+}
+
+/// Get where a file is copied before replacing `path`, see `SyncOptions::atomic`.
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(PARTIAL_SUFFIX);
+
+    path.with_file_name(name)
+}
+
+/// Whether `path` is a copy left unfinished by an interrupted run.
+fn is_partial_path(path: &Path) -> bool {
+    path.file_name()
+        .and_then(OsStr::to_str)
+        .is_some_and(|name| name.starts_with('.') && name.ends_with(PARTIAL_SUFFIX))
+}
+
+/// Get the path a staged entry replaces, `None` if `path` isn't a staged entry.
+fn unstaged_path(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    let original = name.strip_prefix('.')?.strip_suffix(STAGED_SUFFIX)?;
+
+    Some(path.with_file_name(original))
+}
+
+/// Finish or roll back a replacement interrupted while `staged` was being swapped in.
+fn recover_staged(staged: &Path, original: &Path, ctx: &Context) -> Result<(), SyncError> {
+    let staged_metadata = match fs::symlink_metadata(staged) {
+        Ok(metadata) => metadata,
+        // already recovered
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(SyncError::io(staged, err)),
+    };
+
+    if fs::symlink_metadata(original).is_ok() {
+        // the replaced entry is only removed once the staged one is complete => roll back
+        if staged_metadata.is_dir() {
+            ctx.remove_dir_all(staged)
+        } else {
+            ctx.remove_file(staged)
+        }
+    } else {
+        ctx.rename(staged, original)
+    }
+}
+
+/// Create `missing` as a copy of the `existing` directory, see
+/// `SyncOptions::create_missing_target`.
+fn create_missing_target(existing: &Path, missing: &Path, ctx: &Context) -> Result<(), ()> {
+    macro_rules! unwrap_result {
+        ($e:expr) => {
+            match $e {
+                Ok(x) => x,
+                Err(err) => {
+                    use ErrorHandlingType::*;
+
+                    match ctx.on_err(err) {
+                        Fail => return Err(()),
+                        Skip | Ignore => return Ok(()),
+                    }
+                }
+            }
+        };
+    }
+
+    if !ctx.accepts_changes(missing) {
+        return Ok(());
+    }
+
+    let metadata = unwrap_result!(existing
+        .metadata()
+        .map_err(|err| SyncError::io(existing, err)));
+
+    ctx.decided(Decision::CreatedDir, existing);
+    unwrap_result!(ctx.create_dir_all(missing));
+    copy_dir(
+        existing,
+        missing,
+        FileTime::from_last_modification_time(&metadata),
+        ctx,
+    )
+    .map(|_| ())
+}
+
+/// Copy a directory, preserving the timestamps of its entries, `target` itself gets `time`.
+///
+/// Returns whether the whole directory has been copied, errors handled by skipping or ignoring
+/// them stop the copy, except walk errors which only leave out the unreadable entries.
+fn copy_dir(source: &Path, target: &Path, time: FileTime, ctx: &Context) -> Result<bool, ()> {
+    let skip = RefCell::from(false);
+    let fail = RefCell::from(false);
+
+    let relative_path_iter = ctx
+        .walk(source)
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|entry| {
+            let is_walked = !ctx.is_excluded(entry);
+            if is_walked {
+                ctx.discovered();
+            }
+
+            is_walked
+        })
         // Get path
         .filter_map(|e: walkdir::Result<DirEntry>| match e {
+            Ok(x) if is_special_file(&x, ctx) => None,
             Ok(x) => Some(x.path().to_owned()),
             Err(err) => {
                 use ErrorHandlingType::*;
 
-                match on_err(&err) {
-                    Fail => *fail.borrow_mut() = true,
-                    Skip => *skip.borrow_mut() = true,
-                    Ignore => (),
-                }
+                match ctx.on_err(err) {
+                    Fail => *fail.borrow_mut() = true,
+                    Skip => *skip.borrow_mut() = true,
+                    Ignore => (),
+                }
+
+                None
+            }
+        })
+        // Get relative path (returns a PathBuf)
+        .filter_map(|absolute_path: PathBuf| trim_base_path(source, absolute_path));
+
+    macro_rules! handle_on_error {
+        ($e:expr) => {
+            match $e {
+                Ok(_) => (),
+                Err(err) => {
+                    use ErrorHandlingType::*;
+
+                    match ctx.on_err(err) {
+                        Fail => return Err(()),
+                        Skip | Ignore => return Ok(false),
+                    }
+                }
+            }
+        };
+    }
+
+    // copying their content changes the times of directories => set once everything is copied
+    let mut dir_times = Vec::new();
+    // first copy of each file with several hard links, see `SyncOptions::preserve_hardlinks`
+    let mut linked_copies = HashMap::<_, PathBuf>::new();
+
+    // walk errors are only handled while iterating, skipped ones leave the copy incomplete
+    for relative_path in relative_path_iter {
+        if *fail.borrow() || ctx.is_cancelled() {
+            return Err(());
+        }
+
+        let relative_path: &Path = &relative_path;
+        let path_in_dir = source.join(relative_path);
+        ctx.progress(&path_in_dir);
+        let path_in_file = match ctx.destination_path(target, relative_path) {
+            Ok(path_in_file) => path_in_file,
+            Err(err) => {
+                handle_on_error!(Err::<(), _>(err));
+                continue;
+            }
+        };
+
+        if path_in_dir.is_symlink() && !ctx.options.follow_symlinks {
+            // setting its times would modify what it points to
+            handle_on_error!(ctx.copy_symlink(&path_in_dir, &path_in_file));
+            continue;
+        }
+
+        let metadata = match fs::metadata(&path_in_dir) {
+            Ok(metadata) => metadata,
+            Err(err) => {
+                handle_on_error!(Err::<(), _>(SyncError::io(&path_in_dir, err)));
+                continue;
+            }
+        };
+        let entry_time = FileTime::from_last_modification_time(&metadata);
+
+        if metadata.is_dir() {
+            handle_on_error!(ctx.create_dir(&path_in_file));
+            dir_times.push((path_in_file, entry_time));
+        } else if ctx.is_too_large(&path_in_dir, &metadata) {
+            continue;
+        } else {
+            let link_id = hard_link_id(&metadata).filter(|_| ctx.options.preserve_hardlinks);
+
+            // the link shares the data and times of the first copy
+            if let Some(first_copy) = link_id.and_then(|id| linked_copies.get(&id)) {
+                handle_on_error!(ctx.create_hard_link(first_copy, &path_in_file));
+                continue;
+            }
+
+            match is_filtered_by_content(&path_in_dir, ctx) {
+                Ok(false) => handle_on_error!(ctx.copy(&path_in_dir, &path_in_file)),
+                Ok(true) => continue,
+                Err(err) => handle_on_error!(Err::<(), _>(err)),
+            }
+
+            handle_on_error!(ctx.set_file_times(&path_in_file, entry_time, entry_time));
+
+            if let Some(id) = link_id {
+                linked_copies.insert(id, path_in_file);
+            }
+        }
+    }
+
+    if *fail.borrow() {
+        return Err(());
+    }
+
+    // deepest directories first
+    for (path_in_file, entry_time) in dir_times.iter().rev() {
+        handle_on_error!(ctx.set_file_times(path_in_file, *entry_time, *entry_time));
+    }
+
+    handle_on_error!(ctx.set_file_times(target, time, time));
+
+    let skipped = *skip.borrow();
+    Ok(!skipped)
+}
+
+/// Identify the file `metadata` is about if it has several hard links, see
+/// `SyncOptions::preserve_hardlinks`.
+#[cfg(unix)]
+fn hard_link_id(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    (metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn hard_link_id(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Synchronize 2 directories, only keeps the one with the latest modification time.
+fn synchronize_dirs_replace(dir1_path: &Path, dir2_path: &Path, ctx: &Context) -> Result<(), ()> {
+    // replacing a directory copies its data
+    if ctx.options.metadata_only {
+        return Ok(());
+    }
+
+    /// Unwrap or print error and return.
+    macro_rules! unwrap_result {
+        ($e:expr) => {
+            match $e {
+                Ok(x) => x,
+                Err(err) => {
+                    use ErrorHandlingType::*;
+
+                    match ctx.on_err(err) {
+                        Fail => return Err(()),
+                        Skip | Ignore => return Ok(()),
+                    }
+                }
+            }
+        };
+    }
+
+    let metadata = |path: &Path| path.metadata().map_err(|err| SyncError::io(path, err));
+
+    let dir1_time = FileTime::from_last_modification_time(&unwrap_result!(metadata(dir1_path)));
+
+    let dir2_time = FileTime::from_last_modification_time(&unwrap_result!(metadata(dir2_path)));
+
+    let decision = match &ctx.options.should_replace {
+        Some(should_replace) => should_replace(
+            &unwrap_result!(dir_info(dir1_path, ctx)),
+            &unwrap_result!(dir_info(dir2_path, ctx)),
+        ),
+        None => ReplaceDecision::Newest,
+    };
+
+    let (keep_dir1, keep_dir2) = match decision {
+        ReplaceDecision::Newest => (dir1_time > dir2_time, dir1_time < dir2_time),
+        ReplaceDecision::First => (true, false),
+        ReplaceDecision::Second => (false, true),
+        ReplaceDecision::Neither => (false, false),
+    };
+
+    if keep_dir1 {
+        if !ctx.accepts_changes(dir2_path) {
+            return Ok(());
+        }
+
+        unwrap_result!(ctx.remove_dir_all(dir2_path));
+        unwrap_result!(ctx.create_dir(dir2_path));
+        copy_dir(dir1_path, dir2_path, dir1_time, ctx)?;
+    } else if keep_dir2 {
+        if !ctx.accepts_changes(dir1_path) {
+            return Ok(());
+        }
+
+        unwrap_result!(ctx.remove_dir_all(dir1_path));
+        unwrap_result!(ctx.create_dir(dir1_path));
+        copy_dir(dir2_path, dir1_path, dir2_time, ctx)?;
+    }
+
+    Ok(())
+}
+
+/// Get the information given to `SyncOptions::should_replace` about a directory.
+fn dir_info(path: &Path, ctx: &Context) -> Result<DirInfo, SyncError> {
+    let mut latest_modification = path
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .map_err(|err| SyncError::io(path, err))?;
+    let mut size = 0;
+
+    for entry in ctx.walk(path).min_depth(1) {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let modified = metadata
+            .modified()
+            .map_err(|err| SyncError::io(entry.path(), err))?;
+
+        latest_modification = latest_modification.max(modified);
+        if metadata.is_file() {
+            size += metadata.len();
+        }
+    }
+
+    Ok(DirInfo {
+        path: path.to_owned(),
+        latest_modification,
+        size,
+    })
+}
+
+/// Get the latest modification time of a directory and of the entries inside it, excluded ones
+/// aside. The directory itself is walked too, an empty one gets its own modification time.
+fn dir_latest_modification_time(path: &Path, ctx: &Context) -> Result<FileTime, ErrorHandlingType> {
+    let mut skip = false;
+    let mut fail = false;
+
+    macro_rules! unwrap_result {
+        ($e:expr) => {
+            match $e {
+                Err(err) => {
+                    use ErrorHandlingType::*;
+
+                    match ctx.on_err(err) {
+                        Fail => fail = true,
+                        Skip => skip = true,
+                        Ignore => (),
+                    }
+
+                    return None;
+                }
+                Ok(x) => x,
+            }
+        };
+    }
+
+    let max = ctx
+        .walk(path)
+        .into_iter()
+        .filter_entry(|entry| ctx.exclusion_reason(entry).is_none())
+        .filter_map(|e: walkdir::Result<DirEntry>| {
+            let e = unwrap_result!(e);
+
+            let path: &Path = e.path();
+
+            Some(FileTime::from_last_modification_time(&unwrap_result!(path
+                .metadata()
+                .map_err(|err| SyncError::io(path, err)))))
+        })
+        .max()
+        .unwrap_or_else(FileTime::zero);
+
+    if fail {
+        Err(ErrorHandlingType::Fail)
+    } else if skip {
+        Err(ErrorHandlingType::Skip)
+    } else {
+        Ok(max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let name = std::ffi::OsStr::from_bytes(b"invalid-\xff\xfe");
+
+        std::fs::create_dir(dir1.path().join(name)).unwrap();
+        std::fs::write(dir1.path().join(name).join(name), "content").unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            ..Default::default()
+        };
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        assert_eq!(
+            std::fs::read(dir2.path().join(name).join(name)).unwrap(),
+            b"content"
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn long_paths() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let nested: std::path::PathBuf = std::iter::repeat("a".repeat(50)).take(6).collect();
+
+        // built with the prefix, the test itself would hit the limit otherwise
+        let path1 = super::long_path(&dir1.path().join(&nested).join("file")).into_owned();
+        std::fs::create_dir_all(path1.parent().unwrap()).unwrap();
+        std::fs::write(&path1, "content").unwrap();
+        assert!(dir2.path().join(&nested).join("file").as_os_str().len() > 260);
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            ..Default::default()
+        };
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        let path2 = super::long_path(&dir2.path().join(&nested).join("file")).into_owned();
+        assert_eq!(std::fs::read(path2).unwrap(), b"content");
+    }
+
+    #[test]
+    fn content_filter_skips_matching_files() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        std::fs::write(dir1.path().join("movie.txt"), b"\x1a\x45\xdf\xa3 not text").unwrap();
+        std::fs::write(dir1.path().join("notes.txt"), b"some notes").unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            content_filter: Some(super::ContentFilter::new(4, |header| {
+                header == b"\x1a\x45\xdf\xa3"
+            })),
+            ..Default::default()
+        };
+        let result = super::synchronize_with(dir1.path(), dir2.path(), &options);
+
+        assert_eq!(result.unwrap().filtered.content_filter, 1);
+        assert!(!dir2.path().join("movie.txt").exists());
+        assert!(dir2.path().join("notes.txt").exists());
+    }
+
+    /// Content and modification time of every path under `root`, including `root` itself.
+    fn snapshot(root: &std::path::Path) -> Vec<(std::path::PathBuf, Vec<u8>, filetime::FileTime)> {
+        walkdir::WalkDir::new(root)
+            .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+            .into_iter()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let metadata = entry.metadata().unwrap();
+                let content = if metadata.is_file() {
+                    std::fs::read(entry.path()).unwrap()
+                } else {
+                    Vec::new()
+                };
+
+                (
+                    entry.path().to_owned(),
+                    content,
+                    filetime::FileTime::from_last_modification_time(&metadata),
+                )
+            })
+            .collect()
+    }
+
+    fn write_with_mtime(path: &std::path::Path, content: &str, unix_time: i64) {
+        std::fs::write(path, content).unwrap();
+        filetime::set_file_mtime(path, filetime::FileTime::from_unix_time(unix_time, 0)).unwrap();
+    }
+
+    fn read_only_side1() -> super::SyncOptions {
+        super::SyncOptions {
+            side1: super::SideOptions { read_only: true },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn read_only_side_dirs() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        write_with_mtime(&dir1.path().join("only1"), "1", 1_000);
+        write_with_mtime(&dir1.path().join("newer1"), "new", 2_000);
+        write_with_mtime(&dir2.path().join("newer1"), "old", 1_000);
+        write_with_mtime(&dir1.path().join("newer2"), "old", 1_000);
+        write_with_mtime(&dir2.path().join("newer2"), "new", 2_000);
+        std::fs::create_dir(dir2.path().join("only2")).unwrap();
+        write_with_mtime(&dir2.path().join("only2").join("file"), "2", 1_000);
+
+        let before = snapshot(dir1.path());
+        let _ = super::synchronize_with(
+            dir1.path(),
+            dir2.path(),
+            &super::SyncOptions {
+                error_handler: Box::new(|_| super::ErrorHandlingType::Ignore),
+                ..read_only_side1()
+            },
+        );
+
+        assert_eq!(before, snapshot(dir1.path()));
+        assert_eq!(std::fs::read(dir2.path().join("only1")).unwrap(), b"1");
+        assert_eq!(std::fs::read(dir2.path().join("newer1")).unwrap(), b"new");
+    }
+
+    #[test]
+    fn read_only_side_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let file1 = dir.path().join("file1");
+        let file2 = dir.path().join("file2");
+
+        for (older, newer) in [(&file1, &file2), (&file2, &file1)].iter() {
+            write_with_mtime(older, "old", 1_000);
+            write_with_mtime(newer, "new", 2_000);
+
+            let before = snapshot(&file1);
+            let _ = super::synchronize_with(
+                &file1,
+                &file2,
+                &super::SyncOptions {
+                    error_handler: Box::new(|_| super::ErrorHandlingType::Ignore),
+                    ..read_only_side1()
+                },
+            );
+
+            assert_eq!(before, snapshot(&file1));
+        }
+    }
+
+    #[test]
+    fn error_read_only_target() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        write_with_mtime(&dir1.path().join("file"), "old", 1_000);
+        write_with_mtime(&dir2.path().join("file"), "new", 2_000);
+
+        let result = super::synchronize_with(
+            dir1.path(),
+            dir2.path(),
+            &super::SyncOptions {
+                error_handler: Box::new(|_| super::ErrorHandlingType::Fail),
+                ..read_only_side1()
+            },
+        );
+
+        match result {
+            Err(super::SyncError::ReadOnlySide(path)) => {
+                assert_eq!(path, dir1.path().join("file"))
+            }
+            result => panic!("{:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn error_io_path() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let manifest = dir1.path().join(super::MANIFEST_NAME);
+        // can't be read as a file
+        std::fs::create_dir(&manifest).unwrap();
+
+        let result = super::synchronize_with(
+            dir1.path(),
+            dir2.path(),
+            &super::SyncOptions {
+                error_handler: Box::new(|_| super::ErrorHandlingType::Fail),
+                propagate_deletions: true,
+                ..Default::default()
+            },
+        );
+
+        match result {
+            Err(super::SyncError::Io { path, .. }) => assert_eq!(path, manifest),
+            result => panic!("{:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn read_only_side_file_with_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        let other_dir = dir.path().join("dir");
+
+        write_with_mtime(&file, "file", 2_000);
+        std::fs::create_dir(&other_dir).unwrap();
+        write_with_mtime(&other_dir.join("inner"), "inner", 1_000);
+        filetime::set_file_mtime(&other_dir, filetime::FileTime::from_unix_time(1_000, 0)).unwrap();
+
+        let before = snapshot(&other_dir);
+        let read_only_side2 = super::SyncOptions {
+            error_handler: Box::new(|err| {
+                assert!(err.to_string().contains("read-only side"));
+
+                super::ErrorHandlingType::Fail
+            }),
+            side2: super::SideOptions { read_only: true },
+            ..Default::default()
+        };
+        let result = super::synchronize_with(&file, &other_dir, &read_only_side2);
+
+        assert!(result.is_err());
+        assert_eq!(before, snapshot(&other_dir));
+    }
+
+    #[test]
+    fn read_only_side_mac_apps() {
+        let dir = tempfile::tempdir().unwrap();
+        let app1 = dir.path().join("1.app");
+        let app2 = dir.path().join("2.app");
+
+        std::fs::create_dir(&app1).unwrap();
+        std::fs::create_dir(&app2).unwrap();
+        write_with_mtime(&app1.join("binary"), "old", 1_000);
+        write_with_mtime(&app2.join("binary"), "new", 2_000);
+        filetime::set_file_mtime(&app1, filetime::FileTime::from_unix_time(1_000, 0)).unwrap();
+
+        let before = snapshot(&app1);
+        let _ = super::synchronize_with(
+            &app1,
+            &app2,
+            &super::SyncOptions {
+                error_handler: Box::new(|_| super::ErrorHandlingType::Ignore),
+                ..read_only_side1()
+            },
+        );
+
+        assert_eq!(before, snapshot(&app1));
+    }
+
+    #[cfg(all(target_os = "linux", feature = "xattr"))]
+    #[test]
+    fn preserve_xattrs() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let file1 = dir1.path().join("file");
+
+        std::fs::write(&file1, "content").unwrap();
+        if xattr::set(&file1, "user.test", b"value").is_err() {
+            // Extended attributes not supported by the file system
+            return;
+        }
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            preserve_xattrs: true,
+            ..Default::default()
+        };
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        let copied = xattr::get(dir2.path().join("file"), "user.test").unwrap();
+        assert_eq!(copied.as_deref(), Some(&b"value"[..]));
+    }
+
+    #[cfg(all(unix, feature = "xattr"))]
+    #[test]
+    fn checksum_xattrs_repair_bit_rot() {
+        let dir = tempfile::tempdir().unwrap();
+        let file1 = dir.path().join("file1");
+        let file2 = dir.path().join("file2");
+
+        write_with_mtime(&file1, "content", 2_000);
+        write_with_mtime(&file2, "old", 1_000);
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            checksum_xattrs: true,
+            ..Default::default()
+        };
+        let report = super::synchronize_with(&file1, &file2, &options);
+        assert!(report.unwrap().bit_rot.is_empty());
+
+        if xattr::list(&file2).unwrap().next().is_none() {
+            // Extended attributes not supported by the file system
+            return;
+        }
+
+        // Silent corruption: the content changes but not the modification time
+        write_with_mtime(&file2, "c0ntent", 2_000);
+
+        let report = super::synchronize_with(&file1, &file2, &options);
+        assert_eq!(report.unwrap().bit_rot, vec![file2.clone()]);
+        assert_eq!(std::fs::read(&file2).unwrap(), b"content");
+    }
+
+    #[test]
+    fn push_leaves_first_path_untouched() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        write_with_mtime(&dir1.path().join("only1"), "1", 1_000);
+        write_with_mtime(&dir1.path().join("newer2"), "old", 1_000);
+        write_with_mtime(&dir2.path().join("newer2"), "new", 2_000);
+        write_with_mtime(&dir2.path().join("only2"), "2", 1_000);
+
+        let before = snapshot(dir1.path());
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            direction: super::Direction::Push,
+            ..Default::default()
+        };
+        let result = super::synchronize_with(dir1.path(), dir2.path(), &options);
+
+        assert!(result.is_ok());
+        assert_eq!(before, snapshot(dir1.path()));
+        assert_eq!(std::fs::read(dir2.path().join("only1")).unwrap(), b"1");
+        assert_eq!(std::fs::read(dir2.path().join("newer2")).unwrap(), b"new");
+    }
+
+    #[test]
+    fn mirror_deletes_extras() {
+        let synchronize = |direction| {
+            let dir1 = tempfile::tempdir().unwrap();
+            let dir2 = tempfile::tempdir().unwrap();
+            write_with_mtime(&dir1.path().join("both"), "1", 1_000);
+            write_with_mtime(&dir2.path().join("both"), "2", 2_000);
+            write_with_mtime(&dir2.path().join("only2"), "2", 1_000);
+            std::fs::create_dir(dir2.path().join("dir2")).unwrap();
+            write_with_mtime(&dir2.path().join("dir2").join("nested"), "n", 1_000);
+
+            let options = super::SyncOptions {
+                error_handler: Box::new(|err| panic!("{}", err)),
+                direction,
+                ..Default::default()
+            };
+            super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+            (dir1, dir2)
+        };
+
+        let (_, dir2) = synchronize(super::Direction::Mirror);
+        assert!(!dir2.path().join("only2").exists());
+        assert!(!dir2.path().join("dir2").exists());
+        assert_eq!(std::fs::read(dir2.path().join("both")).unwrap(), b"1");
+
+        let (dir1, dir2) = synchronize(super::Direction::Bidirectional);
+        assert!(dir2.path().join("only2").is_file());
+        assert!(dir1.path().join("only2").is_file());
+        assert!(dir1.path().join("dir2").join("nested").is_file());
+        assert_eq!(std::fs::read(dir2.path().join("both")).unwrap(), b"2");
+
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        write_with_mtime(&dir1.path().join("only1"), "1", 1_000);
+        write_with_mtime(&dir2.path().join("only2"), "2", 1_000);
+        super::synchronize_mirror(dir1.path(), dir2.path(), |err| panic!("{}", err)).unwrap();
+        assert!(dir2.path().join("only1").is_file());
+        assert!(!dir2.path().join("only2").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn mirror_reconciles_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let path1 = dir1.path().join("file");
+        let path2 = dir2.path().join("file");
+
+        write_with_mtime(&path1, "same", 1_000);
+        write_with_mtime(&path2, "same", 1_000);
+        std::fs::set_permissions(&path1, std::fs::Permissions::from_mode(0o640)).unwrap();
+        std::fs::set_permissions(&path2, std::fs::Permissions::from_mode(0o600)).unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            direction: super::Direction::Mirror,
+            ..Default::default()
+        };
+        let result = super::synchronize_with(dir1.path(), dir2.path(), &options);
+
+        let mode = |path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert!(result.is_ok());
+        assert_eq!(mode(&path1), 0o640);
+        assert_eq!(mode(&path2), 0o640);
+    }
+
+    #[test]
+    fn sanitize_replace() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir(dir1.path().join("12:30")).unwrap();
+        write_with_mtime(&dir1.path().join("12:30").join("notes"), "n", 1_000);
+        write_with_mtime(&dir1.path().join("what?"), "w", 1_000);
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            sanitize: Some(super::SanitizePolicy::fat(super::SanitizeAction::Replace)),
+            ..Default::default()
+        };
+        let sync = || super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        let report = sync();
+        sync();
+
+        assert_eq!(report.renamed.len(), 2);
+        assert_eq!(
+            std::fs::read(dir2.path().join("12：30").join("notes")).unwrap(),
+            b"n"
+        );
+        assert_eq!(std::fs::read(dir2.path().join("what？")).unwrap(), b"w");
+        // sanitized copies are never synchronized back as new entries
+        assert_eq!(std::fs::read_dir(dir1.path()).unwrap().count(), 2);
+        assert!(!dir1.path().join("what？").exists());
+    }
+
+    #[test]
+    fn sanitize_skip() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        std::fs::write(dir1.path().join("what?"), "w").unwrap();
+        std::fs::write(dir1.path().join("fine"), "f").unwrap();
+
+        let errors = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let options = super::SyncOptions {
+            error_handler: Box::new({
+                let errors = errors.clone();
+                move |err| {
+                    errors.borrow_mut().push(err.to_string());
+                    super::ErrorHandlingType::Ignore
+                }
+            }),
+            sanitize: Some(super::SanitizePolicy::fat(super::SanitizeAction::Skip)),
+            ..Default::default()
+        };
+        let result = super::synchronize_with(dir1.path(), dir2.path(), &options);
+
+        assert!(result.is_ok());
+        assert_eq!(errors.borrow().len(), 1);
+        assert!(errors.borrow()[0].contains("destination file system"));
+        assert!(dir2.path().join("fine").exists());
+        assert!(!dir2.path().join("what?").exists());
+        assert!(!dir2.path().join("what？").exists());
+    }
+
+    #[test]
+    fn sanitize_reverse() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir(dir1.path().join("a:b")).unwrap();
+        write_with_mtime(&dir1.path().join("a:b").join("x"), "x", 1_000);
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            sanitize: Some(super::SanitizePolicy::fat(super::SanitizeAction::Replace)),
+            ..Default::default()
+        };
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        // created in the sanitized directory, goes back to the original one
+        write_with_mtime(&dir2.path().join("a：b").join("y"), "y", 2_000);
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        assert_eq!(
+            std::fs::read(dir1.path().join("a:b").join("y")).unwrap(),
+            b"y"
+        );
+        assert!(!dir1.path().join("a：b").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn regular_files_only() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        std::fs::write(dir1.path().join("file"), "f").unwrap();
+        std::os::unix::fs::symlink("file", dir1.path().join("link")).unwrap();
+        let _socket = std::os::unix::net::UnixListener::bind(dir1.path().join("socket")).unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            regular_files_only: true,
+            ..Default::default()
+        };
+        let report = super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        let names: Vec<_> = std::fs::read_dir(dir2.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, vec!["file"]);
+        assert_eq!(report.filtered.symlinks, 1);
+        assert_eq!(report.filtered.sockets, 1);
+    }
+
+    #[test]
+    fn should_replace_overrides_newest_mac_app() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let app1 = dir1.path().join("Editor.app");
+        let app2 = dir2.path().join("Editor.app");
+
+        std::fs::create_dir(&app1).unwrap();
+        std::fs::create_dir(&app2).unwrap();
+        write_with_mtime(&app1.join("binary"), "bigger version", 1_000);
+        write_with_mtime(&app2.join("binary"), "newer", 2_000);
+        filetime::set_file_mtime(&app1, filetime::FileTime::from_unix_time(1_000, 0)).unwrap();
+        filetime::set_file_mtime(&app2, filetime::FileTime::from_unix_time(2_000, 0)).unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            should_replace: Some(Box::new(|first, second| {
+                assert!(first.latest_modification < second.latest_modification);
+
+                if first.size > second.size {
+                    super::ReplaceDecision::First
+                } else {
+                    super::ReplaceDecision::Newest
+                }
+            })),
+            ..Default::default()
+        };
+        let result = super::synchronize_with(dir1.path(), dir2.path(), &options);
+
+        assert!(result.is_ok());
+        assert_eq!(
+            std::fs::read(app2.join("binary")).unwrap(),
+            b"bigger version"
+        );
+    }
+
+    #[test]
+    fn mirror_preserves_dir_timestamps() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let sub = dir1.path().join("sub");
+
+        std::fs::create_dir_all(sub.join("nested")).unwrap();
+        write_with_mtime(&sub.join("nested").join("file"), "f", 500);
+        filetime::set_file_mtime(&sub, filetime::FileTime::from_unix_time(1_000, 0)).unwrap();
+        filetime::set_file_mtime(
+            sub.join("nested"),
+            filetime::FileTime::from_unix_time(800, 0),
+        )
+        .unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            direction: super::Direction::Mirror,
+            preserve_dir_timestamps: true,
+            ..Default::default()
+        };
+        let result = super::synchronize_with(dir1.path(), dir2.path(), &options);
+
+        let mtime = |path: std::path::PathBuf| {
+            filetime::FileTime::from_last_modification_time(&std::fs::metadata(path).unwrap())
+        };
+        assert!(result.is_ok());
+        assert_eq!(mtime(dir2.path().join("sub")), mtime(sub.clone()));
+        assert_eq!(
+            mtime(dir2.path().join("sub").join("nested")),
+            mtime(sub.join("nested"))
+        );
+        assert_eq!(mtime(dir2.path().to_owned()), mtime(dir1.path().to_owned()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dir_timestamps_skip_failed_entry() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let sub = dir1.path().join("sub");
+
+        std::fs::create_dir(&sub).unwrap();
+        std::fs::create_dir(dir2.path().join("sub")).unwrap();
+        write_with_mtime(&sub.join("file"), "f", 500);
+        // walked before its parent, whose time is set last
+        std::os::unix::fs::symlink(dir1.path(), sub.join("loop")).unwrap();
+        filetime::set_file_mtime(&sub, filetime::FileTime::from_unix_time(1_000, 0)).unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|_| super::ErrorHandlingType::Skip),
+            direction: super::Direction::Push,
+            preserve_dir_timestamps: true,
+            follow_symlinks: true,
+            ..Default::default()
+        };
+        let result = super::synchronize_with(dir1.path(), dir2.path(), &options);
+
+        assert!(result.is_ok());
+        assert_eq!(
+            std::fs::metadata(dir2.path().join("sub"))
+                .unwrap()
+                .modified()
+                .unwrap(),
+            std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000)
+        );
+    }
+
+    #[test]
+    fn interrupted_file_with_dir_loses_nothing() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let entry1 = dir1.path().join("entry");
+        let entry2 = dir2.path().join("entry");
+
+        write_with_mtime(&entry2, "old file", 1_000);
+        std::fs::create_dir(&entry1).unwrap();
+        write_with_mtime(&entry1.join("a"), "a", 2_000);
+        write_with_mtime(&entry1.join("b"), "boom", 2_000);
+
+        // simulate a crash in the middle of copying the directory
+        let crash = Arc::new(AtomicBool::new(true));
+        let crash_in_filter = Arc::clone(&crash);
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            content_filter: Some(super::ContentFilter::new(4, move |header| {
+                if header == b"boom" && crash_in_filter.swap(false, Ordering::SeqCst) {
+                    panic!("simulated crash");
+                }
+
+                false
+            })),
+            ..Default::default()
+        };
+        let sync = || super::synchronize_with(dir1.path(), dir2.path(), &options);
+
+        let crashed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(sync));
+
+        assert!(crashed.is_err());
+        assert_eq!(std::fs::read(&entry2).unwrap(), b"old file");
+        assert!(dir2.path().join(".entry.keepkeeping-staged").is_dir());
+
+        assert!(sync().is_ok());
+        assert_eq!(std::fs::read(entry2.join("a")).unwrap(), b"a");
+        assert_eq!(std::fs::read(entry2.join("b")).unwrap(), b"boom");
+        assert_eq!(std::fs::read_dir(dir2.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn max_file_size() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let small = "small";
+        let large = "large".repeat(100);
+
+        write_with_mtime(&dir1.path().join("small"), small, 2_000);
+        write_with_mtime(&dir1.path().join("large"), &large, 2_000);
+        // older versions aren't replaced either
+        write_with_mtime(&dir2.path().join("large"), "old", 1_000);
+        // copied as a whole like any macOS app
+        std::fs::create_dir_all(dir1.path().join("Editor.app").join("Contents")).unwrap();
+        let contents = dir1.path().join("Editor.app").join("Contents");
+        write_with_mtime(&contents.join("small"), small, 2_000);
+        write_with_mtime(&contents.join("large"), &large, 2_000);
+
+        let skipped = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            on_decision: Some(Box::new({
+                let skipped = skipped.clone();
+                move |decision: &super::Decision| {
+                    if let super::Decision::SkippedTooLarge(path) = decision {
+                        skipped.borrow_mut().push(path.to_path_buf());
+                    }
+                }
+            })),
+            max_file_size: Some(100),
+            ..Default::default()
+        };
+        let report = super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        let contents = dir2.path().join("Editor.app").join("Contents");
+        assert_eq!(std::fs::read(dir2.path().join("small")).unwrap(), b"small");
+        assert_eq!(std::fs::read(dir2.path().join("large")).unwrap(), b"old");
+        assert_eq!(std::fs::read(contents.join("small")).unwrap(), b"small");
+        assert!(!contents.join("large").exists());
+        assert_eq!(report.filtered.too_large, 2);
+
+        let mut skipped = skipped.take();
+        skipped.sort();
+        let expected: Vec<std::path::PathBuf> = vec![
+            ["Editor.app", "Contents", "large"].iter().collect(),
+            "large".into(),
+        ];
+        assert_eq!(skipped, expected);
+    }
+
+    #[test]
+    fn case_insensitive() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir(dir1.path().join("Docs")).unwrap();
+        std::fs::create_dir(dir2.path().join("docs")).unwrap();
+        write_with_mtime(&dir1.path().join("Docs").join("File.txt"), "new", 2_000);
+        write_with_mtime(&dir2.path().join("docs").join("file.txt"), "old", 1_000);
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            case_insensitive: true,
+            ..Default::default()
+        };
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        let names = |dir: &std::path::Path| {
+            let mut names: Vec<_> = walkdir::WalkDir::new(dir)
+                .min_depth(1)
+                .into_iter()
+                .map(|entry| entry.unwrap().path().strip_prefix(dir).unwrap().to_owned())
+                .collect();
+            names.sort();
+            names
+        };
+        let file1 = std::path::Path::new("Docs").join("File.txt");
+        let file2 = std::path::Path::new("docs").join("file.txt");
+        assert_eq!(names(dir1.path()), ["Docs".into(), file1.clone()]);
+        assert_eq!(names(dir2.path()), ["docs".into(), file2.clone()]);
+        assert_eq!(std::fs::read(dir2.path().join(file2)).unwrap(), b"new");
+    }
+
+    #[test]
+    fn rate_limit() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        std::fs::write(dir1.path().join("a"), vec![b'a'; 32 * 1024]).unwrap();
+        std::fs::write(dir1.path().join("b"), vec![b'b'; 32 * 1024]).unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            copy_buffer_size: 8 * 1024,
+            // the limit applies to both files together
+            rate_limit: Some(128 * 1024),
+            ..Default::default()
+        };
+        let start = std::time::Instant::now();
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        assert!(start.elapsed() >= std::time::Duration::from_millis(500));
+        assert_eq!(
+            std::fs::read(dir2.path().join("b")).unwrap().len(),
+            32 * 1024
+        );
+    }
+
+    #[test]
+    fn overlapping_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("nested");
+        std::fs::create_dir(&nested).unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|_| super::ErrorHandlingType::Fail),
+            ..Default::default()
+        };
+        let overlapping = |path1: &std::path::Path, path2: &std::path::Path| {
+            matches!(
+                super::synchronize_with(path1, path2, &options),
+                Err(super::SyncError::OverlappingPaths(_, _))
+            )
+        };
+
+        assert!(overlapping(dir.path(), dir.path()));
+        assert!(overlapping(dir.path(), &nested));
+        assert!(overlapping(&nested, dir.path()));
+        // the same directory through another path
+        assert!(overlapping(dir.path(), &nested.join("..")));
+        assert!(std::fs::read_dir(&nested).unwrap().next().is_none());
+    }
+
+    #[test]
+    fn keep_largest() {
+        let dir = tempfile::tempdir().unwrap();
+        let file1 = dir.path().join("file1");
+        let file2 = dir.path().join("file2");
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            conflict_strategy: super::ConflictStrategy::KeepLargest,
+            ..Default::default()
+        };
+
+        // larger but older
+        write_with_mtime(&file1, "line 1\nline 2\n", 1_000);
+        write_with_mtime(&file2, "line 1\n", 2_000);
+        super::synchronize_with(&file1, &file2, &options).unwrap();
+        assert_eq!(std::fs::read(&file2).unwrap(), b"line 1\nline 2\n");
+
+        // same size => newest
+        write_with_mtime(&file1, "old", 1_000);
+        write_with_mtime(&file2, "new", 2_000);
+        super::synchronize_with(&file1, &file2, &options).unwrap();
+        assert_eq!(std::fs::read(&file1).unwrap(), b"new");
+    }
+
+    #[test]
+    fn copy_dir_preserves_times() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let contents = dir1.path().join("Editor.app").join("Contents");
+
+        std::fs::create_dir_all(contents.join("MacOS")).unwrap();
+        write_with_mtime(&contents.join("Info.plist"), "plist", 1_000);
+        write_with_mtime(&contents.join("MacOS").join("editor"), "binary", 2_000);
+        filetime::set_file_mtime(
+            contents.join("MacOS"),
+            filetime::FileTime::from_unix_time(3_000, 0),
+        )
+        .unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            ..Default::default()
+        };
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        let contents = dir2.path().join("Editor.app").join("Contents");
+        let mtime = |path: std::path::PathBuf| {
+            filetime::FileTime::from_last_modification_time(&path.metadata().unwrap())
+                .unix_seconds()
+        };
+        assert_eq!(mtime(contents.join("Info.plist")), 1_000);
+        assert_eq!(mtime(contents.join("MacOS").join("editor")), 2_000);
+        assert_eq!(mtime(contents.join("MacOS")), 3_000);
+    }
+
+    #[test]
+    fn created_dirs_keep_their_time() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let empty = dir1.path().join("empty");
+        let full = dir1.path().join("full");
+
+        std::fs::create_dir(&empty).unwrap();
+        std::fs::create_dir_all(full.join("nested")).unwrap();
+        write_with_mtime(&full.join("nested").join("file"), "file", 1_000);
+        for (dir, seconds) in [
+            (&empty, 2_000),
+            (&full, 3_000),
+            (&full.join("nested"), 4_000),
+        ] {
+            filetime::set_file_mtime(dir, filetime::FileTime::from_unix_time(seconds, 0)).unwrap();
+        }
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            direction: super::Direction::Mirror,
+            ..Default::default()
+        };
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        let mtime = |path: std::path::PathBuf| {
+            filetime::FileTime::from_last_modification_time(&path.metadata().unwrap())
+                .unix_seconds()
+        };
+        assert_eq!(mtime(dir2.path().join("empty")), 2_000);
+        // set after being filled
+        assert_eq!(mtime(dir2.path().join("full")), 3_000);
+        assert_eq!(mtime(dir2.path().join("full").join("nested")), 4_000);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn preserve_hardlinks() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let source = dir1.path().join("source");
+        let target = dir2.path().join("target");
+
+        std::fs::create_dir_all(source.join("sub")).unwrap();
+        std::fs::write(source.join("file"), "shared").unwrap();
+        std::fs::hard_link(source.join("file"), source.join("sub").join("link")).unwrap();
+        std::fs::write(source.join("other"), "other").unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            create_missing_target: true,
+            preserve_hardlinks: true,
+            ..Default::default()
+        };
+        super::synchronize_with(&source, &target, &options).unwrap();
+
+        let inode = |path: std::path::PathBuf| std::fs::metadata(path).unwrap().ino();
+        assert_eq!(
+            inode(target.join("file")),
+            inode(target.join("sub").join("link"))
+        );
+        assert_ne!(inode(target.join("file")), inode(target.join("other")));
+        assert_eq!(
+            std::fs::read(target.join("sub").join("link")).unwrap(),
+            b"shared"
+        );
+    }
+
+    #[test]
+    fn file_with_empty_dir() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let empty_dir = dir1.path().join("entry");
+
+        write_with_mtime(&dir2.path().join("entry"), "file", 1_000);
+        std::fs::create_dir(&empty_dir).unwrap();
+        filetime::set_file_mtime(&empty_dir, filetime::FileTime::from_unix_time(2_000, 0)).unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            ..Default::default()
+        };
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        // the directory is as recent as its own modification time
+        assert!(dir1.path().join("entry").is_dir());
+        assert!(dir2.path().join("entry").is_dir());
+    }
+
+    #[test]
+    fn file_with_dir_ignores_excluded() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let dir = dir1.path().join("entry");
+
+        write_with_mtime(&dir2.path().join("entry"), "file", 2_000);
+        std::fs::create_dir(&dir).unwrap();
+        write_with_mtime(&dir.join("old"), "old", 1_000);
+        write_with_mtime(&dir.join("new.tmp"), "new", 3_000);
+        filetime::set_file_mtime(&dir, filetime::FileTime::from_unix_time(1_000, 0)).unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            exclude: vec![super::Pattern::new("**/*.tmp").unwrap()],
+            ..Default::default()
+        };
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        assert_eq!(std::fs::read(dir1.path().join("entry")).unwrap(), b"file");
+    }
+
+    #[test]
+    fn failed_file_with_dir_keeps_file() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let entry2 = dir2.path().join("entry");
+
+        write_with_mtime(&entry2, "old file", 1_000);
+        std::fs::create_dir(dir1.path().join("entry")).unwrap();
+        write_with_mtime(&dir1.path().join("entry").join("a"), "a", 2_000);
+        write_with_mtime(&dir1.path().join("entry").join("b"), "b", 2_000);
+
+        // stop in the middle of copying the directory
+        let cancel = Arc::new(AtomicBool::new(false));
+        let cancel_on_progress = Arc::clone(&cancel);
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            on_progress: Some(Box::new(move |progress| {
+                if progress.path.starts_with("entry") {
+                    cancel_on_progress.store(true, Ordering::SeqCst);
+                }
+            })),
+            cancel: Some(cancel),
+            ..Default::default()
+        };
+        let result = super::synchronize_with(dir1.path(), dir2.path(), &options);
+
+        assert!(matches!(result, Err(super::SyncError::Aborted)));
+        assert_eq!(std::fs::read(&entry2).unwrap(), b"old file");
+        assert_eq!(std::fs::read_dir(dir2.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn walk_error_file_with_dir_keeps_file() {
+        use super::ErrorHandlingType::{Fail, Skip};
+
+        for handling in [Fail, Skip].iter().copied() {
+            let dir1 = tempfile::tempdir().unwrap();
+            let dir2 = tempfile::tempdir().unwrap();
+            let (entry1, entry2) = (dir1.path().join("entry"), dir2.path().join("entry"));
+
+            write_with_mtime(&entry2, "old file", 1_000);
+            std::fs::create_dir(&entry1).unwrap();
+            std::fs::write(entry1.join("a"), "a").unwrap();
+            std::os::unix::fs::symlink(&entry1, entry1.join("loop")).unwrap();
+
+            // the loop is first met looking for the latest modification time of the directory
+            let errors = std::cell::Cell::new(0);
+            let options = super::SyncOptions {
+                error_handler: Box::new(move |_| match errors.replace(errors.get() + 1) {
+                    0 => super::ErrorHandlingType::Ignore,
+                    _ => handling,
+                }),
+                follow_symlinks: true,
+                ..Default::default()
+            };
+            let result = super::synchronize_with(&entry1, &entry2, &options);
+
+            assert_eq!(result.is_ok(), handling == Skip);
+            assert_eq!(std::fs::read(&entry2).unwrap(), b"old file");
+            assert!(!super::staged_path(&entry2).exists());
+        }
+    }
+
+    #[test]
+    fn rsync_trailing_slash() {
+        let src = tempfile::tempdir().unwrap();
+        let dst = tempfile::tempdir().unwrap();
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            rsync_trailing_slash: true,
+            ..Default::default()
+        };
+
+        std::fs::write(src.path().join("file"), "f").unwrap();
+        let src_name = src.path().file_name().unwrap();
+
+        let mut with_slash = src.path().as_os_str().to_owned();
+        with_slash.push("/");
+        let result = super::synchronize_with(with_slash.as_ref(), dst.path(), &options);
+
+        assert!(result.is_ok());
+        assert!(dst.path().join("file").is_file());
+        assert!(!dst.path().join(src_name).exists());
+
+        let result = super::synchronize_with(src.path(), dst.path(), &options);
+
+        assert!(result.is_ok());
+        assert!(dst.path().join(src_name).join("file").is_file());
+    }
+
+    #[test]
+    fn merge_root_bundle() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let app1 = dir1.path().join("Editor.app");
+        let app2 = dir2.path().join("Editor.app");
+
+        std::fs::create_dir(&app1).unwrap();
+        std::fs::create_dir(&app2).unwrap();
+        std::fs::write(app1.join("only1"), "1").unwrap();
+        std::fs::write(app2.join("only2"), "2").unwrap();
+        write_with_mtime(&app1.join("both"), "old", 1_000);
+        write_with_mtime(&app2.join("both"), "new", 2_000);
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            merge_root_bundle: true,
+            ..Default::default()
+        };
+        let result = super::synchronize_with(&app1, &app2, &options);
+
+        assert!(result.is_ok());
+        for app in [&app1, &app2] {
+            assert!(app.join("only1").is_file());
+            assert!(app.join("only2").is_file());
+            assert_eq!(std::fs::read(app.join("both")).unwrap(), b"new");
+        }
+    }
+
+    fn readonly_dest(policy: super::ReadonlyPolicy) -> (tempfile::TempDir, super::SyncReport) {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let target = dir2.path().join("file");
+
+        write_with_mtime(&dir1.path().join("file"), "new", 2_000);
+        write_with_mtime(&target, "old", 1_000);
+        let mut permissions = std::fs::metadata(&target).unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&target, permissions).unwrap();
+
+        let options = super::SyncOptions {
+            readonly_dest: policy,
+            ..Default::default()
+        };
+        let report = super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        (dir2, report)
+    }
+
+    #[test]
+    fn readonly_dest_skip() {
+        let (dir2, report) = readonly_dest(super::ReadonlyPolicy::Skip);
+        let target = dir2.path().join("file");
+
+        assert_eq!(std::fs::read(&target).unwrap(), b"old");
+        assert_eq!(report.readonly_skipped, vec![target]);
+    }
+
+    #[test]
+    fn readonly_dest_overwrite() {
+        let (dir2, report) = readonly_dest(super::ReadonlyPolicy::Overwrite);
+        let target = dir2.path().join("file");
+
+        assert_eq!(std::fs::read(&target).unwrap(), b"new");
+        assert!(std::fs::metadata(&target).unwrap().permissions().readonly());
+        assert_eq!(report.readonly_overwritten, vec![target]);
+    }
+
+    #[test]
+    fn compare_time_field() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let path1 = dir1.path().join("file");
+        let path2 = dir2.path().join("file");
+
+        // the second file is modified last but the first one is created last
+        write_with_mtime(&path2, "modified last", 2_000);
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        write_with_mtime(&path1, "created last", 1_000);
+
+        let mut options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            direction: super::Direction::Push,
+            compare_time_field: super::TimeField::Birthtime,
+            ..Default::default()
+        };
+        let report = super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        let has_birthtime = std::fs::metadata(&path1).unwrap().created().is_ok();
+        if has_birthtime {
+            assert!(report.warnings.is_empty());
+            assert_eq!(std::fs::read(&path2).unwrap(), b"created last");
+        } else {
+            assert_eq!(report.warnings.len(), 1);
+            assert_eq!(std::fs::read(&path2).unwrap(), b"modified last");
+        }
+
+        // the copy is created after its source, which doesn't make it copied again
+        let report = super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+        assert_eq!(report.stats.files_copied, 0);
+
+        options.direction = super::Direction::Bidirectional;
+        options.error_handler = Box::new(|_| super::ErrorHandlingType::Fail);
+        assert!(matches!(
+            super::synchronize_with(dir1.path(), dir2.path(), &options),
+            Err(super::SyncError::InvalidOptions(_))
+        ));
+    }
+
+    #[test]
+    fn exclude_paths() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir_all(dir1.path().join("cache").join("nested")).unwrap();
+        std::fs::write(dir1.path().join("cache").join("nested").join("file"), "c").unwrap();
+        std::fs::write(dir1.path().join("huge"), "h").unwrap();
+        std::fs::write(dir1.path().join("kept"), "k").unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            exclude_paths: vec![
+                std::path::PathBuf::from("cache"),
+                dir1.path().join("huge"),
+                std::path::PathBuf::from("/not/inside/any/root"),
+            ],
+            ..Default::default()
+        };
+        let report = super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        assert!(dir2.path().join("kept").exists());
+        assert!(!dir2.path().join("cache").exists());
+        assert!(!dir2.path().join("huge").exists());
+        assert_eq!(report.filtered.excluded_paths, 2);
+        assert_eq!(report.warnings.len(), 1);
+    }
+
+    /// Kinds of entries for type conflict tests.
+    #[cfg(unix)]
+    #[derive(Clone, Copy, Debug)]
+    enum Kind {
+        File,
+        Dir,
+        LinkToFile,
+        LinkToDir,
+        BrokenLink,
+    }
+
+    #[cfg(unix)]
+    fn create(path: &std::path::Path, kind: Kind, targets: &std::path::Path) {
+        match kind {
+            Kind::File => write_with_mtime(path, "file", 1_000),
+            Kind::Dir => std::fs::create_dir(path).unwrap(),
+            Kind::LinkToFile => std::os::unix::fs::symlink(targets.join("file"), path).unwrap(),
+            Kind::LinkToDir => std::os::unix::fs::symlink(targets.join("dir"), path).unwrap(),
+            Kind::BrokenLink => std::os::unix::fs::symlink(targets.join("none"), path).unwrap(),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_type_conflicts() {
+        use Kind::*;
+
+        let targets = tempfile::tempdir().unwrap();
+        write_with_mtime(&targets.path().join("file"), "target", 2_000);
+        std::fs::create_dir(targets.path().join("dir")).unwrap();
+
+        let pairs = [
+            (LinkToFile, File),
+            (File, LinkToFile),
+            (LinkToDir, Dir),
+            (Dir, LinkToDir),
+            (LinkToFile, Dir),
+            (Dir, LinkToFile),
+            (LinkToDir, File),
+            (File, LinkToDir),
+            (BrokenLink, File),
+            (File, BrokenLink),
+            (BrokenLink, Dir),
+            (Dir, BrokenLink),
+        ];
+
+        for (kind1, kind2) in pairs.iter().copied() {
+            let dir1 = tempfile::tempdir().unwrap();
+            let dir2 = tempfile::tempdir().unwrap();
+            let path1 = dir1.path().join("x");
+            let path2 = dir2.path().join("x");
+            create(&path1, kind1, targets.path());
+            create(&path2, kind2, targets.path());
+
+            let file_type = |path| std::fs::symlink_metadata(path).unwrap().file_type();
+            let (type1, type2) = (file_type(&path1), file_type(&path2));
+
+            let report = super::synchronize_with(
+                dir1.path(),
+                dir2.path(),
+                &super::SyncOptions {
+                    error_handler: Box::new(move |err| panic!("{:?} {:?}: {}", kind1, kind2, err)),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+            assert_eq!(report.type_conflicts, vec![(path1.clone(), path2.clone())]);
+            assert_eq!(file_type(&path1), type1, "{:?} {:?}", kind1, kind2);
+            assert_eq!(file_type(&path2), type2, "{:?} {:?}", kind1, kind2);
+        }
+
+        assert_eq!(
+            std::fs::read(targets.path().join("file")).unwrap(),
+            b"target"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_type_mismatch_follow_links() {
+        let targets = tempfile::tempdir().unwrap();
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        write_with_mtime(&targets.path().join("file"), "target", 2_000);
+        create(&dir1.path().join("x"), Kind::LinkToFile, targets.path());
+        create(&dir2.path().join("x"), Kind::File, targets.path());
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            type_mismatch: super::TypeMismatchPolicy::FollowLinks,
+            ..Default::default()
+        };
+        let report = super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        assert!(report.type_conflicts.is_empty());
+        assert_eq!(std::fs::read(dir2.path().join("x")).unwrap(), b"target");
+    }
+
+    #[test]
+    fn error_skip_continues() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        for name in ["a?", "b", "c?", "d"] {
+            std::fs::write(dir1.path().join(name), name).unwrap();
+        }
+
+        let errors = std::rc::Rc::new(std::cell::Cell::new(0));
+        let options = super::SyncOptions {
+            error_handler: Box::new({
+                let errors = errors.clone();
+                move |_| {
+                    errors.set(errors.get() + 1);
+                    super::ErrorHandlingType::Skip
+                }
+            }),
+            sanitize: Some(super::SanitizePolicy::fat(super::SanitizeAction::Skip)),
+            ..Default::default()
+        };
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        assert_eq!(errors.get(), 2);
+        assert!(dir2.path().join("b").exists());
+        assert!(dir2.path().join("d").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn walk_error_fails() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir(dir1.path().join("sub")).unwrap();
+        std::fs::write(dir1.path().join("sub").join("a"), "a").unwrap();
+        // only reported once the walk reaches it
+        std::os::unix::fs::symlink(dir1.path(), dir1.path().join("sub").join("loop")).unwrap();
+
+        let errors = std::rc::Rc::new(std::cell::Cell::new(0));
+        let options = super::SyncOptions {
+            error_handler: Box::new({
+                let errors = errors.clone();
+                move |_| {
+                    errors.set(errors.get() + 1);
+                    super::ErrorHandlingType::Fail
+                }
+            }),
+            follow_symlinks: true,
+            ..Default::default()
+        };
+
+        assert!(super::synchronize_with(dir1.path(), dir2.path(), &options).is_err());
+        assert_eq!(errors.get(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn walk_error_fails_copy_dir() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let target = dir2.path().join("backup");
+
+        std::fs::create_dir(dir1.path().join("sub")).unwrap();
+        std::fs::write(dir1.path().join("sub").join("a"), "a").unwrap();
+        // only reported once the copy reaches it
+        std::os::unix::fs::symlink(dir1.path(), dir1.path().join("sub").join("loop")).unwrap();
+
+        let errors = std::rc::Rc::new(std::cell::Cell::new(0));
+        let options = super::SyncOptions {
+            error_handler: Box::new({
+                let errors = errors.clone();
+                move |_| {
+                    errors.set(errors.get() + 1);
+                    super::ErrorHandlingType::Fail
+                }
+            }),
+            follow_symlinks: true,
+            create_missing_target: true,
+            ..Default::default()
+        };
+
+        assert!(super::synchronize_with(dir1.path(), &target, &options).is_err());
+        assert_eq!(errors.get(), 1);
+    }
+
+    #[test]
+    fn rebase_strip_prefix() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir_all(dir1.path().join("photos").join("2024")).unwrap();
+        std::fs::create_dir_all(dir1.path().join("other")).unwrap();
+        std::fs::create_dir_all(dir2.path().join("2024")).unwrap();
+        std::fs::write(dir1.path().join("photos").join("2024").join("a.jpg"), "a").unwrap();
+        std::fs::write(dir1.path().join("other").join("b"), "b").unwrap();
+        std::fs::write(dir2.path().join("2024").join("c.jpg"), "c").unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            rebase: Some(super::Rebase::new("photos", "")),
+            ..Default::default()
+        };
+        let result = super::synchronize_with(dir1.path(), dir2.path(), &options);
+
+        assert!(result.is_ok());
+        assert!(dir2.path().join("2024").join("a.jpg").is_file());
+        assert!(dir1
+            .path()
+            .join("photos")
+            .join("2024")
+            .join("c.jpg")
+            .is_file());
+        assert!(!dir2.path().join("photos").exists());
+        assert!(!dir2.path().join("other").exists());
+        assert!(!dir1.path().join("2024").exists());
+    }
+
+    #[test]
+    fn rebase_add_prefix() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        std::fs::write(dir1.path().join("a"), "a").unwrap();
+        std::fs::write(dir2.path().join("outside"), "o").unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            rebase: Some(super::Rebase::new("", "backup")),
+            ..Default::default()
+        };
+        let result = super::synchronize_with(dir1.path(), dir2.path(), &options);
+
+        assert!(result.is_ok());
+        assert!(dir2.path().join("backup").join("a").is_file());
+        assert!(!dir1.path().join("outside").exists());
+        assert!(!dir1.path().join("backup").exists());
+    }
+
+    #[test]
+    fn low_memory() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        // deeper than the directories walkdir keeps open by default
+        let deep: std::path::PathBuf = (0..20).map(|depth| depth.to_string()).collect();
+        std::fs::create_dir_all(dir1.path().join(&deep)).unwrap();
+        std::fs::write(dir1.path().join(&deep).join("a"), "a").unwrap();
+        std::fs::write(dir1.path().join("0").join("b"), "b").unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            low_memory: true,
+            ..Default::default()
+        };
+        let result = super::synchronize_with(dir1.path(), dir2.path(), &options);
+
+        assert!(result.is_ok());
+        assert!(dir2.path().join(&deep).join("a").is_file());
+        assert!(dir2.path().join("0").join("b").is_file());
+    }
+
+    #[test]
+    fn low_memory_finishes_left_dirs() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let new = dir1.path().join("new");
+
+        std::fs::create_dir_all(new.join("nested")).unwrap();
+        write_with_mtime(&new.join("nested").join("file"), "file", 1_000);
+        write_with_mtime(&dir1.path().join("other"), "other", 1_000);
+        for (dir, seconds) in [(&new, 2_000), (&new.join("nested"), 3_000)] {
+            filetime::set_file_mtime(dir, filetime::FileTime::from_unix_time(seconds, 0)).unwrap();
+        }
+        std::fs::create_dir_all(dir2.path().join("extra").join("nested")).unwrap();
+        std::fs::write(dir2.path().join("extra").join("nested").join("file"), "e").unwrap();
+        std::fs::write(dir2.path().join("extra_file"), "e").unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            direction: super::Direction::Mirror,
+            low_memory: true,
+            parallel: true,
+            ..Default::default()
+        };
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        let mtime = |path: std::path::PathBuf| {
+            filetime::FileTime::from_last_modification_time(&path.metadata().unwrap())
+                .unix_seconds()
+        };
+        assert_eq!(mtime(dir2.path().join("new")), 2_000);
+        assert_eq!(mtime(dir2.path().join("new").join("nested")), 3_000);
+        assert!(dir2
+            .path()
+            .join("new")
+            .join("nested")
+            .join("file")
+            .is_file());
+        assert!(dir2.path().join("other").is_file());
+        assert!(!dir2.path().join("extra").exists());
+        assert!(!dir2.path().join("extra_file").exists());
+    }
+
+    #[test]
+    fn metadata_only() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        write_with_mtime(&dir1.path().join("same"), "same", 2_000);
+        write_with_mtime(&dir2.path().join("same"), "same", 1_000);
+        write_with_mtime(&dir1.path().join("resized"), "longer", 2_000);
+        write_with_mtime(&dir2.path().join("resized"), "short", 1_000);
+        write_with_mtime(&dir1.path().join("missing"), "missing", 2_000);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            // only the permissions differ
+            for (dir, mode) in [(&dir1, 0o600), (&dir2, 0o644)] {
+                let path = dir.path().join("private");
+                write_with_mtime(&path, "private", 1_000);
+                std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+            }
+        }
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            metadata_only: true,
+            ..Default::default()
+        };
+        let report = super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        let mtime = |path: std::path::PathBuf| {
+            filetime::FileTime::from_last_modification_time(&std::fs::metadata(path).unwrap())
+        };
+
+        assert_eq!(mtime(dir2.path().join("same")).unix_seconds(), 2_000);
+        assert!(report.metadata_updated.contains(&dir2.path().join("same")));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = |path: std::path::PathBuf| {
+                std::fs::metadata(path).unwrap().permissions().mode() & 0o777
+            };
+            assert_eq!(mode(dir2.path().join("private")), 0o600);
+            assert_eq!(mtime(dir2.path().join("private")).unix_seconds(), 1_000);
+            assert_eq!(report.metadata_updated.len(), 2);
+        }
+        assert_eq!(
+            std::fs::read_to_string(dir2.path().join("resized")).unwrap(),
+            "short"
+        );
+        assert_eq!(mtime(dir2.path().join("resized")).unix_seconds(), 1_000);
+        assert_eq!(
+            report.size_mismatches,
+            vec![(dir1.path().join("resized"), dir2.path().join("resized"))]
+        );
+        assert!(!dir2.path().join("missing").exists());
+    }
+
+    #[test]
+    fn synchronize_twice_copies_once() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let file1 = dir1.path().join("a");
+        let file2 = dir2.path().join("a");
+
+        std::fs::write(&file1, "new").unwrap();
+        std::fs::write(&file2, "old").unwrap();
+        filetime::set_file_mtime(&file1, filetime::FileTime::from_unix_time(2_000, 0)).unwrap();
+        filetime::set_file_mtime(&file2, filetime::FileTime::from_unix_time(1_000, 0)).unwrap();
+
+        let result = super::synchronize(dir1.path(), dir2.path(), |err| panic!("{}", err));
+        assert!(result.is_ok());
+
+        let mtime = |path: &std::path::Path| {
+            filetime::FileTime::from_last_modification_time(&std::fs::metadata(path).unwrap())
+        };
+        assert_eq!(std::fs::read_to_string(&file2).unwrap(), "new");
+        assert_eq!(mtime(&file1).unix_seconds(), 2_000);
+        assert_eq!(mtime(&file2).unix_seconds(), 2_000);
+
+        // any copy to a read-only side is reported as an error
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            side1: super::SideOptions { read_only: true },
+            side2: super::SideOptions { read_only: true },
+            ..Default::default()
+        };
+        let result = super::synchronize_with(dir1.path(), dir2.path(), &options);
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn synchronize_many() {
+        let dirs: Vec<_> = (0..3).map(|_| tempfile::tempdir().unwrap()).collect();
+        let paths: Vec<_> = dirs.iter().map(|dir| dir.path()).collect();
+
+        for (dir, unix_time) in paths.iter().zip([1_000, 3_000, 2_000]) {
+            let file = dir.join("a");
+            std::fs::write(&file, unix_time.to_string()).unwrap();
+            filetime::set_file_mtime(&file, filetime::FileTime::from_unix_time(unix_time, 0))
+                .unwrap();
+        }
+        std::fs::write(paths[2].join("only_in_last"), "last").unwrap();
+
+        let result = super::synchronize_many(&paths, |err| panic!("{}", err));
+        assert!(result.is_ok());
+
+        for dir in paths {
+            let file = dir.join("a");
+            let mtime = filetime::FileTime::from_last_modification_time(&file.metadata().unwrap());
+
+            assert_eq!(std::fs::read_to_string(&file).unwrap(), "3000");
+            assert_eq!(mtime.unix_seconds(), 3_000);
+            assert!(dir.join("only_in_last").is_file());
+        }
+    }
+
+    #[test]
+    fn dry_run() {
+        use super::PlannedAction;
+
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let time = filetime::FileTime::from_unix_time(2_000, 0);
+
+        std::fs::write(dir1.path().join("newer"), "new").unwrap();
+        std::fs::write(dir2.path().join("newer"), "old").unwrap();
+        std::fs::create_dir(dir1.path().join("missing")).unwrap();
+        std::fs::write(dir1.path().join("missing").join("a"), "a").unwrap();
+        filetime::set_file_mtime(dir1.path().join("newer"), time).unwrap();
+        filetime::set_file_mtime(dir1.path().join("missing").join("a"), time).unwrap();
+        filetime::set_file_mtime(dir1.path().join("missing"), time).unwrap();
+        filetime::set_file_mtime(dir2.path().join("newer"), filetime::FileTime::zero()).unwrap();
+
+        let before = (snapshot(dir1.path()), snapshot(dir2.path()));
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            dry_run: true,
+            ..Default::default()
+        };
+        let report = super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        assert_eq!(before, (snapshot(dir1.path()), snapshot(dir2.path())));
+
+        let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(2_000);
+        let copy_and_set_times = |path: &str| {
+            let path = std::path::Path::new(path);
+            vec![
+                PlannedAction::Copy {
+                    from: dir1.path().join(path),
+                    to: dir2.path().join(path),
+                },
+                PlannedAction::SetTimes {
+                    path: dir2.path().join(path),
+                    mtime,
+                },
+            ]
+        };
+
+        let mut expected = vec![PlannedAction::CreateDir(dir2.path().join("missing"))];
+        expected.extend(copy_and_set_times("missing/a"));
+        expected.extend(copy_and_set_times("newer"));
+        expected.push(PlannedAction::SetTimes {
+            path: dir2.path().join("missing"),
+            mtime,
+        });
+
+        let mut planned = report.planned;
+        let key = |action: &PlannedAction| format!("{:?}", action);
+        planned.sort_by_key(key);
+        expected.sort_by_key(key);
+        assert_eq!(planned, expected);
+    }
+
+    #[test]
+    fn on_plan() {
+        use super::{PlannedAction, SyncPlan};
+        use std::cell::{Cell, RefCell};
+        use std::rc::Rc;
+
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        std::fs::write(dir1.path().join("newer"), "new").unwrap();
+        std::fs::write(dir2.path().join("newer"), "old").unwrap();
+        std::fs::create_dir(dir1.path().join("missing")).unwrap();
+        std::fs::write(dir1.path().join("missing").join("a"), "a").unwrap();
+        filetime::set_file_mtime(dir2.path().join("newer"), filetime::FileTime::zero()).unwrap();
+
+        let plans = Rc::new(RefCell::new(Vec::new()));
+        let approved = Rc::new(Cell::new(false));
+        let actions = Rc::new(Cell::new(0));
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            on_plan: Some(Box::new({
+                let (plans, approved) = (plans.clone(), approved.clone());
+                move |plan: &SyncPlan| {
+                    plans.borrow_mut().push(plan.clone());
+                    approved.get()
+                }
+            })),
+            on_action: Some(Box::new({
+                let actions = actions.clone();
+                move |_| actions.set(actions.get() + 1)
+            })),
+            ..Default::default()
+        };
+
+        let before = (snapshot(dir1.path()), snapshot(dir2.path()));
+        assert!(matches!(
+            super::synchronize_with(dir1.path(), dir2.path(), &options),
+            Err(super::SyncError::Rejected)
+        ));
+        assert_eq!(before, (snapshot(dir1.path()), snapshot(dir2.path())));
+        // only the approved plan is announced
+        assert_eq!(actions.get(), 0);
+
+        let plan = plans.borrow_mut().remove(0);
+        assert_eq!(
+            (plan.files_to_copy, plan.bytes_to_copy, plan.dirs_to_create),
+            (2, 4, 1)
+        );
+        assert_eq!(
+            plan.destructive,
+            [PlannedAction::Copy {
+                from: dir1.path().join("newer"),
+                to: dir2.path().join("newer"),
+            }]
+        );
+        assert!(plan
+            .actions
+            .contains(&PlannedAction::CreateDir(dir2.path().join("missing"))));
+
+        approved.set(true);
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        assert_eq!(plans.borrow().len(), 1);
+        assert!(actions.get() > 0);
+        assert_eq!(
+            std::fs::read_to_string(dir2.path().join("newer")).unwrap(),
+            "new"
+        );
+        assert!(dir2.path().join("missing").join("a").is_file());
+    }
+
+    #[test]
+    fn stats() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir_all(dir1.path().join("a").join("b")).unwrap();
+        std::fs::write(dir1.path().join("a").join("b").join("c"), "123").unwrap();
+        std::fs::write(dir1.path().join("d"), "45").unwrap();
+        std::fs::write(dir2.path().join("d"), "6").unwrap();
+        filetime::set_file_mtime(dir2.path().join("d"), filetime::FileTime::zero()).unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            ..Default::default()
+        };
+        let report = super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        assert_eq!(
+            report.stats,
+            super::SyncStats {
+                files_copied: 2,
+                bytes_copied: 5,
+                dirs_created: 2,
+                files_deleted: 0,
+                dirs_deleted: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn compare_content() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        let write = |path: std::path::PathBuf, content: &str, unix_time: i64| {
+            std::fs::write(&path, content).unwrap();
+            filetime::set_file_mtime(&path, filetime::FileTime::from_unix_time(unix_time, 0))
+                .unwrap();
+        };
+
+        write(dir1.path().join("same_mtime"), "longer", 1_000);
+        write(dir2.path().join("same_mtime"), "short", 1_000);
+        write(dir1.path().join("same_content"), "same", 1_000);
+        write(dir2.path().join("same_content"), "same", 2_000);
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            compare: super::CompareMode::Content,
+            ..Default::default()
+        };
+        let report = super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(dir2.path().join("same_mtime")).unwrap(),
+            "longer"
+        );
+        // identical contents are never copied
+        assert_eq!(report.stats.files_copied, 1);
+    }
+
+    #[test]
+    fn propagate_deletions() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir_all(dir1.path().join("sub").join("inner")).unwrap();
+        std::fs::write(dir1.path().join("sub").join("inner").join("a"), "a").unwrap();
+        std::fs::write(dir1.path().join("deleted"), "d").unwrap();
+        std::fs::write(dir1.path().join("modified"), "m").unwrap();
+        std::fs::write(dir1.path().join("kept"), "k").unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            propagate_deletions: true,
+            ..Default::default()
+        };
+
+        // nothing is deleted without the manifest of a previous synchronization
+        std::fs::write(dir2.path().join("new"), "n").unwrap();
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+        assert!(dir1.path().join("new").is_file());
+
+        std::fs::remove_file(dir2.path().join("deleted")).unwrap();
+        std::fs::remove_file(dir2.path().join("modified")).unwrap();
+        std::fs::remove_dir_all(dir2.path().join("sub")).unwrap();
+        std::fs::remove_file(dir1.path().join("new")).unwrap();
+        std::fs::write(dir1.path().join("modified"), "modified since").unwrap();
+        filetime::set_file_mtime(
+            dir1.path().join("modified"),
+            filetime::FileTime::from_unix_time(i32::MAX as i64, 0),
+        )
+        .unwrap();
+
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        for dir in [dir1.path(), dir2.path()] {
+            assert!(!dir.join("deleted").exists());
+            assert!(!dir.join("new").exists());
+            assert!(!dir.join("sub").exists());
+            assert!(dir.join("kept").is_file());
+            assert_eq!(
+                std::fs::read_to_string(dir.join("modified")).unwrap(),
+                "modified since"
+            );
+        }
+    }
+
+    #[test]
+    fn initialize() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        std::fs::write(dir1.path().join("a"), "a").unwrap();
+        std::fs::write(dir1.path().join("b"), "b").unwrap();
+
+        let mut options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            propagate_deletions: true,
+            ..Default::default()
+        };
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        // the manifest of the last synchronization would delete it from the first side
+        std::fs::remove_file(dir2.path().join("a")).unwrap();
+        options.initialize = true;
+        let report = super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        assert_eq!(report.stats.files_deleted, 0);
+        assert!(dir1.path().join("a").is_file());
+        assert!(dir2.path().join("a").is_file());
+
+        // the manifest recorded by the initialization is used afterwards
+        std::fs::remove_file(dir2.path().join("b")).unwrap();
+        options.initialize = false;
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        assert!(!dir1.path().join("b").exists());
+    }
+
+    #[test]
+    fn exclude_patterns() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let set_mtime = |path: &std::path::Path, unix_time| {
+            filetime::set_file_mtime(path, filetime::FileTime::from_unix_time(unix_time, 0))
+                .unwrap();
+        };
+
+        std::fs::create_dir_all(dir1.path().join("app").join("node_modules")).unwrap();
+        std::fs::write(dir1.path().join("app").join("node_modules").join("m"), "m").unwrap();
+        std::fs::write(dir1.path().join("app").join("main.js"), "j").unwrap();
+        std::fs::write(dir1.path().join(".DS_Store"), "d").unwrap();
+
+        // only the excluded subtree of the directory is newer than the file replacing it
+        std::fs::write(dir1.path().join("replaced"), "file").unwrap();
+        set_mtime(&dir1.path().join("replaced"), 2_000);
+        let replaced_dir = dir2.path().join("replaced");
+        std::fs::create_dir_all(replaced_dir.join("node_modules")).unwrap();
+        std::fs::write(replaced_dir.join("node_modules").join("m"), "m").unwrap();
+        std::fs::write(replaced_dir.join("old"), "old").unwrap();
+        set_mtime(&replaced_dir.join("node_modules").join("m"), 3_000);
+        set_mtime(&replaced_dir.join("node_modules"), 3_000);
+        set_mtime(&replaced_dir.join("old"), 1_000);
+        set_mtime(&replaced_dir, 1_000);
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            exclude: vec![
+                super::Pattern::new("**/node_modules").unwrap(),
+                super::Pattern::new("**/.DS_Store").unwrap(),
+            ],
+            ..Default::default()
+        };
+        let report = super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        assert!(dir2.path().join("app").join("main.js").is_file());
+        assert!(!dir2.path().join("app").join("node_modules").exists());
+        assert!(!dir2.path().join(".DS_Store").exists());
+        assert!(dir2.path().join("replaced").is_file());
+        assert!(report.filtered.excluded_patterns >= 2);
+    }
+
+    #[test]
+    fn ignore_file() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        std::fs::write(dir1.path().join(super::IGNORE_FILE_NAME), "*.log\n").unwrap();
+        std::fs::create_dir(dir1.path().join("sub")).unwrap();
+        std::fs::write(dir1.path().join("a.log"), "a").unwrap();
+        std::fs::write(dir1.path().join("sub").join("b.log"), "b").unwrap();
+        std::fs::write(dir1.path().join("sub").join("kept"), "kept").unwrap();
+        // only the patterns of its own root apply
+        std::fs::write(dir2.path().join("c.log"), "c").unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            ..Default::default()
+        };
+        let report = super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        assert!(!dir2.path().join("a.log").exists());
+        assert!(!dir2.path().join("sub").join("b.log").exists());
+        assert_eq!(
+            std::fs::read(dir2.path().join("sub").join("kept")).unwrap(),
+            b"kept"
+        );
+        assert_eq!(std::fs::read(dir1.path().join("c.log")).unwrap(), b"c");
+        assert_eq!(report.filtered.excluded_patterns, 2);
+    }
+
+    #[test]
+    fn include_patterns() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir_all(dir1.path().join("photos").join("raw")).unwrap();
+        std::fs::write(dir1.path().join("photos").join("a.jpg"), "a").unwrap();
+        std::fs::write(dir1.path().join("photos").join("a.txt"), "a").unwrap();
+        std::fs::write(dir1.path().join("photos").join("raw").join("b.jpg"), "b").unwrap();
+        std::fs::write(dir1.path().join("photos").join("raw").join("c.jpg"), "c").unwrap();
+        std::fs::write(dir1.path().join("notes.txt"), "notes").unwrap();
+        // left untouched on the other side too
+        std::fs::write(dir2.path().join("todo.txt"), "todo").unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            include: vec![super::Pattern::new("**/*.jpg").unwrap()],
+            // excludes win over includes
+            exclude: vec![super::Pattern::new("**/c.jpg").unwrap()],
+            ..Default::default()
+        };
+        let report = super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        assert!(dir2.path().join("photos").join("a.jpg").is_file());
+        assert!(dir2
+            .path()
+            .join("photos")
+            .join("raw")
+            .join("b.jpg")
+            .is_file());
+        assert!(!dir2
+            .path()
+            .join("photos")
+            .join("raw")
+            .join("c.jpg")
+            .exists());
+        assert!(!dir2.path().join("photos").join("a.txt").exists());
+        assert!(!dir2.path().join("notes.txt").exists());
+        assert!(!dir1.path().join("todo.txt").exists());
+        assert_eq!(report.filtered.not_included, 3);
+        assert_eq!(report.filtered.excluded_patterns, 1);
+    }
+
+    #[test]
+    fn skip_hidden() {
+        let synchronize = |skip_hidden| {
+            let dir1 = tempfile::tempdir().unwrap();
+            let dir2 = tempfile::tempdir().unwrap();
+            std::fs::create_dir(dir1.path().join(".cache")).unwrap();
+            std::fs::write(dir1.path().join(".cache").join("data"), "data").unwrap();
+            std::fs::write(dir1.path().join(".hidden"), "hidden").unwrap();
+            std::fs::write(dir1.path().join("visible"), "visible").unwrap();
+
+            let options = super::SyncOptions {
+                error_handler: Box::new(|err| panic!("{}", err)),
+                skip_hidden,
+                ..Default::default()
+            };
+            let report = super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+            assert!(dir2.path().join("visible").is_file());
+            (dir2, report.filtered.hidden)
+        };
+
+        let (dir2, hidden) = synchronize(true);
+        assert!(!dir2.path().join(".hidden").exists());
+        assert!(!dir2.path().join(".cache").exists());
+        assert_eq!(hidden, 2);
+
+        let (dir2, hidden) = synchronize(false);
+        assert!(dir2.path().join(".hidden").is_file());
+        assert!(dir2.path().join(".cache").join("data").is_file());
+        assert_eq!(hidden, 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn copy_symlinks() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir(dir1.path().join("sub")).unwrap();
+        std::fs::write(dir1.path().join("sub").join("large"), "large").unwrap();
+        std::os::unix::fs::symlink("sub/large", dir1.path().join("link")).unwrap();
+        std::os::unix::fs::symlink("large", dir1.path().join("sub").join("link")).unwrap();
+        std::os::unix::fs::symlink("sub", dir1.path().join("dir_link")).unwrap();
+        std::os::unix::fs::symlink("missing", dir1.path().join("broken")).unwrap();
+        std::os::unix::fs::symlink("old", dir2.path().join("changed")).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        std::os::unix::fs::symlink("new", dir1.path().join("changed")).unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            ..Default::default()
+        };
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        let link_target = |path: std::path::PathBuf| {
+            assert!(path.symlink_metadata().unwrap().file_type().is_symlink());
+            std::fs::read_link(path).unwrap()
+        };
+
+        assert_eq!(
+            link_target(dir2.path().join("link")),
+            std::path::Path::new("sub/large")
+        );
+        assert_eq!(
+            link_target(dir2.path().join("sub").join("link")),
+            std::path::Path::new("large")
+        );
+        assert_eq!(
+            link_target(dir2.path().join("dir_link")),
+            std::path::Path::new("sub")
+        );
+        assert_eq!(
+            link_target(dir2.path().join("broken")),
+            std::path::Path::new("missing")
+        );
+        assert_eq!(
+            link_target(dir2.path().join("changed")),
+            std::path::Path::new("new")
+        );
+        assert_eq!(
+            link_target(dir1.path().join("changed")),
+            std::path::Path::new("new")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follow_symlinks() {
+        let dir1 = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir1.path().join("real")).unwrap();
+        std::fs::write(dir1.path().join("real").join("f"), "f").unwrap();
+        std::os::unix::fs::symlink("real", dir1.path().join("link")).unwrap();
+
+        let synchronize = |follow_symlinks| {
+            let dir2 = tempfile::tempdir().unwrap();
+            let options = super::SyncOptions {
+                error_handler: Box::new(|err| panic!("{}", err)),
+                follow_symlinks,
+                ..Default::default()
+            };
+            super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+            dir2
+        };
+
+        // a symbolic link to a directory is a leaf by default
+        let dir2 = synchronize(false);
+        let link = dir2.path().join("link");
+        assert!(link.symlink_metadata().unwrap().file_type().is_symlink());
+
+        let dir2 = synchronize(true);
+        let link = dir2.path().join("link");
+        assert!(link.symlink_metadata().unwrap().is_dir());
+        assert!(link.join("f").is_file());
+
+        // loops are reported
+        std::os::unix::fs::symlink("..", dir1.path().join("real").join("loop")).unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let errors = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let options = super::SyncOptions {
+            error_handler: Box::new({
+                let errors = errors.clone();
+                move |err| {
+                    errors.borrow_mut().push(err.to_string());
+                    super::ErrorHandlingType::Ignore
+                }
+            }),
+            follow_symlinks: true,
+            ..Default::default()
+        };
+        let result = super::synchronize_with(dir1.path(), dir2.path(), &options);
+
+        assert!(result.is_ok());
+        assert!(!errors.borrow().is_empty());
+        assert!(errors.borrow().iter().all(|err| err.contains("loop")));
+    }
+
+    #[test]
+    fn atomic_copy() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        std::fs::write(dir1.path().join("a"), "new").unwrap();
+        std::fs::write(dir1.path().join("b"), "b").unwrap();
+        std::fs::write(dir2.path().join("a"), "old").unwrap();
+        filetime::set_file_mtime(dir2.path().join("a"), filetime::FileTime::zero()).unwrap();
+        // left by an interrupted copy
+        std::fs::write(dir2.path().join(".b.keepkeeping-partial"), "").unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            ..Default::default()
+        };
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        assert_eq!(std::fs::read(dir2.path().join("a")).unwrap(), b"new");
+        assert_eq!(std::fs::read(dir2.path().join("b")).unwrap(), b"b");
+        for dir in [dir1.path(), dir2.path()] {
+            let names: Vec<_> = std::fs::read_dir(dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect();
+            assert_eq!(names.len(), 2, "{:?}", names);
+        }
+    }
+
+    #[test]
+    fn parallel() {
+        let synchronize = |parallel| {
+            let dir1 = tempfile::tempdir().unwrap();
+            let dir2 = tempfile::tempdir().unwrap();
+
+            for i in 0..500 {
+                let (dir, side) = if i % 5 == 0 {
+                    (dir2.path(), "2")
+                } else {
+                    (dir1.path(), "1")
+                };
+                let sub_dir = dir
+                    .join(format!("sub{}", i % 7))
+                    .join(format!("sub{}", i % 3));
+                std::fs::create_dir_all(&sub_dir).unwrap();
+                write_with_mtime(&sub_dir.join(i.to_string()), side, 1_000 + i);
+            }
+
+            let options = super::SyncOptions {
+                error_handler: Box::new(|err| panic!("{}", err)),
+                parallel,
+                jobs: Some(2),
+                ..Default::default()
+            };
+            let report = super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+            let relative_snapshot = |root: &std::path::Path| {
+                // directories are created when synchronizing, only compare files
+                snapshot(root)
+                    .into_iter()
+                    .filter(|(_, content, _)| !content.is_empty())
+                    .map(|(path, content, time)| {
+                        (path.strip_prefix(root).unwrap().to_owned(), content, time)
+                    })
+                    .collect::<Vec<_>>()
+            };
+            let (snapshot1, snapshot2) = (
+                relative_snapshot(dir1.path()),
+                relative_snapshot(dir2.path()),
+            );
+            assert_eq!(snapshot1, snapshot2);
+
+            (snapshot1, report.stats)
+        };
+
+        let (serial, serial_stats) = synchronize(false);
+        let (parallel, parallel_stats) = synchronize(true);
+
+        assert_eq!(serial.len(), 500);
+        assert_eq!(serial, parallel);
+        assert_eq!(serial_stats, parallel_stats);
+        assert_eq!(parallel_stats.files_copied, 500);
+    }
 
-                None
-            }
-        })
-        // Get path string representation
-        .filter_map(|absolute_path: PathBuf| match absolute_path.to_str() {
-            None => None,
-            Some(absolute_path_str) => Some(absolute_path_str.to_owned()),
-        })
-        // Get relative path (returns a PathBuf)
-        .filter_map(|absolute_path_str: String| match source.to_str() {
-            None => None,
-            Some(source_str) => trim_base_path(source_str, &absolute_path_str),
-        });
+    #[test]
+    fn conflict_keep_both() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
 
-    if *fail.borrow() {
-        return Err(());
-    } else if *skip.borrow() {
-        return Ok(());
+        write_with_mtime(&dir1.path().join("foo.txt"), "first", 1_000);
+        write_with_mtime(&dir2.path().join("foo.txt"), "second", 1_000);
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            compare: super::CompareMode::Content,
+            conflict_strategy: super::ConflictStrategy::KeepBoth,
+            ..Default::default()
+        };
+        let report = super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+        assert_eq!(report.warnings.len(), 1);
+
+        let conflict_name = format!("foo.conflict-{}.txt", super::host_name());
+        for dir in [dir1.path(), dir2.path()] {
+            let names: Vec<_> = std::fs::read_dir(dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect();
+            assert_eq!(names.len(), 2, "{:?}", names);
+
+            assert_eq!(std::fs::read(dir.join("foo.txt")).unwrap(), b"first");
+            assert_eq!(std::fs::read(dir.join(&conflict_name)).unwrap(), b"second");
+        }
     }
 
-    macro_rules! handle_on_error {
-        ($e:expr) => {
-            match $e {
-                Ok(_) => (),
-                Err(err) => {
-                    use ErrorHandlingType::*;
+    #[test]
+    fn max_depth() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
 
-                    match on_err(&err) {
-                        Fail => return Err(()),
-                        Skip | Ignore => return Ok(()),
+        let deepest = dir1.path().join("a").join("b").join("c");
+        std::fs::create_dir_all(&deepest).unwrap();
+        std::fs::write(deepest.join("file"), "deep").unwrap();
+        std::fs::write(dir1.path().join("a").join("file"), "middle").unwrap();
+        std::fs::write(dir1.path().join("file"), "top").unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            max_depth: Some(1),
+            ..Default::default()
+        };
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        assert_eq!(std::fs::read(dir2.path().join("file")).unwrap(), b"top");
+        assert!(dir2.path().join("a").is_dir());
+        assert_eq!(std::fs::read_dir(dir2.path().join("a")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn on_progress_bytes() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let content: Vec<u8> = (0..3 * 1024 * 1024 + 5).map(|i| i as u8).collect();
+        std::fs::write(dir1.path().join("big"), &content).unwrap();
+
+        let chunks = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            on_progress: Some(Box::new({
+                let chunks = chunks.clone();
+                move |progress: &super::Progress| {
+                    if let Some(bytes) = progress.bytes {
+                        assert_eq!(progress.path, std::path::Path::new("big"));
+                        chunks.borrow_mut().push(bytes);
                     }
                 }
+            })),
+            copy_buffer_size: 64 * 1024,
+            ..Default::default()
+        };
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        let size = content.len() as u64;
+        let chunks = chunks.borrow();
+        assert_eq!(std::fs::read(dir2.path().join("big")).unwrap(), content);
+        assert!(chunks.len() > 1);
+        assert!(chunks.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(chunks.iter().all(|&(_, total)| total == size));
+        assert_eq!(chunks.last(), Some(&(size, size)));
+    }
+
+    #[test]
+    fn cancel() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        for i in 0..10 {
+            std::fs::write(dir1.path().join(i.to_string()), "content").unwrap();
+        }
+
+        let cancel = std::sync::Arc::new(AtomicBool::new(false));
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            // cancelled while copying the first file
+            on_progress: Some(Box::new({
+                let cancel = cancel.clone();
+                move |_| cancel.store(true, Ordering::Relaxed)
+            })),
+            cancel: Some(cancel),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            super::synchronize_with(dir1.path(), dir2.path(), &options),
+            Err(super::SyncError::Aborted)
+        ));
+        // the interrupted copy is removed
+        assert_eq!(std::fs::read_dir(dir2.path()).unwrap().count(), 0);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn cancel_parallel() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        for i in 0..10 {
+            std::fs::write(dir1.path().join(i.to_string()), "content").unwrap();
+        }
+
+        let cancel = std::sync::Arc::new(AtomicBool::new(false));
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            // cancelled before the copies are started
+            on_action: Some(Box::new({
+                let cancel = cancel.clone();
+                move |_: &super::PlannedAction| cancel.store(true, Ordering::Relaxed)
+            })),
+            cancel: Some(cancel),
+            parallel: true,
+            jobs: Some(2),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            super::synchronize_with(dir1.path(), dir2.path(), &options),
+            Err(super::SyncError::Aborted)
+        ));
+        assert_eq!(std::fs::read_dir(dir2.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn missing_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let other_dir = tempfile::tempdir().unwrap();
+        let (file, missing1, missing2) = (
+            dir.path().join("file"),
+            dir.path().join("missing1"),
+            dir.path().join("missing2"),
+        );
+        std::fs::write(&file, "file").unwrap();
+
+        for (path1, path2, missing) in [
+            (&missing1, &file, &missing1),
+            (&file, &missing2, &missing2),
+            (&missing1, &missing2, &missing1),
+            (&missing1, &other_dir.path().to_owned(), &missing1),
+        ] {
+            let options = super::SyncOptions {
+                error_handler: Box::new(|_| super::ErrorHandlingType::Fail),
+                ..Default::default()
+            };
+
+            match super::synchronize_with(path1, path2, &options) {
+                Err(super::SyncError::NotFound(path)) => assert_eq!(&path, missing),
+                result => panic!("unexpected result: {:?}", result),
             }
+            assert!(!missing1.exists() && !missing2.exists());
+        }
+    }
+
+    #[test]
+    fn deadline() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        for i in 0..100 {
+            std::fs::write(dir1.path().join(i.to_string()), "content").unwrap();
+        }
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            // every copy takes a while
+            on_action: Some(Box::new(|_: &super::PlannedAction| {
+                std::thread::sleep(std::time::Duration::from_millis(20))
+            })),
+            deadline: Some(std::time::Instant::now() + std::time::Duration::from_millis(100)),
+            ..Default::default()
         };
+
+        match super::synchronize_with(dir1.path(), dir2.path(), &options) {
+            Err(super::SyncError::TimedOut { stats }) => {
+                assert!(stats.files_copied > 0);
+                assert!(stats.files_copied < 100);
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
     }
 
-    for relative_path in relative_path_iter {
-        let relative_path: &Path = &relative_path;
-        let path_in_dir = source.join(relative_path);
-        let path_in_file = target.join(relative_path);
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn deadline_parallel() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        for i in 0..10 {
+            std::fs::write(dir1.path().join(i.to_string()), "content").unwrap();
+        }
 
-        if path_in_dir.is_dir() {
-            handle_on_error!(fs::create_dir(&path_in_file));
-        } else {
-            handle_on_error!(fs::copy(&path_in_dir, &path_in_file));
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            // the deadline is reached before the copies are started
+            on_action: Some(Box::new(|_: &super::PlannedAction| {
+                std::thread::sleep(std::time::Duration::from_millis(20))
+            })),
+            deadline: Some(std::time::Instant::now() + std::time::Duration::from_millis(10)),
+            parallel: true,
+            jobs: Some(2),
+            ..Default::default()
+        };
+
+        match super::synchronize_with(dir1.path(), dir2.path(), &options) {
+            Err(super::SyncError::TimedOut { stats }) => assert_eq!(stats.files_copied, 0),
+            result => panic!("unexpected result: {:?}", result),
         }
+        assert_eq!(std::fs::read_dir(dir2.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn mac_app_on_one_side() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let macos = dir1
+            .path()
+            .join("Editor.app")
+            .join("Contents")
+            .join("MacOS");
+        std::fs::create_dir_all(&macos).unwrap();
+        std::fs::write(macos.join("editor"), "binary").unwrap();
+        std::fs::write(macos.parent().unwrap().join("Info.plist"), "plist").unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            dry_run: true,
+            ..Default::default()
+        };
+        let report = super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+        // the content of the app isn't synchronized again once copied
+        let copies = report
+            .planned
+            .iter()
+            .filter(|action| matches!(action, super::PlannedAction::Copy { .. }))
+            .count();
+        assert_eq!(copies, 2);
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            ..Default::default()
+        };
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
 
-        handle_on_error!(filetime::set_file_times(&path_in_file, time, time));
+        let contents = dir2.path().join("Editor.app").join("Contents");
+        assert_eq!(
+            std::fs::read(contents.join("MacOS").join("editor")).unwrap(),
+            b"binary"
+        );
+        assert_eq!(
+            std::fs::read(contents.join("Info.plist")).unwrap(),
+            b"plist"
+        );
     }
 
-    handle_on_error!(filetime::set_file_times(target, time, time));
+    #[test]
+    fn on_action() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        std::fs::write(dir1.path().join("a"), "a").unwrap();
 
-    Ok(())
-}
+        let actions = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            on_action: Some(Box::new({
+                let actions = actions.clone();
+                move |action: &super::PlannedAction| actions.borrow_mut().push(action.clone())
+            })),
+            ..Default::default()
+        };
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
 
-/// Synchronize 2 directories, only keeps the one with the latest modification time.
-fn synchronize_dirs_replace<'t1, 't2, FErr>(
-    dir1_path: &'t1 Path,
-    dir2_path: &'t2 Path,
-    on_err: &FErr,
-) -> Result<(), ()>
-where
-    FErr: Fn(&dyn std::error::Error) -> ErrorHandlingType,
-{
-    /// Unwrap or print error and return.
-    macro_rules! unwrap_result {
-        ($e:expr) => {
-            match $e {
-                Ok(x) => x,
-                Err(err) => {
-                    use ErrorHandlingType::*;
+        assert!(dir2.path().join("a").exists());
+        assert!(actions.borrow().contains(&super::PlannedAction::Copy {
+            from: dir1.path().join("a"),
+            to: dir2.path().join("a"),
+        }));
+    }
 
-                    match on_err(&err) {
-                        Fail => return Err(()),
-                        Skip | Ignore => return Ok(()),
+    #[test]
+    fn verify() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir1.path().join("sub")).unwrap();
+        std::fs::write(dir1.path().join("sub").join("a"), "a").unwrap();
+        std::fs::write(dir2.path().join("b"), "b").unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            verify: true,
+            ..Default::default()
+        };
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        // the copy goes wrong right after being written
+        let corrupted = dir2.path().join("c");
+        std::fs::write(dir1.path().join("c"), "c").unwrap();
+        let options = super::SyncOptions {
+            error_handler: Box::new(|_| super::ErrorHandlingType::Fail),
+            on_action: Some(Box::new({
+                let corrupted = corrupted.clone();
+                move |action: &super::PlannedAction| {
+                    if let super::PlannedAction::SetTimes { path, .. } = action {
+                        if *path == corrupted {
+                            std::fs::write(path, "corrupted").unwrap();
+                        }
                     }
                 }
-            }
+            })),
+            ..options
         };
+        let result = super::synchronize_with(dir1.path(), dir2.path(), &options);
+
+        match result {
+            Err(super::SyncError::VerificationFailed { path }) => assert_eq!(path, corrupted),
+            result => panic!("unexpected result: {:?}", result),
+        }
     }
 
-    let dir1_time = FileTime::from_last_modification_time(&unwrap_result!(dir1_path.metadata()));
+    #[test]
+    fn verify_skipped_files() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        std::fs::write(dir1.path().join("large"), [0; 100]).unwrap();
+        write_with_mtime(&dir1.path().join("readonly"), "newer", 2_000);
+        let readonly = dir2.path().join("readonly");
+        write_with_mtime(&readonly, "old", 1_000);
+        let mut permissions = std::fs::metadata(&readonly).unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&readonly, permissions).unwrap();
 
-    let dir2_time = FileTime::from_last_modification_time(&unwrap_result!(dir2_path.metadata()));
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            verify: true,
+            max_file_size: Some(10),
+            readonly_dest: super::ReadonlyPolicy::Skip,
+            ..Default::default()
+        };
+        let report = super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
 
-    if dir1_time > dir2_time {
-        unwrap_result!(fs::remove_dir_all(dir2_path));
-        copy_dir(dir1_path, dir2_path, dir1_time, on_err)?;
-    } else if dir1_time != dir2_time {
-        unwrap_result!(fs::remove_dir_all(dir1_path));
-        copy_dir(dir2_path, dir1_path, dir2_time, on_err)?;
+        assert!(!dir2.path().join("large").exists());
+        assert_eq!(report.readonly_skipped, vec![readonly]);
     }
 
-    Ok(())
-}
+    #[test]
+    fn error_context() {
+        use super::{ErrorHandlingType, Operation, PlannedAction};
 
-fn dir_latest_modification_time<'t, FErr>(
-    path: &'t Path,
-    on_err: &FErr,
-) -> Result<FileTime, ErrorHandlingType>
-where
-    FErr: Fn(&dyn std::error::Error) -> ErrorHandlingType,
-{
-    let mut skip = false;
-    let mut fail = false;
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        std::fs::write(dir1.path().join("a"), "a").unwrap();
+        std::fs::write(dir1.path().join("b"), "b").unwrap();
+        std::fs::write(dir2.path().join("extra"), "extra").unwrap();
 
-    macro_rules! unwrap_result {
-        ($e:expr) => {
-            match $e {
-                Err(err) => {
-                    use ErrorHandlingType::*;
+        let errors = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let options = super::SyncOptions {
+            error_handler: Box::new({
+                let errors = errors.clone();
+                move |context| {
+                    let relative_path = context.relative_path.map(std::path::Path::to_path_buf);
+                    errors.borrow_mut().push((context.operation, relative_path));
 
-                    match on_err(&err) {
-                        Fail => fail = true,
-                        Skip => skip = true,
-                        Ignore => (),
+                    match context.operation {
+                        Operation::Copy => ErrorHandlingType::Ignore,
+                        _ => ErrorHandlingType::Fail,
+                    }
+                }
+            }),
+            // both entries disappear right before being handled
+            on_action: Some(Box::new({
+                let dir1 = dir1.path().to_owned();
+                let dir2 = dir2.path().to_owned();
+                move |action: &PlannedAction| match action {
+                    PlannedAction::Copy { to, .. } if *to == dir2.join("a") => {
+                        std::fs::remove_file(dir1.join("a")).unwrap()
                     }
+                    PlannedAction::Delete(path) => std::fs::remove_file(path).unwrap(),
+                    _ => (),
+                }
+            })),
+            direction: super::Direction::Mirror,
+            ..Default::default()
+        };
+        let result = super::synchronize_with(dir1.path(), dir2.path(), &options);
 
-                    return None;
+        assert_eq!(
+            result.unwrap_err().path(),
+            Some(dir2.path().join("extra").as_path())
+        );
+        assert_eq!(std::fs::read(dir2.path().join("b")).unwrap(), b"b");
+        assert_eq!(
+            errors.take(),
+            vec![
+                (Operation::Copy, Some("a".into())),
+                (Operation::Delete, Some("extra".into())),
+            ]
+        );
+
+        // the staged directory replacing a file disappears right before taking its place
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let staged = super::staged_path(&dir2.path().join("entry"));
+        write_with_mtime(&dir2.path().join("entry"), "old file", 1_000);
+        std::fs::create_dir(dir1.path().join("entry")).unwrap();
+        std::fs::write(dir1.path().join("entry").join("a"), "a").unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new({
+                let errors = errors.clone();
+                move |context| {
+                    let relative_path = context.relative_path.map(std::path::Path::to_path_buf);
+                    errors.borrow_mut().push((context.operation, relative_path));
+
+                    ErrorHandlingType::Fail
                 }
-                Ok(x) => x,
+            }),
+            on_action: Some(Box::new(|action: &PlannedAction| {
+                if let PlannedAction::Rename { from, .. } = action {
+                    std::fs::remove_dir_all(from).unwrap();
+                }
+            })),
+            ..Default::default()
+        };
+        let result = super::synchronize_with(dir1.path(), dir2.path(), &options);
+
+        assert_eq!(result.unwrap_err().path(), Some(staged.as_path()));
+        assert_eq!(
+            errors.take(),
+            vec![(
+                Operation::Rename,
+                Some(staged.strip_prefix(dir2.path()).unwrap().into())
+            )]
+        );
+    }
+
+    #[test]
+    fn create_missing_target() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let target = dir2.path().join("backups").join("first");
+
+        std::fs::create_dir(dir1.path().join("sub")).unwrap();
+        write_with_mtime(&dir1.path().join("sub").join("nested"), "nested", 1_000);
+        write_with_mtime(&dir1.path().join("file"), "file", 1_000);
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            create_missing_target: true,
+            ..Default::default()
+        };
+        let relative_snapshot = |root: &std::path::Path| {
+            let mut snapshot = snapshot(root);
+            for (path, _, _) in &mut snapshot {
+                *path = path.strip_prefix(root).unwrap().to_owned();
             }
+            snapshot
         };
+        let report = super::synchronize_with(dir1.path(), &target, &options).unwrap();
+
+        assert_eq!(relative_snapshot(dir1.path()), relative_snapshot(&target));
+        assert_eq!(report.stats.files_copied, 2);
+
+        // the missing path may be the first one too
+        let other_target = dir2.path().join("second");
+        super::synchronize_with(&other_target, dir1.path(), &options).unwrap();
+        assert_eq!(
+            relative_snapshot(dir1.path()),
+            relative_snapshot(&other_target)
+        );
     }
 
-    let max = WalkDir::new(path)
-        .into_iter()
-        .filter_map(|e: walkdir::Result<DirEntry>| {
-            let e = unwrap_result!(e);
+    #[test]
+    fn on_decision() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir1.path().join("dir")).unwrap();
+        write_with_mtime(&dir1.path().join("dir").join("new"), "new", 1_000);
+        write_with_mtime(&dir1.path().join("same"), "same", 1_000);
+        write_with_mtime(&dir2.path().join("same"), "same", 1_000);
+        write_with_mtime(&dir1.path().join("updated"), "new", 2_000);
+        write_with_mtime(&dir2.path().join("updated"), "old", 1_000);
+        std::fs::create_dir(dir1.path().join("replaced")).unwrap();
+        write_with_mtime(&dir1.path().join("replaced").join("inner"), "dir", 3_000);
+        write_with_mtime(&dir2.path().join("replaced"), "file", 1_000);
 
-            let path: &Path = e.path();
+        let decisions = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            on_decision: Some(Box::new({
+                let decisions = decisions.clone();
+                move |decision: &super::Decision| {
+                    let name = format!("{:?}", decision);
+                    let kind = name.split('(').next().unwrap().to_owned();
+                    decisions
+                        .borrow_mut()
+                        .push((decision.path().to_owned(), kind));
+                }
+            })),
+            ..Default::default()
+        };
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
 
-            Some(FileTime::from_last_modification_time(&unwrap_result!(
-                path.metadata()
-            )))
-        })
-        .max()
-        .unwrap_or_else(FileTime::zero);
+        // the order of the entries depends on the file system
+        let mut decisions = decisions.take();
+        decisions.sort();
+        let expected = [
+            ("dir", "CreatedDir"),
+            ("dir/new", "CopiedMissing"),
+            ("replaced", "ReplacedFileWithDir"),
+            // walked once replaced
+            ("replaced/inner", "AlreadyInSync"),
+            ("same", "AlreadyInSync"),
+            ("updated", "CopiedNewer"),
+        ];
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|(path, kind)| (std::path::PathBuf::from(path), kind.to_string()))
+            .collect();
+        assert_eq!(decisions, expected);
+    }
 
-    if fail {
-        Err(ErrorHandlingType::Fail)
-    } else if skip {
-        Err(ErrorHandlingType::Skip)
-    } else {
-        Ok(max)
+    #[test]
+    fn on_progress() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir(dir1.path().join("sub")).unwrap();
+        std::fs::write(dir1.path().join("sub").join("a"), "a").unwrap();
+        std::fs::write(dir1.path().join("b"), "b").unwrap();
+        std::fs::write(dir2.path().join("c"), "c").unwrap();
+
+        let events = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            on_progress: Some(Box::new({
+                let events = events.clone();
+                move |progress: &super::Progress| {
+                    // the progress of copies is tested by `on_progress_bytes`
+                    if progress.bytes.is_none() {
+                        events.borrow_mut().push((
+                            progress.path.to_owned(),
+                            progress.processed,
+                            progress.discovered,
+                        ))
+                    }
+                }
+            })),
+            ..Default::default()
+        };
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        let events = events.borrow();
+        assert_eq!(events.len(), 4);
+        for (i, (path, processed, discovered)) in events.iter().enumerate() {
+            assert!(path.is_relative());
+            assert_eq!(*processed, i as u64 + 1);
+            assert!(discovered >= processed);
+        }
     }
-}
 
-#[cfg(test)]
-mod tests {
+    #[test]
+    fn precount() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir(dir1.path().join("sub")).unwrap();
+        std::fs::write(dir1.path().join("sub").join("a"), "a").unwrap();
+        std::fs::write(dir1.path().join("b"), "b").unwrap();
+        std::fs::write(dir1.path().join("excluded.tmp"), "excluded").unwrap();
+        std::fs::write(dir2.path().join("b"), "b").unwrap();
+        std::fs::write(dir2.path().join("c"), "c").unwrap();
+
+        let events = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            on_progress: Some(Box::new({
+                let events = events.clone();
+                move |progress: &super::Progress| {
+                    events
+                        .borrow_mut()
+                        .push((progress.processed, progress.total))
+                }
+            })),
+            precount: true,
+            exclude: vec![super::Pattern::new("*.tmp").unwrap()],
+            ..Default::default()
+        };
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        // "sub", "sub/a", "b" and "c", counted as they are before synchronizing
+        let events = events.borrow();
+        assert_eq!(events.last(), Some(&(4, Some(4))));
+        for (processed, total) in events.iter() {
+            assert_eq!(*total, Some(4));
+            assert!(*processed <= 4);
+        }
+    }
+
+    #[test]
+    fn flatten() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+
+        std::fs::create_dir_all(source.path().join("a")).unwrap();
+        std::fs::create_dir_all(source.path().join("b").join("c")).unwrap();
+        std::fs::write(source.path().join("a").join("photo.jpg"), "a").unwrap();
+        std::fs::write(source.path().join("b").join("c").join("photo.jpg"), "c").unwrap();
+        std::fs::write(source.path().join("b").join("notes"), "notes").unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            direction: super::Direction::Push,
+            flatten: true,
+            ..Default::default()
+        };
+        let report = super::synchronize_with(source.path(), target.path(), &options).unwrap();
+
+        let mut names: Vec<_> = std::fs::read_dir(target.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+
+        assert_eq!(names, vec!["notes", "photo-1.jpg", "photo.jpg"]);
+        assert_eq!(
+            std::fs::read(target.path().join("photo.jpg")).unwrap(),
+            b"a"
+        );
+        assert_eq!(
+            std::fs::read(target.path().join("photo-1.jpg")).unwrap(),
+            b"c"
+        );
+        assert_eq!(
+            report.renamed,
+            vec![(
+                source.path().join("b").join("c").join("photo.jpg"),
+                target.path().join("photo-1.jpg")
+            )]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn flatten_skips_failed_entry() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+
+        std::fs::write(source.path().join("a"), "a").unwrap();
+        // walked between the 2 files
+        std::os::unix::fs::symlink(source.path(), source.path().join("b")).unwrap();
+        std::fs::write(source.path().join("c"), "c").unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|_| super::ErrorHandlingType::Skip),
+            direction: super::Direction::Push,
+            flatten: true,
+            follow_symlinks: true,
+            ..Default::default()
+        };
+        let result = super::synchronize_with(source.path(), target.path(), &options);
+
+        assert!(result.is_ok());
+        assert!(target.path().join("a").is_file());
+        assert!(target.path().join("c").is_file());
+    }
 
     #[test]
-    fn trim_base_path_unix() {
-        let base = "/some/path";
-        let entry = "/some/path/to/entry";
-        let trimmed = super::trim_base_path(base, entry);
+    fn flatten_requires_push() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|_| super::ErrorHandlingType::Ignore),
+            flatten: true,
+            ..Default::default()
+        };
+        let result = super::synchronize_with(source.path(), target.path(), &options);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn check_unchanged_type() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("entry");
+
+        std::fs::write(&path, "file").unwrap();
+        let scanned = std::fs::metadata(&path).unwrap();
+        assert!(super::check_unchanged_type(&path, &scanned).is_ok());
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::create_dir(&path).unwrap();
+        assert!(matches!(
+            super::check_unchanged_type(&path, &scanned),
+            Err(super::SyncError::TypeChangedDuringSync(changed)) if changed == path
+        ));
+
+        std::fs::remove_dir(&path).unwrap();
+        assert!(super::check_unchanged_type(&path, &scanned).is_err());
+    }
+
+    #[test]
+    fn diff() {
+        use super::DiffStatus::*;
+
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        let (path1, path2) = (dir1.path(), dir2.path());
+
+        write_with_mtime(&path1.join("only1"), "only1", 1_000);
+        std::fs::create_dir_all(path2.join("only2")).unwrap();
+        write_with_mtime(&path2.join("only2").join("a"), "a", 1_000);
+        std::fs::create_dir(path1.join("both")).unwrap();
+        std::fs::create_dir(path2.join("both")).unwrap();
+        write_with_mtime(&path1.join("both").join("newer1"), "new", 2_000);
+        write_with_mtime(&path2.join("both").join("newer1"), "old", 1_000);
+        write_with_mtime(&path1.join("newer2"), "old", 1_000);
+        write_with_mtime(&path2.join("newer2"), "new", 2_000);
+        // the content is compared, the time doesn't matter
+        write_with_mtime(&path1.join("same"), "same", 1_000);
+        write_with_mtime(&path2.join("same"), "same", 2_000);
+        write_with_mtime(&path1.join("different"), "one", 1_000);
+        write_with_mtime(&path2.join("different"), "two", 1_000);
+        write_with_mtime(&path1.join("mismatch"), "file", 1_000);
+        std::fs::create_dir(path2.join("mismatch")).unwrap();
+        write_with_mtime(&path2.join("mismatch").join("a"), "a", 1_000);
+
+        let before = (snapshot(path1), snapshot(path2));
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            compare: super::CompareMode::Content,
+            ..Default::default()
+        };
+        let differences = super::diff(path1, path2, &options).unwrap();
 
-        assert_eq!(trimmed, Some(std::path::PathBuf::from("to/entry")))
+        let expected = [
+            ("both/newer1", NewerInFirst),
+            ("different", Different),
+            ("mismatch", TypeMismatch),
+            ("newer2", NewerInSecond),
+            ("only1", OnlyInFirst),
+            ("only2", OnlyInSecond),
+            ("same", Identical),
+        ];
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|&(path, status)| super::DiffEntry {
+                path: std::path::PathBuf::from(path),
+                status,
+            })
+            .collect();
+        assert_eq!(differences, expected);
+        assert_eq!((snapshot(path1), snapshot(path2)), before);
     }
 
     #[test]
-    fn trim_base_path_windows() {
-        let base = "C:\\some\\path";
-        let entry = "C:\\some\\path\\to\\entry";
-        let trimmed = super::trim_base_path(base, entry);
+    fn same_mtime_different_sizes() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        write_with_mtime(&dir1.path().join("a"), "complete", 1_000);
+        write_with_mtime(&dir2.path().join("a"), "compl", 1_000);
 
-        assert_eq!(trimmed, Some(std::path::PathBuf::from("to\\entry")))
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            ..Default::default()
+        };
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
+
+        // the truncated copy is replaced by the largest file
+        assert_eq!(std::fs::read(dir2.path().join("a")).unwrap(), b"complete");
+        assert_eq!(std::fs::read(dir1.path().join("a")).unwrap(), b"complete");
+    }
+
+    /// Records of the `log` crate along with the thread which emitted them, tests running in
+    /// parallel.
+    static LOGS: std::sync::Mutex<Vec<(std::thread::ThreadId, log::Level, String)>> =
+        std::sync::Mutex::new(Vec::new());
+
+    struct CapturingLogger;
+
+    impl log::Log for CapturingLogger {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            LOGS.lock().unwrap().push((
+                std::thread::current().id(),
+                record.level(),
+                record.args().to_string(),
+            ));
+        }
+
+        fn flush(&self) {}
     }
 
     #[test]
-    fn path_has_extension_true() {
-        let path = &std::path::Path::new("hello/rust.rs");
-        let extension = "rs";
-        let has_extension = super::path_has_extension(path, extension);
+    fn logs_recoverable_errors() {
+        static LOGGER: CapturingLogger = CapturingLogger;
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(log::LevelFilter::Warn);
+
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        std::fs::write(dir1.path().join("a"), "a").unwrap();
+
+        let options = super::SyncOptions {
+            error_handler: Box::new(|_| super::ErrorHandlingType::Skip),
+            // the file disappears right before being copied
+            on_action: Some(Box::new({
+                let dir1 = dir1.path().to_owned();
+                move |action: &super::PlannedAction| {
+                    if let super::PlannedAction::Copy { .. } = action {
+                        std::fs::remove_file(dir1.join("a")).unwrap()
+                    }
+                }
+            })),
+            ..Default::default()
+        };
+        super::synchronize_with(dir1.path(), dir2.path(), &options).unwrap();
 
-        assert!(has_extension)
+        let thread = std::thread::current().id();
+        let copied = [dir1.path().join("a"), dir2.path().join("a")];
+        let logs = LOGS.lock().unwrap();
+        assert!(logs.iter().any(|(emitter, level, message)| {
+            *emitter == thread
+                && *level == log::Level::Warn
+                && copied
+                    .iter()
+                    .any(|path| message.contains(&*path.to_string_lossy()))
+        }));
     }
 
+    #[cfg(feature = "tar")]
     #[test]
-    fn path_has_extension_false() {
-        let path = &std::path::Path::new("hello/rust.rs");
-        let not_extension = "md";
-        let has_extension = super::path_has_extension(path, not_extension);
+    fn synchronize_into_tar() {
+        use super::archive::TarArchive;
+
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        let archive = dir.path().join("archive.tar");
+        std::fs::create_dir_all(source.join("sub")).unwrap();
+        std::fs::write(source.join("a"), "a").unwrap();
+        std::fs::write(source.join("sub").join("b"), "b").unwrap();
 
-        assert!(!has_extension)
+        let options = super::SyncOptions {
+            error_handler: Box::new(|err| panic!("{}", err)),
+            direction: super::Direction::Mirror,
+            ..Default::default()
+        };
+        let synchronize = || {
+            let mut target = TarArchive::open(&archive).unwrap();
+            super::synchronize_into(&source, &mut target, &options)
+                .unwrap()
+                .stats
+        };
+
+        assert_eq!(synchronize().files_copied, 2);
+
+        let target = TarArchive::open(&archive).unwrap();
+        let paths: Vec<_> = target
+            .list()
+            .into_iter()
+            .map(|entry| (entry.path, entry.is_dir))
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                ("a".into(), false),
+                ("sub".into(), true),
+                (std::path::Path::new("sub").join("b"), false),
+            ]
+        );
+        assert_eq!(
+            target.read(&std::path::Path::new("sub").join("b")),
+            Some(&b"b"[..])
+        );
+
+        // times are rounded down in the archive, unchanged files stay as they are
+        std::fs::remove_file(source.join("a")).unwrap();
+        let stats = synchronize();
+        assert_eq!((stats.files_copied, stats.files_deleted), (0, 1));
     }
 
-    // Not available in other OSes yet.
-    #[cfg(target_os = "macos")]
+    #[cfg(feature = "tar")]
     #[test]
-    fn is_part_of_mac_app_true() {
-        let path_inside_app = std::path::Path::new("/Applications/App Store.app/randomStuff");
-        let is_part_of_mac_app = super::is_part_of_mac_app(path_inside_app);
+    fn tar_archive_current_dir_prefix() {
+        use super::archive::TarArchive;
+
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("archive.tar");
 
-        assert!(is_part_of_mac_app)
+        // like `tar -C dir .`
+        let mut builder = tar::Builder::new(std::fs::File::create(&archive).unwrap());
+        for (path, data) in [("./", None), ("./a", Some("a"))].iter().copied() {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(match data {
+                Some(_) => tar::EntryType::Regular,
+                None => tar::EntryType::Directory,
+            });
+            header.set_size(data.map_or(0, |data| data.len() as u64));
+            header.set_mtime(1_000);
+            builder
+                .append_data(&mut header, path, data.unwrap_or("").as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap();
+
+        let target = TarArchive::open(&archive).unwrap();
+        let paths: Vec<_> = target.list().into_iter().map(|entry| entry.path).collect();
+
+        assert_eq!(paths, vec![std::path::PathBuf::from("a")]);
+        assert_eq!(target.read(std::path::Path::new("a")), Some(&b"a"[..]));
     }
 
     #[test]
-    fn is_part_of_mac_app_false() {
-        let path_outside_app = std::path::Path::new("hello/myAppOrNotReally/randomThingy");
-        let is_part_of_mac_app = super::is_part_of_mac_app(path_outside_app);
+    fn invalid_options_modify_nothing() {
+        let dir1 = tempfile::tempdir().unwrap();
+        let dir2 = tempfile::tempdir().unwrap();
+        std::fs::write(dir1.path().join("a"), "a").unwrap();
+        let target = dir2.path().join("target");
+
+        let flattened = super::SyncOptions {
+            flatten: true,
+            create_missing_target: true,
+            ..Default::default()
+        };
+        let no_jobs = super::SyncOptions {
+            jobs: Some(0),
+            rsync_trailing_slash: true,
+            ..Default::default()
+        };
 
-        assert!(!is_part_of_mac_app)
+        for options in [flattened, no_jobs] {
+            let result = super::synchronize_with(dir1.path(), &target, &options);
+
+            assert!(matches!(result, Err(super::SyncError::InvalidOptions(_))));
+            assert_eq!(std::fs::read_dir(dir2.path()).unwrap().count(), 0);
+        }
     }
 }
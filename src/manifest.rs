@@ -0,0 +1,157 @@
+//! Entries seen by the last synchronization of a pair of directories, telling entries deleted
+//! since apart from new ones, see `SyncOptions::propagate_deletions`.
+//!
+//! The manifest is stored as a `.keepkeeping-manifest` file at the root of both directories,
+//! one entry per line: `<seconds> <nanoseconds> <relative path>` for files and
+//! `- <relative path>` for directories. Relative paths are the ones of the first directory.
+
+use crate::SyncError;
+use filetime::FileTime;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Name of the manifest file stored at the root of both synchronized directories.
+pub(crate) const MANIFEST_NAME: &str = ".keepkeeping-manifest";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Manifest {
+    /// Modification time of each file, `None` for directories.
+    entries: HashMap<PathBuf, Option<FileTime>>,
+}
+
+impl Manifest {
+    /// Read the manifest of the last synchronization of `dir1` and `dir2`.
+    ///
+    /// Returns `None` when they have never been synchronized together, i.e. when one of them
+    /// has no manifest or when they don't match (e.g. one of them has been synchronized with
+    /// another directory since).
+    pub(crate) fn read(dir1: &Path, dir2: &Path) -> Result<Option<Self>, SyncError> {
+        let read = |dir: &Path| {
+            let path = dir.join(MANIFEST_NAME);
+
+            match fs::read_to_string(&path) {
+                Ok(content) => Ok(Some(content)),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(SyncError::io(&path, err)),
+            }
+        };
+
+        match (read(dir1)?, read(dir2)?) {
+            (Some(content1), Some(content2)) if content1 == content2 => {
+                Ok(Some(Self::parse(&content1)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn parse(content: &str) -> Self {
+        let entries = content
+            .lines()
+            .filter_map(|line| {
+                let (time, path) = match line.strip_prefix("- ") {
+                    Some(path) => (None, path),
+                    None => {
+                        let mut parts = line.splitn(3, ' ');
+                        let seconds = parts.next()?.parse().ok()?;
+                        let nanoseconds = parts.next()?.parse().ok()?;
+
+                        (
+                            Some(FileTime::from_unix_time(seconds, nanoseconds)),
+                            parts.next()?,
+                        )
+                    }
+                };
+
+                Some((PathBuf::from(path), time))
+            })
+            .collect();
+
+        Self { entries }
+    }
+
+    /// Record an entry as it is once synchronized.
+    ///
+    /// Paths which can't be stored on a line (non-UTF-8 or containing line breaks) are left out,
+    /// they are never deleted.
+    pub(crate) fn insert(&mut self, relative_path: &Path, metadata: &fs::Metadata) {
+        let storable = relative_path
+            .to_str()
+            .is_some_and(|path| !path.contains(['\n', '\r']));
+
+        if storable {
+            let time =
+                (!metadata.is_dir()).then(|| FileTime::from_last_modification_time(metadata));
+
+            self.entries.insert(relative_path.to_owned(), time);
+        }
+    }
+
+    /// Whether the entry at `relative_path` has been seen by the last synchronization and not
+    /// modified since.
+    pub(crate) fn is_unchanged(&self, relative_path: &Path, metadata: &fs::Metadata) -> bool {
+        match self.entries.get(relative_path) {
+            Some(None) => metadata.is_dir(),
+            Some(Some(time)) => {
+                !metadata.is_dir() && FileTime::from_last_modification_time(metadata) <= *time
+            }
+            None => false,
+        }
+    }
+
+    /// Write the manifest at the root of `dir`.
+    pub(crate) fn write(&self, dir: &Path) -> io::Result<()> {
+        let mut lines: Vec<_> = self
+            .entries
+            .iter()
+            .filter_map(|(path, time)| {
+                let path = path.to_str()?;
+
+                Some(match time {
+                    Some(time) => {
+                        format!("{} {} {}\n", time.unix_seconds(), time.nanoseconds(), path)
+                    }
+                    None => format!("- {}\n", path),
+                })
+            })
+            .collect();
+
+        // the same entries always give the same file, both manifests can then be compared
+        lines.sort();
+
+        // an unchanged manifest is left as is, modifying nothing when nothing has changed
+        let path = dir.join(MANIFEST_NAME);
+        let content = lines.concat();
+        if fs::read_to_string(&path).is_ok_and(|previous| previous == content) {
+            return Ok(());
+        }
+
+        fs::write(path, content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Manifest;
+    use std::path::Path;
+
+    #[test]
+    fn manifest_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file with spaces");
+        std::fs::write(&file, "content").unwrap();
+
+        let mut manifest = Manifest::default();
+        manifest.insert(Path::new("file with spaces"), &file.metadata().unwrap());
+        manifest.insert(Path::new("dir"), &dir.path().metadata().unwrap());
+        manifest.write(dir.path()).unwrap();
+
+        let read = Manifest::read(dir.path(), dir.path()).unwrap().unwrap();
+        assert_eq!(read, manifest);
+        assert!(read.is_unchanged(Path::new("file with spaces"), &file.metadata().unwrap()));
+        assert!(read.is_unchanged(Path::new("dir"), &dir.path().metadata().unwrap()));
+        assert!(!read.is_unchanged(Path::new("dir"), &file.metadata().unwrap()));
+        assert!(!read.is_unchanged(Path::new("other"), &file.metadata().unwrap()));
+    }
+}
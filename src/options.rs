@@ -0,0 +1,486 @@
+use crate::compare::{CompareMode, TimeField};
+use crate::error::ErrorContext;
+use crate::hash::{self, HashAlgorithm};
+use crate::report::{Decision, PlannedAction, Progress, SyncPlan};
+use crate::sanitize::SanitizePolicy;
+use crate::ErrorHandlingType;
+use glob::Pattern;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Options configuring a synchronization, see `synchronize_with`.
+pub struct SyncOptions {
+    /// Decide how each error is handled, stopping the synchronization by default.
+    pub error_handler: Box<ErrorHandler>,
+    /// Called before processing each entry, e.g. to display a counter, and after copying each
+    /// chunk of a file (`None` to report nothing). Files copied in parallel (see `parallel`)
+    /// don't report their chunks, only sequential copies fill `Progress::bytes`.
+    pub on_progress: Option<Box<OnProgress>>,
+    /// Count the entries to process before synchronizing directories, giving the progress its
+    /// total (see `Progress::total`) at the cost of walking them twice. Only with
+    /// `on_progress`.
+    pub precount: bool,
+    /// Called with each modification of the file system before making it, e.g. to log it
+    /// (`None` to report nothing). Dry runs call it instead of making it.
+    pub on_action: Option<Box<OnAction>>,
+    /// Called with every modification the synchronization is about to make before making any of
+    /// them, e.g. to ask for a confirmation, returning `false` stops it with
+    /// `SyncError::Rejected` (`None` to synchronize right away). Ignored by dry runs.
+    ///
+    /// The synchronization is planned like a dry run without calling the other callbacks,
+    /// errors are handled as usual and those it goes on after are met again once approved.
+    /// `synchronize_many_with` calls it for each pair of paths it synchronizes.
+    pub on_plan: Option<Box<OnPlan>>,
+    /// Called with why each entry is synchronized the way it is, e.g. to understand an
+    /// unexpected synchronization (`None` to report nothing).
+    pub on_decision: Option<Box<OnDecision>>,
+    /// Stop the synchronization as soon as possible once set to `true` (e.g. from another
+    /// thread), it then returns `SyncError::Aborted` (`None` to never stop). Copies in progress
+    /// are interrupted, leaving their target untouched when they are atomic.
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Stop the synchronization as soon as possible once this instant is reached, like
+    /// `cancel`, it then returns `SyncError::TimedOut` (`None` for no time limit).
+    pub deadline: Option<Instant>,
+    /// Which way changes are propagated.
+    pub direction: Direction,
+    /// Never modify anything, only record what would have been done in
+    /// `SyncReport::planned`.
+    ///
+    /// Errors that would happen before modifying anything (e.g. a read-only side) are still
+    /// reported.
+    pub dry_run: bool,
+    /// Delete entries which have been deleted from the other path since the last
+    /// synchronization, instead of copying them back. Entries modified since then are still
+    /// copied back.
+    ///
+    /// Only applies to bidirectional synchronizations of 2 directories. Entries seen by the
+    /// last synchronization are stored in a `.keepkeeping-manifest` file at the root of both
+    /// directories, nothing is deleted by the first synchronization of a pair (see
+    /// `initialize`).
+    pub propagate_deletions: bool,
+    /// Synchronize as if for the first time with `propagate_deletions`: the manifest of the
+    /// last synchronization is ignored, entries missing from one side are copied back instead
+    /// of being deleted, and a new manifest is recorded for the next synchronizations (e.g.
+    /// after restoring one side from a backup).
+    ///
+    /// The first synchronization of a pair (no manifest, or manifests which don't match) always
+    /// does so.
+    pub initialize: bool,
+    /// Follow the rsync convention when the first path is a directory: without a trailing
+    /// separator (`src`) it's synchronized with a directory of the same name inside the second
+    /// path (`dst/src`, created if missing), with one (`src/`) its contents are synchronized
+    /// with the second path directly. Trailing separators are ignored when disabled.
+    pub rsync_trailing_slash: bool,
+    /// When one of the paths doesn't exist and the other one is a directory, create it (along
+    /// with its missing parents) as a copy of that directory instead of failing, e.g. for a
+    /// first backup.
+    pub create_missing_target: bool,
+    /// Merge the contents of the given paths even when they are macOS apps, instead of
+    /// replacing one by the other. Apps found inside the given paths are still replaced as a
+    /// whole.
+    pub merge_root_bundle: bool,
+    /// Map the relative paths of the first directory to different relative paths in the
+    /// second one (`None` to use the same relative paths).
+    pub rebase: Option<Rebase>,
+    /// Copy all files directly into the second directory instead of recreating the directory
+    /// structure of the first one, name collisions are resolved by adding a `-1`, `-2`...
+    /// suffix.
+    ///
+    /// This is an export mode, it requires `Direction::Push` since a flattened directory can't
+    /// be synchronized back.
+    pub flatten: bool,
+    /// When pushing or mirroring, give every directory of the second path the modification
+    /// time of the matching directory of the first path once synchronized so that re-runs see
+    /// no directory-level difference. Modification times of files and of newly created
+    /// directories are always preserved.
+    pub preserve_dir_timestamps: bool,
+    /// Exact paths never synchronized, along with their content if they are directories.
+    ///
+    /// Relative paths are excluded from both paths, absolute ones should be inside one of them
+    /// (a warning is reported otherwise).
+    pub exclude_paths: Vec<PathBuf>,
+    /// Entries never synchronized, along with their content if they are directories, matched
+    /// against their path relative to the synchronized path they are part of (e.g.
+    /// `**/node_modules` for every `node_modules` directory, `*` never matches separators).
+    ///
+    /// The ignore file at the root of a synchronized path adds patterns for that path, see
+    /// `IGNORE_FILE_NAME`.
+    pub exclude: Vec<Pattern>,
+    /// When not empty, only files matching at least one of these patterns are synchronized,
+    /// matched like `SyncOptions::exclude` which wins over them. Directories are still walked
+    /// to reach the matching files they contain.
+    pub include: Vec<Pattern>,
+    /// Skip hidden entries, along with their content if they are directories: entries whose
+    /// name starts with a `.` and, on Windows, entries with the hidden attribute.
+    pub skip_hidden: bool,
+    /// Synchronize the content of the directories symbolic links point to (and files like
+    /// any other file) instead of recreating the links. File system loops are reported as
+    /// errors.
+    pub follow_symlinks: bool,
+    /// Match the entries of both paths whatever the case of their names (e.g. `README.md` and
+    /// `Readme.md`), when synchronizing a case-sensitive file system with a case-insensitive
+    /// one. The names of existing entries are kept.
+    pub case_insensitive: bool,
+    /// Only synchronize entries at most this deep in the synchronized directories (`Some(1)`
+    /// for their direct children only), deeper entries are left untouched (`None` for no
+    /// limit).
+    pub max_depth: Option<usize>,
+    /// Never copy files larger than this many bytes, e.g. on metered connections (`None` for
+    /// no limit). Skipped files are left as they are on both sides.
+    pub max_file_size: Option<u64>,
+    /// Only synchronize regular files and the directories holding them, skipping symbolic
+    /// links, FIFOs, sockets, devices and any other special file.
+    pub regular_files_only: bool,
+    /// Never copy any data, only give files present on both paths the modification time and
+    /// permissions of the newest one, e.g. after an extraction reset every timestamp. Files
+    /// with the same modification time are given the permissions of the one in the first path.
+    ///
+    /// Files are expected to have the same content, which is only checked by comparing sizes:
+    /// files whose sizes differ are left as they are and recorded in
+    /// `SyncReport::size_mismatches`.
+    pub metadata_only: bool,
+    /// Skip files depending on their first bytes (`None` to synchronize every file).
+    pub content_filter: Option<ContentFilter>,
+    /// Reduce memory use on huge directories (e.g. maildirs holding millions of files).
+    ///
+    /// Entries are always streamed: each entry is looked up on the other side as it's walked
+    /// so the entries of both paths are never held in memory, and only the current directory
+    /// of each nesting level is being read. By default, directories nested deeper than 10
+    /// levels have the rest of their listing read in memory to close them, directories are
+    /// sorted (reading them whole) when flattening, and what waits for the walk to end
+    /// (deleting extras when mirroring, giving created directories their time) is remembered
+    /// until then. This option keeps every directory open instead, using one file descriptor
+    /// per nesting level, reads them in file system order, which makes flattened name
+    /// suffixes depend on it, finishes each directory as soon as the walk leaves it and
+    /// ignores `parallel`.
+    ///
+    /// Memory use still grows with what is recorded in the `SyncReport` (e.g. renamed or
+    /// skipped entries) and with the names flattening gives.
+    pub low_memory: bool,
+    /// Copy the new files of a directory once it has been walked, all at once across a thread
+    /// pool with the `parallel` feature (one by one without it). Directories are still created
+    /// in order, and errors are still handled on the calling thread. Ignored with
+    /// `low_memory`, the copies waiting to be run taking memory.
+    pub parallel: bool,
+    /// Number of files copied at once with `parallel` (`None` for one per CPU).
+    pub jobs: Option<usize>,
+    /// How files are compared to know whether they are already synchronized.
+    ///
+    /// Files with the same modification time but different contents (or sizes, when only
+    /// comparing times) are settled by `conflict_strategy`.
+    pub compare: CompareMode,
+    /// How files with the same modification time but different contents are settled, or
+    /// which of 2 different files is kept with `ConflictStrategy::KeepLargest`.
+    pub conflict_strategy: ConflictStrategy,
+    /// Once synchronized, walk both paths again and check that every entry has a counterpart
+    /// of the same size on the other side (and the same contents with `CompareMode::Content`),
+    /// catching copies which silently went wrong. Mismatches are handled as
+    /// `SyncError::VerificationFailed`. Entries only on the second side are expected when
+    /// pushing, and nothing is checked when flattening or rebasing.
+    pub verify: bool,
+    /// Timestamp deciding which file is the newest, modification times are always the ones
+    /// copied. Falls back to modification times with a warning when not available.
+    /// `TimeField::Ctime` and `TimeField::Birthtime` are rejected with
+    /// `SyncError::InvalidOptions` unless pushing or mirroring.
+    pub compare_time_field: TimeField,
+    /// Timestamps at most this far apart are considered equal, e.g. a couple of seconds for
+    /// FAT or network file systems which don't store them precisely.
+    pub time_tolerance: Duration,
+    /// Decide which directory wins when directories are replaced as a whole (macOS apps)
+    /// instead of being merged (`None` to keep the most recently modified one).
+    pub should_replace: Option<Box<ShouldReplace>>,
+    /// What to do when only one of 2 matching entries is a symbolic link.
+    pub type_mismatch: TypeMismatchPolicy,
+    /// What to do when the file to replace is read-only.
+    pub readonly_dest: ReadonlyPolicy,
+    /// Options only applying to the first path.
+    pub side1: SideOptions,
+    /// Options only applying to the second path.
+    pub side2: SideOptions,
+    /// Algorithm used whenever file contents are hashed (`None` if no algorithm is available).
+    pub hash_algorithm: Option<Arc<dyn HashAlgorithm>>,
+    /// Store the checksum of every copied file in the `user.keepkeeping.<algorithm>` extended
+    /// attribute, and detect bit rot by checking files already synchronized against it.
+    ///
+    /// Needs the `xattr` feature on Unix, does nothing on other platforms or file systems
+    /// without extended attributes.
+    pub checksum_xattrs: bool,
+    /// Copy the extended attributes of files along with their content (e.g. macOS Finder tags
+    /// or quarantine flags).
+    ///
+    /// Needs the `xattr` feature on Unix, does nothing on other platforms or file systems
+    /// without extended attributes.
+    pub preserve_xattrs: bool,
+    /// When copying a whole directory (a missing target, see `create_missing_target`, or a
+    /// macOS app), link the copies of files hard linked together instead of copying their data
+    /// again (Unix only).
+    pub preserve_hardlinks: bool,
+    /// Copy files next to their target first then rename them over it, so that an interrupted
+    /// synchronization never leaves a half-written file. Files are copied directly when that's
+    /// not possible (e.g. read-only targets or directories). Unfinished copies left by an
+    /// interrupted run are removed by the next one.
+    pub atomic: bool,
+    /// When to preserve the holes of sparse files (e.g. virtual machine images) instead of
+    /// writing them out as zeros.
+    pub sparse: SparseMode,
+    /// Size of the chunks files are copied by when their progress is reported (see
+    /// `SyncOptions::on_progress`), they may be cancelled (see `SyncOptions::cancel`) or their
+    /// throughput is limited (see `SyncOptions::rate_limit`), they are copied at once by the
+    /// OS otherwise. Parallel copies are never copied by chunks.
+    pub copy_buffer_size: usize,
+    /// Maximum number of bytes copied per second across the whole synchronization, e.g. to
+    /// avoid saturating a slow drive (`None` for no limit). Files are then never copied in
+    /// parallel.
+    pub rate_limit: Option<u64>,
+    /// Names the file system of the second path can't store (`None` to copy every name as is).
+    pub sanitize: Option<SanitizePolicy>,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        Self {
+            error_handler: Box::new(|_| ErrorHandlingType::Fail),
+            on_progress: None,
+            precount: false,
+            on_action: None,
+            on_plan: None,
+            on_decision: None,
+            cancel: None,
+            deadline: None,
+            direction: Direction::default(),
+            dry_run: false,
+            propagate_deletions: false,
+            initialize: false,
+            rsync_trailing_slash: false,
+            create_missing_target: false,
+            merge_root_bundle: false,
+            rebase: None,
+            flatten: false,
+            preserve_dir_timestamps: false,
+            exclude_paths: Vec::new(),
+            exclude: Vec::new(),
+            include: Vec::new(),
+            skip_hidden: false,
+            follow_symlinks: false,
+            case_insensitive: false,
+            max_depth: None,
+            max_file_size: None,
+            regular_files_only: false,
+            metadata_only: false,
+            content_filter: None,
+            low_memory: false,
+            parallel: false,
+            jobs: None,
+            compare: CompareMode::default(),
+            compare_time_field: TimeField::default(),
+            time_tolerance: Duration::ZERO,
+            should_replace: None,
+            conflict_strategy: ConflictStrategy::default(),
+            verify: false,
+            type_mismatch: TypeMismatchPolicy::default(),
+            readonly_dest: ReadonlyPolicy::default(),
+            side1: SideOptions::default(),
+            side2: SideOptions::default(),
+            hash_algorithm: hash::default_algorithm(),
+            checksum_xattrs: false,
+            preserve_xattrs: false,
+            preserve_hardlinks: false,
+            atomic: true,
+            sparse: SparseMode::default(),
+            copy_buffer_size: 64 * 1024,
+            rate_limit: None,
+            sanitize: None,
+        }
+    }
+}
+
+/// Which way changes are propagated between the 2 synchronized paths.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Direction {
+    /// Both paths end up with the newest version of every entry.
+    #[default]
+    Bidirectional,
+    /// Only the second path is modified, receiving the entries of the first path which are
+    /// missing or newer. Entries only present in the second path are left untouched.
+    Push,
+    /// Only the second path is modified, becoming a replica of the first path: entries only
+    /// present in the second path are deleted, files are replaced whenever their modification
+    /// times differ (even by an older version) and permissions are reconciled even when
+    /// contents already match. Ownership is not reconciled.
+    Mirror,
+}
+
+/// How files with the same modification time but different contents are settled (or any
+/// different files with `KeepLargest`), see `SyncOptions::conflict_strategy`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConflictStrategy {
+    /// Keep the largest file when the modification times are the same, leaving both files as
+    /// they are if their sizes are the same too (with a warning).
+    #[default]
+    KeepLargestOnTie,
+    /// Keep both files on both sides: the second one is renamed to
+    /// `<name>.conflict-<host>.<extension>` then copied to the first side, and the first one
+    /// is copied over its original name. Only when synchronizing in both directions, the
+    /// largest file is kept otherwise.
+    KeepBoth,
+    /// Keep the largest file whatever the modification times (e.g. for append-only logs),
+    /// the newest one if their sizes are the same. Not when mirroring.
+    KeepLargest,
+}
+
+/// What to do when only one of 2 matching entries is a symbolic link (e.g. a link in the first
+/// path and a regular file in the second one), see `SyncOptions::type_mismatch`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TypeMismatchPolicy {
+    /// Leave both entries as they are, recording them in `SyncReport::type_conflicts`.
+    #[default]
+    Report,
+    /// Follow the link and synchronize its target like any other entry.
+    FollowLinks,
+}
+
+/// What to do when the file to replace is read-only, see `SyncOptions::readonly_dest`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReadonlyPolicy {
+    /// Leave the file as is, recording it in `SyncReport::readonly_skipped`.
+    Skip,
+    /// Make the file writable, replace it then make it read-only again, recording it in
+    /// `SyncReport::readonly_overwritten`.
+    Overwrite,
+    /// Try replacing the file anyway, which usually fails.
+    #[default]
+    Fail,
+}
+
+/// When to copy files as sparse files.
+///
+/// Sparse copies only copy the data extents of a file, using `SEEK_DATA`/`SEEK_HOLE` to find
+/// them. This is only supported on Linux, files are always copied normally elsewhere.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SparseMode {
+    /// Only files with fewer allocated blocks than their apparent size.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Replace the `from` prefix of relative paths in the first directory by `to` in the second
+/// one, e.g. `Rebase::new("photos", "")` synchronizes `dir1/photos/2024` with `dir2/2024`.
+///
+/// Entries outside of `from` in the first directory and outside of `to` in the second one are
+/// left untouched. Replacing a prefix maps each path to a single other path and the other
+/// way around, so entries can never collide, which is also what allows synchronizing in both
+/// directions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rebase {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+impl Rebase {
+    pub fn new<P1: Into<PathBuf>, P2: Into<PathBuf>>(from: P1, to: P2) -> Self {
+        Self {
+            from: from.into(),
+            to: to.into(),
+        }
+    }
+
+    /// Map a relative path of the first directory to the second one.
+    pub(crate) fn apply(&self, relative_path: &Path) -> Option<PathBuf> {
+        Self::replace_prefix(relative_path, &self.from, &self.to)
+    }
+
+    /// Map a relative path of the second directory to the first one.
+    pub(crate) fn invert(&self, relative_path: &Path) -> Option<PathBuf> {
+        Self::replace_prefix(relative_path, &self.to, &self.from)
+    }
+
+    fn replace_prefix(relative_path: &Path, prefix: &Path, replacement: &Path) -> Option<PathBuf> {
+        let rest = relative_path.strip_prefix(prefix).ok()?;
+
+        if rest.as_os_str().is_empty() {
+            Some(replacement.to_owned())
+        } else {
+            Some(replacement.join(rest))
+        }
+    }
+}
+
+/// Receive an error along with the operation which failed and the entry it's about, return how
+/// it should be handled.
+pub type ErrorHandler = dyn Fn(&ErrorContext) -> ErrorHandlingType;
+
+/// Receive the progress of the synchronization.
+pub type OnProgress = dyn Fn(&Progress);
+
+/// Receive a modification of the file system made by the synchronization.
+pub type OnAction = dyn Fn(&PlannedAction);
+
+/// Receive the plan of a synchronization, return `false` to reject it.
+pub type OnPlan = dyn Fn(&SyncPlan) -> bool;
+
+/// Receive why an entry is synchronized the way it is.
+pub type OnDecision = dyn Fn(&Decision);
+
+/// Options applying to only one of the synchronized paths.
+#[derive(Clone, Copy, Default)]
+pub struct SideOptions {
+    /// Never write, delete or touch anything on this side.
+    ///
+    /// Any attempt to do so is reported as a `SyncError::ReadOnlySide`.
+    pub read_only: bool,
+}
+
+/// Receive the first bytes of a file, return `true` to skip that file.
+pub type ContentPredicate = dyn Fn(&[u8]) -> bool;
+
+/// Skip files depending on their content signature (magic bytes) regardless of their extension.
+pub struct ContentFilter {
+    /// Maximum number of bytes read from the start of each file.
+    pub header_len: usize,
+    /// Predicate deciding which files to skip.
+    pub skip: Box<ContentPredicate>,
+}
+
+impl ContentFilter {
+    /// Create a filter giving the first `header_len` bytes of each file to `skip`.
+    pub fn new<F>(header_len: usize, skip: F) -> Self
+    where
+        F: Fn(&[u8]) -> bool + 'static,
+    {
+        Self {
+            header_len,
+            skip: Box::new(skip),
+        }
+    }
+}
+
+/// Receive the directory from the first path then the one from the second path, return which
+/// one should replace the other.
+pub type ShouldReplace = dyn Fn(&DirInfo, &DirInfo) -> ReplaceDecision;
+
+/// Directory about to replace or be replaced by another one, see `SyncOptions::should_replace`.
+#[derive(Clone, Debug)]
+pub struct DirInfo {
+    pub path: PathBuf,
+    /// Latest modification time of the directory and all of its content.
+    pub latest_modification: SystemTime,
+    /// Total size of the files it contains, in bytes.
+    pub size: u64,
+}
+
+/// Which directory should be kept, see `SyncOptions::should_replace`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplaceDecision {
+    /// Keep the most recently modified directory, like when there's no callback.
+    Newest,
+    /// Replace the second directory by the first one.
+    First,
+    /// Replace the first directory by the second one.
+    Second,
+    /// Leave both directories as they are.
+    Neither,
+}
@@ -0,0 +1,256 @@
+//! Path helpers, matching paths exactly the way synchronizations do.
+//!
+//! Tools working next to this crate (e.g. listing what a synchronization would touch) can use
+//! them instead of reimplementing subtly different rules.
+
+#[cfg(not(unix))]
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+/// Remove a base path from another path, making it relative to the base path.
+///
+/// Paths are compared byte by byte, names don't need to be valid Unicode on Unix. Both `/` and
+/// `\` are separators, and separators following the base path are removed. Returns `None` if
+/// `entry_path` isn't inside `base_path`.
+///
+/// ```
+/// use keep_keeping_lib::pathutil::trim_base_path;
+/// use std::path::PathBuf;
+///
+/// let trimmed = trim_base_path("/some/path", "/some/path/to/entry");
+/// assert_eq!(trimmed, Some(PathBuf::from("to/entry")));
+///
+/// // names are never cut in the middle
+/// assert_eq!(trim_base_path("/some/path", "/some/pathology/entry"), None);
+/// ```
+#[cfg(unix)]
+pub fn trim_base_path(
+    base_path: impl AsRef<OsStr>,
+    entry_path: impl AsRef<OsStr>,
+) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let trimmed = trim_base_bytes(
+        base_path.as_ref().as_bytes(),
+        entry_path.as_ref().as_bytes(),
+    )?;
+
+    Some(PathBuf::from(OsStr::from_bytes(trimmed)))
+}
+
+/// Remove a base path from another path, making it relative to the base path.
+///
+/// Both `/` and `\` are separators, and separators following the base path are removed.
+/// Either path may have the extended-length prefix (`\\?\`). Returns `None` if `entry_path`
+/// isn't inside `base_path` or if either of them isn't valid Unicode.
+///
+/// ```
+/// use keep_keeping_lib::pathutil::trim_base_path;
+/// use std::path::PathBuf;
+///
+/// let trimmed = trim_base_path("/some/path", "/some/path/to/entry");
+/// assert_eq!(trimmed, Some(PathBuf::from("to/entry")));
+///
+/// // names are never cut in the middle
+/// assert_eq!(trim_base_path("/some/path", "/some/pathology/entry"), None);
+/// ```
+#[cfg(not(unix))]
+pub fn trim_base_path(
+    base_path: impl AsRef<OsStr>,
+    entry_path: impl AsRef<OsStr>,
+) -> Option<PathBuf> {
+    let entry_path = without_verbatim_prefix(entry_path.as_ref().to_str()?);
+    let trimmed = trim_base_bytes(
+        without_verbatim_prefix(base_path.as_ref().to_str()?).as_bytes(),
+        entry_path.as_bytes(),
+    )?;
+
+    // only whole characters are trimmed, starting after the base path and its separators
+    Some(PathBuf::from(
+        &entry_path[entry_path.len() - trimmed.len()..],
+    ))
+}
+
+/// Remove the extended-length prefix of `path`, if any.
+#[cfg(not(unix))]
+fn without_verbatim_prefix(path: &str) -> Cow<'_, str> {
+    if let Some(rest) = path.strip_prefix(r"\\?\UNC\") {
+        Cow::Owned(format!(r"\\{}", rest))
+    } else {
+        Cow::Borrowed(path.strip_prefix(r"\\?\").unwrap_or(path))
+    }
+}
+
+/// Remove `base` from the bytes of `entry`, see `trim_base_path`.
+fn trim_base_bytes<'a>(base: &[u8], entry: &'a [u8]) -> Option<&'a [u8]> {
+    let is_separator = |byte: &u8| *byte == b'/' || *byte == b'\\';
+
+    let rest = entry.strip_prefix(base)?;
+
+    // the separator following the base path is part of it when given with a trailing one
+    if base.is_empty() || rest.is_empty() {
+        Some(rest)
+    } else if base.last().is_some_and(is_separator) || rest.first().is_some_and(is_separator) {
+        let start = rest
+            .iter()
+            .position(|byte| !is_separator(byte))
+            .unwrap_or(rest.len());
+
+        Some(&rest[start..])
+    } else {
+        // `entry_path` only starts with the same characters (e.g. "/a/bc" in "/a/b")
+        None
+    }
+}
+
+/// Check whether the name of `path` has the given extension (without the dot), compared
+/// exactly.
+///
+/// ```
+/// use keep_keeping_lib::pathutil::path_has_extension;
+/// use std::path::Path;
+///
+/// assert!(path_has_extension(Path::new("hello/rust.rs"), "rs"));
+/// assert!(!path_has_extension(Path::new("hello/rust.rs"), "md"));
+/// assert!(!path_has_extension(Path::new("Editor.APP"), "app"));
+/// ```
+#[inline]
+pub fn path_has_extension(path: &Path, extension: &str) -> bool {
+    path.extension() == Some(OsStr::new(extension))
+}
+
+/// Check whether `path` is a macOS app, which synchronizations handle as a whole: a directory
+/// with the `app` extension.
+///
+/// ```
+/// use keep_keeping_lib::pathutil::is_mac_app;
+///
+/// let dir = tempfile::tempdir().unwrap();
+/// let app = dir.path().join("Editor.app");
+///
+/// // only existing directories are apps
+/// assert!(!is_mac_app(&app));
+/// std::fs::create_dir(&app).unwrap();
+/// assert!(is_mac_app(&app));
+/// ```
+#[inline]
+pub fn is_mac_app(path: &Path) -> bool {
+    path_has_extension(path, "app") && path.is_dir()
+}
+
+/// Check whether `path` is inside a macOS app, see `is_mac_app`.
+///
+/// ```
+/// use keep_keeping_lib::pathutil::is_part_of_mac_app;
+///
+/// let dir = tempfile::tempdir().unwrap();
+/// let contents = dir.path().join("Editor.app").join("Contents");
+/// std::fs::create_dir_all(&contents).unwrap();
+///
+/// assert!(is_part_of_mac_app(&contents.join("Info.plist")));
+/// // an app isn't part of itself
+/// assert!(!is_part_of_mac_app(&dir.path().join("Editor.app")));
+/// ```
+pub fn is_part_of_mac_app(path: &Path) -> bool {
+    path.ancestors().skip(1).any(is_mac_app)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn trim_base_path_unix() {
+        let base = "/some/path";
+        let entry = "/some/path/to/entry";
+        let trimmed = super::trim_base_path(base, entry);
+
+        assert_eq!(trimmed, Some(std::path::PathBuf::from("to/entry")))
+    }
+
+    #[test]
+    fn trim_base_path_windows() {
+        let base = "C:\\some\\path";
+        let entry = "C:\\some\\path\\to\\entry";
+        let trimmed = super::trim_base_path(base, entry);
+
+        assert_eq!(trimmed, Some(std::path::PathBuf::from("to\\entry")))
+    }
+
+    #[test]
+    fn trim_base_path_unix_trailing_separator() {
+        let base = "/some/path/";
+        let entry = "/some/path/to/entry";
+        let trimmed = super::trim_base_path(base, entry);
+
+        assert_eq!(trimmed, Some(std::path::PathBuf::from("to/entry")))
+    }
+
+    #[test]
+    fn trim_base_path_windows_trailing_separator() {
+        let base = "C:\\some\\path\\";
+        let entry = "C:\\some\\path\\to\\entry";
+        let trimmed = super::trim_base_path(base, entry);
+
+        assert_eq!(trimmed, Some(std::path::PathBuf::from("to\\entry")))
+    }
+
+    #[test]
+    fn trim_base_path_prefix_mismatch() {
+        assert_eq!(
+            super::trim_base_path("/some/path", "/some/pathology/entry"),
+            None
+        );
+        assert_eq!(super::trim_base_path("/some/ab", "/some/ac/entry"), None);
+        assert_eq!(
+            super::trim_base_path("/some/path", "/some/path//to/entry"),
+            Some(std::path::PathBuf::from("to/entry"))
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn trim_base_path_verbatim() {
+        let trimmed = super::trim_base_path("C:\\some\\path", "\\\\?\\C:\\some\\path\\to\\entry");
+        assert_eq!(trimmed, Some(std::path::PathBuf::from("to\\entry")));
+
+        let trimmed =
+            super::trim_base_path("\\\\?\\UNC\\server\\share", "\\\\server\\share\\entry");
+        assert_eq!(trimmed, Some(std::path::PathBuf::from("entry")));
+    }
+
+    #[test]
+    fn path_has_extension_true() {
+        let path = &std::path::Path::new("hello/rust.rs");
+        let extension = "rs";
+        let has_extension = super::path_has_extension(path, extension);
+
+        assert!(has_extension)
+    }
+
+    #[test]
+    fn path_has_extension_false() {
+        let path = &std::path::Path::new("hello/rust.rs");
+        let not_extension = "md";
+        let has_extension = super::path_has_extension(path, not_extension);
+
+        assert!(!has_extension)
+    }
+
+    // Not available in other OSes yet.
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn is_part_of_mac_app_true() {
+        let path_inside_app = std::path::Path::new("/Applications/App Store.app/randomStuff");
+        let is_part_of_mac_app = super::is_part_of_mac_app(path_inside_app);
+
+        assert!(is_part_of_mac_app)
+    }
+
+    #[test]
+    fn is_part_of_mac_app_false() {
+        let path_outside_app = std::path::Path::new("hello/myAppOrNotReally/randomThingy");
+        let is_part_of_mac_app = super::is_part_of_mac_app(path_outside_app);
+
+        assert!(!is_part_of_mac_app)
+    }
+}
@@ -0,0 +1,58 @@
+//! Limit of the throughput of copies, see `SyncOptions::rate_limit`.
+
+use std::cell::Cell;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Token bucket shared by every copy of a synchronization, holding at most a second worth of
+/// bytes and starting empty.
+pub(crate) struct RateLimiter {
+    /// Bytes per second.
+    rate: f64,
+    /// Bytes which can be copied without waiting, negative once exceeded.
+    tokens: Cell<f64>,
+    last_refill: Cell<Instant>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(bytes_per_second: u64) -> Self {
+        RateLimiter {
+            rate: bytes_per_second.max(1) as f64,
+            tokens: Cell::new(0.0),
+            last_refill: Cell::new(Instant::now()),
+        }
+    }
+
+    /// Record that `bytes` more bytes have been copied, waiting as long as they exceed the rate.
+    pub(crate) fn consume(&self, bytes: u64) {
+        let now = Instant::now();
+        let refilled = now.duration_since(self.last_refill.get()).as_secs_f64() * self.rate;
+        let tokens = (self.tokens.get() + refilled).min(self.rate) - bytes as f64;
+
+        self.tokens.set(tokens);
+        self.last_refill.set(now);
+
+        // waiting refills the bucket, the debt is paid by the next refill
+        if tokens < 0.0 {
+            thread::sleep(Duration::from_secs_f64(-tokens / self.rate));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RateLimiter;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn consume() {
+        let limiter = RateLimiter::new(10_000);
+        let start = Instant::now();
+
+        for _ in 0..4 {
+            limiter.consume(1_000);
+        }
+
+        assert!(start.elapsed() >= Duration::from_millis(400));
+    }
+}
@@ -0,0 +1,399 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Summary of what happened during a synchronization.
+#[derive(Clone, Debug, Default)]
+pub struct SyncReport {
+    /// Files whose content no longer matched their stored checksum.
+    ///
+    /// Each of them has been replaced by the version from the other side if that one was intact.
+    pub bit_rot: Vec<PathBuf>,
+    /// Files copied under another name to avoid collisions: `(source, copy)`.
+    ///
+    /// Only happens when flattening or sanitizing names, see `SyncOptions::flatten` and
+    /// `SyncOptions::sanitize`.
+    pub renamed: Vec<(PathBuf, PathBuf)>,
+    /// Entries left as they are because only one of them is a symbolic link: `(entry in the
+    /// first path, entry in the second path)`, see `TypeMismatchPolicy::Report`.
+    pub type_conflicts: Vec<(PathBuf, PathBuf)>,
+    /// Read-only files left outdated, see `ReadonlyPolicy::Skip`.
+    pub readonly_skipped: Vec<PathBuf>,
+    /// Read-only files replaced anyway, see `ReadonlyPolicy::Overwrite`.
+    pub readonly_overwritten: Vec<PathBuf>,
+    /// Files whose modification time or permissions have been updated without copying their
+    /// data, see `SyncOptions::metadata_only`.
+    pub metadata_updated: Vec<PathBuf>,
+    /// Files left as they are because their sizes differ: `(newest file, other file)`, see
+    /// `SyncOptions::metadata_only`.
+    pub size_mismatches: Vec<(PathBuf, PathBuf)>,
+    /// How much work has been done.
+    pub stats: SyncStats,
+    /// Every modification a dry run would have made, in order, see `SyncOptions::dry_run`.
+    pub planned: Vec<PlannedAction>,
+    /// How many entries each filter excluded.
+    pub filtered: FilterStats,
+    /// Non-fatal problems (e.g. an option not supported on this platform).
+    pub warnings: Vec<String>,
+}
+
+impl SyncReport {
+    /// Add the results of another synchronization, e.g. to summarize several pairs of paths.
+    pub fn merge(&mut self, other: SyncReport) {
+        self.bit_rot.extend(other.bit_rot);
+        self.renamed.extend(other.renamed);
+        self.type_conflicts.extend(other.type_conflicts);
+        self.readonly_skipped.extend(other.readonly_skipped);
+        self.readonly_overwritten.extend(other.readonly_overwritten);
+        self.metadata_updated.extend(other.metadata_updated);
+        self.size_mismatches.extend(other.size_mismatches);
+        self.stats.merge(&other.stats);
+        self.planned.extend(other.planned);
+        self.filtered.merge(&other.filtered);
+
+        for warning in other.warnings {
+            if !self.warnings.contains(&warning) {
+                self.warnings.push(warning);
+            }
+        }
+    }
+}
+
+/// Progress of a synchronization, see `SyncOptions::on_progress`.
+#[derive(Clone, Copy, Debug)]
+pub struct Progress<'a> {
+    /// Entry being processed, relative to the synchronized path it's part of.
+    pub path: &'a Path,
+    /// Number of entries processed so far, including this one.
+    pub processed: u64,
+    /// Number of entries found so far, never less than `processed`. Directories are walked as
+    /// they are synchronized, the total isn't known until the end.
+    pub discovered: u64,
+    /// Number of entries to process, counted before synchronizing with
+    /// `SyncOptions::precount` (`None` otherwise). Entries which can't be walked or appear while
+    /// synchronizing aren't counted, `processed` may then end up above it.
+    pub total: Option<u64>,
+    /// Bytes copied so far and size of the file at `path`, only when reporting the progress of
+    /// its copy (see `SyncOptions::copy_buffer_size`). Never set for files copied in parallel.
+    pub bytes: Option<(u64, u64)>,
+    /// Modifications made so far, not counting the copy in progress.
+    pub stats: SyncStats,
+}
+
+/// Counters of the modifications made by a synchronization, dry runs make none.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct SyncStats {
+    /// Files copied, including replaced ones.
+    pub files_copied: u64,
+    /// Bytes written by these copies.
+    pub bytes_copied: u64,
+    /// Directories created.
+    pub dirs_created: u64,
+    /// Files removed, including the content of removed directories.
+    pub files_deleted: u64,
+    /// Directories removed, including the ones inside them.
+    pub dirs_deleted: u64,
+}
+
+impl SyncStats {
+    /// Add the counters of `other` to these ones.
+    pub fn merge(&mut self, other: &SyncStats) {
+        self.files_copied += other.files_copied;
+        self.bytes_copied += other.bytes_copied;
+        self.dirs_created += other.dirs_created;
+        self.files_deleted += other.files_deleted;
+        self.dirs_deleted += other.dirs_deleted;
+    }
+}
+
+/// Modification of the file system, planned by a dry run or about to be made (see
+/// `SyncOptions::on_action`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PlannedAction {
+    /// Copy a file, replacing `to` if it exists.
+    Copy { from: PathBuf, to: PathBuf },
+    /// Remove a file or a directory along with its content.
+    Delete(PathBuf),
+    /// Create a directory, along with its missing parents.
+    CreateDir(PathBuf),
+    /// Create a symbolic link pointing to `target`.
+    CreateSymlink { link: PathBuf, target: PathBuf },
+    /// Create a hard link to the existing file `original`.
+    CreateHardLink { link: PathBuf, original: PathBuf },
+    /// Move an entry, replacing `to` if it exists.
+    Rename { from: PathBuf, to: PathBuf },
+    /// Give an entry a modification time.
+    SetTimes { path: PathBuf, mtime: SystemTime },
+    /// Give an entry the permissions of the one it's synchronized with.
+    SetPermissions(PathBuf),
+}
+
+/// Modifications a synchronization is about to make, see `SyncOptions::on_plan`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SyncPlan {
+    /// Every modification, in order.
+    pub actions: Vec<PlannedAction>,
+    /// Number of files to copy.
+    pub files_to_copy: u64,
+    /// Number of bytes to copy, as the files to copy are now.
+    pub bytes_to_copy: u64,
+    /// Number of directories to create.
+    pub dirs_to_create: u64,
+    /// Modifications losing data: deletions, and copies or moves replacing an existing entry.
+    pub destructive: Vec<PlannedAction>,
+}
+
+impl SyncPlan {
+    /// Summarize the modifications planned by a dry run.
+    pub(crate) fn new(actions: Vec<PlannedAction>) -> Self {
+        let mut plan = SyncPlan::default();
+
+        for action in &actions {
+            let destructive = match action {
+                PlannedAction::Copy { from, to } => {
+                    plan.files_to_copy += 1;
+                    plan.bytes_to_copy += fs::metadata(from).map_or(0, |metadata| metadata.len());
+                    fs::symlink_metadata(to).is_ok()
+                }
+                PlannedAction::CreateDir(_) => {
+                    plan.dirs_to_create += 1;
+                    false
+                }
+                PlannedAction::Delete(_) => true,
+                PlannedAction::Rename { to, .. } => fs::symlink_metadata(to).is_ok(),
+                PlannedAction::CreateSymlink { .. }
+                | PlannedAction::CreateHardLink { .. }
+                | PlannedAction::SetTimes { .. }
+                | PlannedAction::SetPermissions(_) => false,
+            };
+
+            if destructive {
+                plan.destructive.push(action.clone());
+            }
+        }
+
+        plan.actions = actions;
+        plan
+    }
+}
+
+/// Why an entry is synchronized the way it is, see `SyncOptions::on_decision`.
+///
+/// Paths are relative to the synchronized path the entry is part of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision<'a> {
+    /// The file is newer than (or preferred over, see `SyncOptions::conflict_strategy`) the
+    /// other one, which is replaced.
+    CopiedNewer(&'a Path),
+    /// Both files are already synchronized.
+    AlreadyInSync(&'a Path),
+    /// The file is missing from the other side, it's copied there.
+    CopiedMissing(&'a Path),
+    /// The directory is missing from the other side, it's created there.
+    CreatedDir(&'a Path),
+    /// The file is older than the directory of the other side, it's replaced by it.
+    ReplacedFileWithDir(&'a Path),
+    /// The directory is older than the file of the other side, it's replaced by it.
+    ReplacedDirWithFile(&'a Path),
+    /// Both versions of the conflicting file are kept, see `ConflictStrategy::KeepBoth`.
+    KeptBoth(&'a Path),
+    /// The entry has been deleted from the other side since the last synchronization (see
+    /// `SyncOptions::propagate_deletions`) or is missing from the mirrored path, it's deleted.
+    Deleted(&'a Path),
+    /// The entry differs from the other one but neither can be chosen or the older one can't
+    /// be modified, both are left as they are.
+    LeftAsIs(&'a Path),
+    /// The file should be copied but it's larger than `SyncOptions::max_file_size`.
+    SkippedTooLarge(&'a Path),
+}
+
+impl<'a> Decision<'a> {
+    /// Get the entry the decision is about.
+    pub fn path(&self) -> &'a Path {
+        match *self {
+            Decision::CopiedNewer(path)
+            | Decision::AlreadyInSync(path)
+            | Decision::CopiedMissing(path)
+            | Decision::CreatedDir(path)
+            | Decision::ReplacedFileWithDir(path)
+            | Decision::ReplacedDirWithFile(path)
+            | Decision::KeptBoth(path)
+            | Decision::Deleted(path)
+            | Decision::LeftAsIs(path)
+            | Decision::SkippedTooLarge(path) => path,
+        }
+    }
+}
+
+/// Difference between the entries at the same path of 2 synchronized paths, see `diff`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiffEntry {
+    /// Path of the entries, relative to the synchronized paths.
+    pub path: PathBuf,
+    pub status: DiffStatus,
+}
+
+/// How 2 entries at the same path differ, regardless of how they would be synchronized.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiffStatus {
+    /// The entry is only in the first path, the content of a directory isn't listed.
+    OnlyInFirst,
+    /// The entry is only in the second path, the content of a directory isn't listed.
+    OnlyInSecond,
+    /// The file of the first path is newer.
+    NewerInFirst,
+    /// The file of the second path is newer.
+    NewerInSecond,
+    /// Both files are the same, compared with `SyncOptions::compare`.
+    Identical,
+    /// Both files have the same modification time but a different size or content (see
+    /// `CompareMode::Content`), neither can be told newer.
+    Different,
+    /// One entry is a file and the other one is a directory.
+    TypeMismatch,
+}
+
+/// Number of entries excluded by each filter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FilterStats {
+    /// Files excluded by `SyncOptions::content_filter`.
+    pub content_filter: u64,
+    /// Entries excluded by `SyncOptions::exclude_paths`.
+    pub excluded_paths: u64,
+    /// Entries excluded by `SyncOptions::exclude`.
+    pub excluded_patterns: u64,
+    /// Files not matching any pattern of `SyncOptions::include`.
+    pub not_included: u64,
+    /// Hidden entries excluded by `SyncOptions::skip_hidden`.
+    pub hidden: u64,
+    /// Files not copied because of `SyncOptions::max_file_size`.
+    pub too_large: u64,
+    /// Symbolic links excluded by `SyncOptions::regular_files_only`.
+    pub symlinks: u64,
+    /// FIFOs excluded by `SyncOptions::regular_files_only`.
+    pub fifos: u64,
+    /// Sockets excluded by `SyncOptions::regular_files_only`.
+    pub sockets: u64,
+    /// Block and character devices excluded by `SyncOptions::regular_files_only`.
+    pub devices: u64,
+    /// Other special files excluded by `SyncOptions::regular_files_only`.
+    pub other_special_files: u64,
+}
+
+/// Why an entry has been excluded from the synchronization.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SkipReason {
+    ContentFilter,
+    ExcludedPath,
+    ExcludedPattern,
+    NotIncluded,
+    Hidden,
+    TooLarge,
+    Symlink,
+    Fifo,
+    Socket,
+    Device,
+    OtherSpecialFile,
+}
+
+impl FilterStats {
+    /// Add the counts of `other` to these ones.
+    pub fn merge(&mut self, other: &FilterStats) {
+        self.content_filter += other.content_filter;
+        self.excluded_paths += other.excluded_paths;
+        self.excluded_patterns += other.excluded_patterns;
+        self.not_included += other.not_included;
+        self.hidden += other.hidden;
+        self.too_large += other.too_large;
+        self.symlinks += other.symlinks;
+        self.fifos += other.fifos;
+        self.sockets += other.sockets;
+        self.devices += other.devices;
+        self.other_special_files += other.other_special_files;
+    }
+
+    pub(crate) fn record(&mut self, reason: SkipReason) {
+        match reason {
+            SkipReason::ContentFilter => self.content_filter += 1,
+            SkipReason::ExcludedPath => self.excluded_paths += 1,
+            SkipReason::ExcludedPattern => self.excluded_patterns += 1,
+            SkipReason::NotIncluded => self.not_included += 1,
+            SkipReason::Hidden => self.hidden += 1,
+            SkipReason::TooLarge => self.too_large += 1,
+            SkipReason::Symlink => self.symlinks += 1,
+            SkipReason::Fifo => self.fifos += 1,
+            SkipReason::Socket => self.sockets += 1,
+            SkipReason::Device => self.devices += 1,
+            SkipReason::OtherSpecialFile => self.other_special_files += 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FilterStats, PlannedAction, SyncReport, SyncStats};
+    use std::path::PathBuf;
+
+    #[test]
+    fn merge() {
+        let mut report = SyncReport {
+            stats: SyncStats {
+                files_copied: 2,
+                bytes_copied: 100,
+                ..Default::default()
+            },
+            filtered: FilterStats {
+                hidden: 1,
+                ..Default::default()
+            },
+            readonly_skipped: vec![PathBuf::from("a")],
+            warnings: vec!["unsupported".into()],
+            ..Default::default()
+        };
+
+        report.merge(SyncReport {
+            stats: SyncStats {
+                files_copied: 1,
+                bytes_copied: 20,
+                dirs_deleted: 3,
+                ..Default::default()
+            },
+            filtered: FilterStats {
+                hidden: 2,
+                too_large: 1,
+                ..Default::default()
+            },
+            readonly_skipped: vec![PathBuf::from("b")],
+            planned: vec![PlannedAction::Delete(PathBuf::from("c"))],
+            warnings: vec!["unsupported".into(), "other".into()],
+            ..Default::default()
+        });
+
+        assert_eq!(
+            report.stats,
+            SyncStats {
+                files_copied: 3,
+                bytes_copied: 120,
+                dirs_deleted: 3,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            report.filtered,
+            FilterStats {
+                hidden: 3,
+                too_large: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            report.readonly_skipped,
+            [PathBuf::from("a"), PathBuf::from("b")]
+        );
+        assert_eq!(report.planned, [PlannedAction::Delete(PathBuf::from("c"))]);
+        // the same warning isn't repeated
+        assert_eq!(report.warnings, ["unsupported", "other"]);
+    }
+}
@@ -0,0 +1,175 @@
+//! Names the file system of the second path can't store.
+//!
+//! Illegal characters are replaced following a reversible mapping so that sanitized names can
+//! be recognized when synchronizing back:
+//!
+//! - ASCII punctuation (e.g. `:`, `?`) becomes its fullwidth form (e.g. `：`, `？`);
+//! - control characters become their Unicode control picture (e.g. `\t` becomes `␉`);
+//! - other characters become `�` (U+FFFD), this one can't be reversed.
+
+use std::borrow::Cow;
+use std::path::{Component, Path, PathBuf};
+
+/// Offset from ASCII punctuation to its fullwidth form.
+const FULLWIDTH_OFFSET: u32 = 0xFEE0;
+/// Offset from ASCII control characters to their control picture.
+const CONTROL_PICTURE_OFFSET: u32 = 0x2400;
+
+/// Characters rejected by FAT file systems (besides the `/` separator).
+const FAT_ILLEGAL_CHARS: &[char] = &['"', '*', ':', '<', '>', '?', '\\', '|'];
+
+/// Restrictions on names of the file system of the second path, declared by the user.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SanitizePolicy {
+    /// Characters the file system rejects in names.
+    pub illegal_chars: Vec<char>,
+    /// What to do with names containing illegal characters.
+    pub action: SanitizeAction,
+}
+
+/// What to do with a name the destination file system can't store.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SanitizeAction {
+    /// Replace illegal characters following the mapping of this module and record the new
+    /// name in `SyncReport::renamed`.
+    Replace,
+    /// Report a `SyncError::IllegalDestinationName` and skip the entry.
+    Skip,
+}
+
+impl SanitizePolicy {
+    /// Restrictions of FAT file systems (e.g. most USB keys and SD cards).
+    pub fn fat(action: SanitizeAction) -> Self {
+        Self {
+            illegal_chars: ('\u{1}'..='\u{1f}')
+                .chain(FAT_ILLEGAL_CHARS.iter().copied())
+                .collect(),
+            action,
+        }
+    }
+
+    /// Whether `name` can be stored as is.
+    pub fn is_legal(&self, name: &str) -> bool {
+        !name.chars().any(|c| self.illegal_chars.contains(&c))
+    }
+
+    /// Replace the illegal characters of `name`.
+    pub fn sanitize_name<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        if self.is_legal(name) {
+            return Cow::Borrowed(name);
+        }
+
+        name.chars()
+            .map(|c| {
+                if !self.illegal_chars.contains(&c) {
+                    c
+                } else if c.is_ascii_control() {
+                    char::from_u32(c as u32 + CONTROL_PICTURE_OFFSET).unwrap_or('\u{fffd}')
+                } else if c.is_ascii_graphic() {
+                    char::from_u32(c as u32 + FULLWIDTH_OFFSET).unwrap_or('\u{fffd}')
+                } else {
+                    '\u{fffd}'
+                }
+            })
+            .collect()
+    }
+
+    /// Get the name `name` would have been sanitized from, if it looks sanitized.
+    pub fn unsanitize_name(&self, name: &str) -> Option<String> {
+        let mut changed = false;
+
+        let unsanitized = name
+            .chars()
+            .map(|c| {
+                let original = [CONTROL_PICTURE_OFFSET, FULLWIDTH_OFFSET]
+                    .iter()
+                    .filter_map(|offset| (c as u32).checked_sub(*offset))
+                    .filter_map(char::from_u32)
+                    .find(|original| original.is_ascii() && self.illegal_chars.contains(original));
+
+                match original {
+                    Some(original) => {
+                        changed = true;
+                        original
+                    }
+                    None => c,
+                }
+            })
+            .collect();
+
+        if changed {
+            Some(unsanitized)
+        } else {
+            None
+        }
+    }
+
+    /// Sanitize every component of a relative path, `None` if it's illegal and must be skipped.
+    pub(crate) fn sanitize_path<'a>(&self, path: &'a Path) -> Option<Cow<'a, Path>> {
+        let is_legal = path
+            .components()
+            .all(|component| match component.as_os_str().to_str() {
+                Some(name) => self.is_legal(name),
+                // non-UTF-8 names are never checked
+                None => true,
+            });
+
+        if is_legal {
+            return Some(Cow::Borrowed(path));
+        } else if self.action == SanitizeAction::Skip {
+            return None;
+        }
+
+        Some(Cow::Owned(map_names(path, |name| {
+            self.sanitize_name(name).into_owned()
+        })))
+    }
+
+    /// Get the path `path` would have been sanitized from, if it looks sanitized.
+    pub(crate) fn unsanitize_path(&self, path: &Path) -> Option<PathBuf> {
+        let unsanitized = map_names(path, |name| {
+            self.unsanitize_name(name)
+                .unwrap_or_else(|| name.to_owned())
+        });
+
+        if unsanitized != path {
+            Some(unsanitized)
+        } else {
+            None
+        }
+    }
+}
+
+/// Rebuild `path`, mapping all its valid UTF-8 normal components.
+fn map_names<F>(path: &Path, map: F) -> PathBuf
+where
+    F: Fn(&str) -> String,
+{
+    path.components()
+        .map(|component| match component {
+            Component::Normal(name) => match name.to_str() {
+                Some(name) => PathBuf::from(map(name)),
+                None => PathBuf::from(name),
+            },
+            other => PathBuf::from(other.as_os_str()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SanitizeAction, SanitizePolicy};
+
+    #[test]
+    fn sanitize_round_trip() {
+        let policy = SanitizePolicy::fat(SanitizeAction::Replace);
+
+        assert_eq!(policy.sanitize_name("notes.txt"), "notes.txt");
+        assert_eq!(policy.sanitize_name("12:30 a|b\t"), "12：30 a｜b␉");
+        assert_eq!(
+            policy.unsanitize_name("12：30 a｜b␉").as_deref(),
+            Some("12:30 a|b\t")
+        );
+        assert_eq!(policy.unsanitize_name("notes.txt"), None);
+    }
+}